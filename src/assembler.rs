@@ -0,0 +1,591 @@
+// A two pass assembler for the syntax the disassembler prints.
+//
+//     ; Comments start with a semicolon
+//     SPEED   EQU 4              ; constants
+//             INCLUDE "font.asm" ; paths are relative to the including file
+//     start:  CLS
+//             LD V0, SPEED
+//             LD I, sprite
+//             DRW V0, V1, 5
+//     loop:   JP loop
+//             ORG 0x300          ; move forward, padding with zeros
+//     sprite: DB 0xF0, 0x90, 0b11110000, "text"
+//             DW 0x1234, sprite + 2
+//
+// Mnemonics, registers and directives are case insensitive; labels and constants are not.
+// Numbers can be decimal, 0x hex, 0b binary or a 'c' character, and operands can add and
+// subtract labels, constants and numbers. Output starts at the 0x200 load address.
+//
+// The first pass works out the address of every label, the second evaluates the operands and
+// encodes each instruction with `Opcode::from_parts`, so anything the assembler accepts decodes
+// back to the same instruction.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::opcode::{Opcode, Operand};
+
+pub const LOAD_ADDRESS: u16 = 0x200;
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// Names that mean something in an operand, so can't be used for labels or constants.
+const RESERVED: [&str; 10] = ["I", "DT", "ST", "K", "F", "HF", "B", "R", "LONG", "EQU"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    // 1-based. 0 if the error isn't about a particular line, like a file that can't be read.
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(
+                f,
+                "{}:{}:{}: {}",
+                self.file, self.line, self.column, self.message
+            )
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+// Assembles `source`. Includes are relative to the current directory.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::default();
+    assembler.read(Rc::from("<input>"), Path::new(""), source, 0)?;
+    assembler.write()
+}
+
+// Assembles the file at `path`.
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::default();
+    assembler.read_file(path, None, 0)?;
+    assembler.write()
+}
+
+#[derive(Debug, Clone)]
+struct Location {
+    file: Rc<str>,
+    line: usize,
+    column: usize,
+}
+
+impl Location {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.to_string(),
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+
+    // The same line at a byte offset into it.
+    fn at(&self, offset: usize) -> Location {
+        Location {
+            column: offset + 1,
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Term {
+    Number(i64),
+    Symbol(String),
+}
+
+// Terms added or subtracted together.
+#[derive(Debug)]
+struct Expr {
+    // (sign, term, location of the term)
+    terms: Vec<(i64, Term, Location)>,
+}
+
+#[derive(Debug)]
+enum ParsedOperand {
+    // A register or one of the other named operands
+    Fixed(Operand),
+    Expr(Expr),
+    // LONG nnnn
+    Long(Expr),
+}
+
+#[derive(Debug)]
+enum Statement {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<ParsedOperand>,
+    },
+    Bytes(Vec<Expr>),
+    Words(Vec<Expr>),
+}
+
+#[derive(Debug)]
+struct Item {
+    address: u16,
+    location: Location,
+    statement: Statement,
+}
+
+#[derive(Default)]
+struct Assembler {
+    items: Vec<Item>,
+    // Labels and constants
+    symbols: HashMap<String, i64>,
+    // The address of the next item. Starts at 0 and is offset by LOAD_ADDRESS.
+    offset: usize,
+    // Canonical paths of the files being read, to catch recursive includes
+    files: Vec<PathBuf>,
+}
+
+impl Assembler {
+    fn read_file(
+        &mut self,
+        path: &Path,
+        included_from: Option<&Location>,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        let name: Rc<str> = Rc::from(path.display().to_string());
+        let error = |message: String| match included_from {
+            Some(location) => location.error(message),
+            None => AsmError {
+                file: name.to_string(),
+                line: 0,
+                column: 0,
+                message,
+            },
+        };
+
+        let source = fs::read_to_string(path)
+            .map_err(|err| error(format!("unable to read {}: {}", path.display(), err)))?;
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if self.files.contains(&canonical) {
+            return Err(error(format!("{} includes itself", path.display())));
+        }
+
+        self.files.push(canonical);
+        let dir = path.parent().unwrap_or(Path::new(""));
+        self.read(name, dir, &source, depth)?;
+        self.files.pop();
+        Ok(())
+    }
+
+    // The first pass: records every statement with its address and defines the symbols.
+    fn read(
+        &mut self,
+        file: Rc<str>,
+        dir: &Path,
+        source: &str,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        for (index, line) in source.lines().enumerate() {
+            let location = Location {
+                file: file.clone(),
+                line: index + 1,
+                column: 1,
+            };
+            let line = strip_comment(line);
+            let mut rest = skip_whitespace(line, 0);
+
+            // label:
+            let label_end = word_end(line, rest);
+            if label_end > rest && line[label_end..].starts_with(':') {
+                let label_location = location.at(rest);
+                let address = self.address(&label_location)?;
+                self.define(&line[rest..label_end], address as i64, &label_location)?;
+                rest = skip_whitespace(line, label_end + 1);
+            }
+            if rest >= line.len() {
+                continue;
+            }
+
+            let word_start = rest;
+            let mnemonic_end = word_end(line, word_start);
+            if mnemonic_end == word_start {
+                return Err(location
+                    .at(word_start)
+                    .error("expected an instruction or directive"));
+            }
+            let word = &line[word_start..mnemonic_end];
+            let args_start = skip_whitespace(line, mnemonic_end);
+
+            // NAME EQU value
+            let second_end = word_end(line, args_start);
+            if line[args_start..second_end].eq_ignore_ascii_case("EQU") {
+                let expr = parse_expr(line, skip_whitespace(line, second_end), &location)?;
+                let value = self.evaluate(&expr)?;
+                self.define(word, value, &location.at(word_start))?;
+                continue;
+            }
+
+            let word_location = location.at(word_start);
+            let address = self.address(&word_location)?;
+            let args = split_operands(line, args_start, &location)?;
+            let statement = match word.to_ascii_uppercase().as_str() {
+                "ORG" => {
+                    let [(start, end)] = args[..] else {
+                        return Err(word_location.error("ORG takes one address"));
+                    };
+                    let target = self.evaluate(&parse_expr(&line[..end], start, &location)?)?;
+                    if target < address as i64 {
+                        return Err(word_location.error(format!(
+                            "ORG {:#X} is before the current address {:#X}",
+                            target, address
+                        )));
+                    }
+                    self.offset = (target - LOAD_ADDRESS as i64) as usize;
+                    self.address(&word_location)?;
+                    continue;
+                }
+                "INCLUDE" => {
+                    let [(start, end)] = args[..] else {
+                        return Err(word_location.error("INCLUDE takes one file name"));
+                    };
+                    let Some(name) = string_literal(&line[start..end]) else {
+                        return Err(location.at(start).error("expected a quoted file name"));
+                    };
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(word_location.error("includes are nested too deeply"));
+                    }
+                    self.read_file(&dir.join(name), Some(&word_location), depth + 1)?;
+                    continue;
+                }
+                "DB" => {
+                    let mut exprs = Vec::new();
+                    for (start, end) in args {
+                        match string_literal(&line[start..end]) {
+                            Some(text) => exprs.extend(text.bytes().map(|byte| Expr {
+                                terms: vec![(1, Term::Number(byte as i64), location.at(start))],
+                            })),
+                            None => exprs.push(parse_expr(&line[..end], start, &location)?),
+                        }
+                    }
+                    self.offset += exprs.len();
+                    Statement::Bytes(exprs)
+                }
+                "DW" => {
+                    let exprs = args
+                        .into_iter()
+                        .map(|(start, end)| parse_expr(&line[..end], start, &location))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    self.offset += exprs.len() * 2;
+                    Statement::Words(exprs)
+                }
+                _ => {
+                    let operands = args
+                        .into_iter()
+                        .map(|(start, end)| parse_operand(line, start, end, &location))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let long = operands
+                        .iter()
+                        .any(|operand| matches!(operand, ParsedOperand::Long(_)));
+                    self.offset += if long { 4 } else { 2 };
+                    Statement::Instruction {
+                        mnemonic: word.to_string(),
+                        operands,
+                    }
+                }
+            };
+
+            self.items.push(Item {
+                address,
+                location: word_location,
+                statement,
+            });
+        }
+        Ok(())
+    }
+
+    // The current address, if it is still inside the address space.
+    fn address(&self, location: &Location) -> Result<u16, AsmError> {
+        let address = LOAD_ADDRESS as usize + self.offset;
+        u16::try_from(address)
+            .map_err(|_| location.error("the program doesn't fit in 64 KiB of memory"))
+    }
+
+    fn define(&mut self, name: &str, value: i64, location: &Location) -> Result<(), AsmError> {
+        if !is_identifier(name)
+            || is_register(name)
+            || RESERVED.iter().any(|r| r.eq_ignore_ascii_case(name))
+        {
+            return Err(location.error(format!("'{}' can't be used as a name", name)));
+        }
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(location.error(format!("'{}' is already defined", name)));
+        }
+        Ok(())
+    }
+
+    fn evaluate(&self, expr: &Expr) -> Result<i64, AsmError> {
+        let mut total = 0;
+        for (sign, term, location) in &expr.terms {
+            let value = match term {
+                Term::Number(value) => *value,
+                Term::Symbol(name) => *self
+                    .symbols
+                    .get(name)
+                    .ok_or_else(|| location.error(format!("'{}' is not defined", name)))?,
+            };
+            total += sign * value;
+        }
+        Ok(total)
+    }
+
+    // Evaluates `expr` and checks it is in `min..=max`.
+    fn evaluate_in_range(&self, expr: &Expr, min: i64, max: i64) -> Result<i64, AsmError> {
+        let value = self.evaluate(expr)?;
+        if value < min || value > max {
+            let location = &expr.terms[0].2;
+            return Err(location.error(format!("{} is out of range", value)));
+        }
+        Ok(value)
+    }
+
+    // The second pass: evaluates operands and writes the ROM.
+    fn write(&self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = vec![0; self.offset];
+        for item in &self.items {
+            let mut bytes = Vec::new();
+            match &item.statement {
+                Statement::Instruction { mnemonic, operands } => {
+                    let mut parts = Vec::new();
+                    let mut long = None;
+                    for operand in operands {
+                        parts.push(match operand {
+                            ParsedOperand::Fixed(operand) => *operand,
+                            ParsedOperand::Expr(expr) => {
+                                // Negative numbers are bytes, like ADD V0, -1
+                                let value = self.evaluate_in_range(expr, -0x80, 0xFFFF)?;
+                                if value < 0 {
+                                    Operand::Number(value as u8 as u16)
+                                } else {
+                                    Operand::Number(value as u16)
+                                }
+                            }
+                            ParsedOperand::Long(expr) => {
+                                long = Some(self.evaluate_in_range(expr, 0, 0xFFFF)? as u16);
+                                Operand::Long
+                            }
+                        });
+                    }
+                    let opcode = Opcode::from_parts(mnemonic, &parts)
+                        .map_err(|message| item.location.error(message))?;
+                    bytes.extend_from_slice(&opcode.encode().to_be_bytes());
+                    if let Some(address) = long {
+                        bytes.extend_from_slice(&address.to_be_bytes());
+                    }
+                }
+                Statement::Bytes(exprs) => {
+                    for expr in exprs {
+                        bytes.push(self.evaluate_in_range(expr, -0x80, 0xFF)? as u8);
+                    }
+                }
+                Statement::Words(exprs) => {
+                    for expr in exprs {
+                        let word = self.evaluate_in_range(expr, -0x8000, 0xFFFF)? as u16;
+                        bytes.extend_from_slice(&word.to_be_bytes());
+                    }
+                }
+            }
+
+            let start = (item.address - LOAD_ADDRESS) as usize;
+            rom[start..start + bytes.len()].copy_from_slice(&bytes);
+        }
+        Ok(rom)
+    }
+}
+
+// Removes a `;` comment, ignoring semicolons inside quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            (';', None) => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+fn skip_whitespace(line: &str, start: usize) -> usize {
+    line[start..]
+        .find(|c: char| !c.is_whitespace())
+        .map_or(line.len(), |offset| start + offset)
+}
+
+// The end of the identifier-like word starting at `start`.
+fn word_end(line: &str, start: usize) -> usize {
+    line[start..]
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+        .map_or(line.len(), |offset| start + offset)
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn is_register(name: &str) -> bool {
+    register_index(name).is_some()
+}
+
+fn register_index(name: &str) -> Option<usize> {
+    let digit = name.strip_prefix(['V', 'v'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    usize::from_str_radix(digit, 16).ok()
+}
+
+// The byte ranges of the comma separated operands from `start`, trimmed.
+fn split_operands(
+    line: &str,
+    start: usize,
+    location: &Location,
+) -> Result<Vec<(usize, usize)>, AsmError> {
+    if start >= line.len() {
+        return Ok(Vec::new());
+    }
+
+    let mut operands = Vec::new();
+    let mut operand_start = start;
+    let mut quote = None;
+    let mut push = |from: usize, to: usize| {
+        let from = skip_whitespace(&line[..to], from);
+        let to = from + line[from..to].trim_end().len();
+        if from == to {
+            return Err(location.at(from).error("missing operand"));
+        }
+        operands.push((from, to));
+        Ok(())
+    };
+    for (i, c) in line[start..].char_indices() {
+        let i = start + i;
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            (',', None) => {
+                push(operand_start, i)?;
+                operand_start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    push(operand_start, line.len())?;
+    Ok(operands)
+}
+
+fn string_literal(text: &str) -> Option<&str> {
+    text.strip_prefix('"')?.strip_suffix('"')
+}
+
+fn parse_operand(
+    line: &str,
+    start: usize,
+    end: usize,
+    location: &Location,
+) -> Result<ParsedOperand, AsmError> {
+    let text = &line[start..end];
+    if let Some(register) = register_index(text) {
+        return Ok(ParsedOperand::Fixed(Operand::Register(register)));
+    }
+    let fixed = match text.to_ascii_uppercase().as_str() {
+        "I" => Some(Operand::Index),
+        "[I]" => Some(Operand::IndexMemory),
+        "DT" => Some(Operand::DelayTimer),
+        "ST" => Some(Operand::SoundTimer),
+        "K" => Some(Operand::Key),
+        "F" => Some(Operand::Font),
+        "HF" => Some(Operand::LargeFont),
+        "B" => Some(Operand::Bcd),
+        "R" => Some(Operand::Flags),
+        _ => None,
+    };
+    if let Some(operand) = fixed {
+        return Ok(ParsedOperand::Fixed(operand));
+    }
+
+    let first_word = word_end(line, start);
+    if line[start..first_word].eq_ignore_ascii_case("LONG") {
+        let expr_start = skip_whitespace(&line[..end], first_word);
+        return parse_expr(&line[..end], expr_start, location).map(ParsedOperand::Long);
+    }
+    parse_expr(&line[..end], start, location).map(ParsedOperand::Expr)
+}
+
+// Parses `term (+|- term)*` from `start` to the end of `line`.
+fn parse_expr(line: &str, start: usize, location: &Location) -> Result<Expr, AsmError> {
+    let mut terms = Vec::new();
+    let mut sign = 1;
+    let mut position = skip_whitespace(line, start);
+    loop {
+        // Unary signs
+        while let Some(c @ ('+' | '-')) = line[position..].chars().next() {
+            if c == '-' {
+                sign = -sign;
+            }
+            position = skip_whitespace(line, position + 1);
+        }
+
+        let term_location = location.at(position);
+        let term = if line[position..].starts_with('\'') {
+            let mut chars = line[position + 1..].chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), Some('\'')) if c.is_ascii() => {
+                    position += 3;
+                    Term::Number(c as i64)
+                }
+                _ => return Err(term_location.error("expected a character like 'A'")),
+            }
+        } else {
+            let end = word_end(line, position);
+            let word = &line[position..end];
+            position = end;
+            if word.is_empty() {
+                return Err(term_location.error("expected a number or name"));
+            } else if word.starts_with(|c: char| c.is_ascii_digit()) {
+                Term::Number(
+                    parse_number(word)
+                        .ok_or_else(|| term_location.error(format!("invalid number '{}'", word)))?,
+                )
+            } else {
+                Term::Symbol(word.to_string())
+            }
+        };
+        terms.push((sign, term, term_location));
+
+        position = skip_whitespace(line, position);
+        match line[position..].chars().next() {
+            None => return Ok(Expr { terms }),
+            Some('+') => sign = 1,
+            Some('-') => sign = -1,
+            Some(c) => return Err(location.at(position).error(format!("unexpected '{}'", c))),
+        }
+        position = skip_whitespace(line, position + 1);
+    }
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
@@ -0,0 +1,273 @@
+// The sound timer makes the buzzer sound while it is non-zero. The `Beeper` turns the state of
+// the sound timer into samples one 60 Hz tick at a time and hands them to an `AudioSink`, which
+// can be a sound card (in the window frontend) or a WAV file (for headless runs).
+//
+// XO-CHIP programs can replace the buzzer with a 128 bit pattern of 1-bit samples (F002) played
+// back at a rate set by the pitch register (FX3A).
+
+use std::f32::consts::TAU;
+use std::fmt;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::str::FromStr;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+const TIMER_HZ: u32 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl Waveform {
+    pub const ALL: [Waveform; 4] = [
+        Waveform::Square,
+        Waveform::Triangle,
+        Waveform::Sawtooth,
+        Waveform::Sine,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Waveform::Square => "square",
+            Waveform::Triangle => "triangle",
+            Waveform::Sawtooth => "sawtooth",
+            Waveform::Sine => "sine",
+        }
+    }
+
+    // The value of the wave at `phase`, which runs from 0.0 to 1.0 over one period.
+    fn sample(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (phase * TAU).sin(),
+        }
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Waveform::ALL
+            .into_iter()
+            .find(|waveform| waveform.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                format!(
+                    "unknown waveform '{}', expected square, triangle, sawtooth or sine",
+                    s
+                )
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioConfig {
+    // Pitch of the beep in Hz.
+    pub frequency: f32,
+    // 0.0 is silent, 1.0 is full scale.
+    pub volume: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            frequency: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+        }
+    }
+}
+
+// What the audio output should be playing during a tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tone<'a> {
+    Silent,
+    // The buzzer, using the configured frequency and waveform.
+    Beep,
+    // An XO-CHIP audio pattern, played from the most significant bit of the first byte.
+    Pattern { pattern: &'a [u8; 16], pitch: u8 },
+}
+
+// The XO-CHIP playback rate in bits per second for a pitch register value.
+pub fn pattern_playback_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+// Somewhere to send the generated samples. Samples are mono f32 in the range -1.0 to 1.0.
+pub trait AudioSink {
+    fn sample_rate(&self) -> u32;
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()>;
+
+    // Called once when the emulator shuts down.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Discards everything. Used when audio is disabled.
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn sample_rate(&self) -> u32 {
+        DEFAULT_SAMPLE_RATE
+    }
+
+    fn write(&mut self, _samples: &[f32]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Writes 16-bit mono PCM to a WAV file. The header is rewritten with the final length by
+// `finish`, so the writer has to be seekable.
+pub struct WavSink<W: Write + Seek> {
+    writer: W,
+    sample_rate: u32,
+    data_len: u32,
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<Self> {
+        write_wav_header(&mut writer, sample_rate, 0)?;
+        Ok(WavSink {
+            writer,
+            sample_rate,
+            data_len: 0,
+        })
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        self.writer.write_all(&bytes)?;
+        self.data_len += bytes.len() as u32;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        write_wav_header(&mut self.writer, self.sample_rate, self.data_len)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+}
+
+// Reference: http://soundfile.sapp.org/doc/WaveFormat/
+fn write_wav_header<W: Write>(writer: &mut W, sample_rate: u32, data_len: u32) -> io::Result<()> {
+    const CHANNELS: u16 = 1;
+    const BITS_PER_SAMPLE: u16 = 16;
+    let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_len).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&CHANNELS.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())
+}
+
+// Generates the buzzer tone and feeds it to a sink.
+pub struct Beeper {
+    config: AudioConfig,
+    sink: Box<dyn AudioSink>,
+    phase: f32,
+    // Position in the XO-CHIP audio pattern, in bits.
+    pattern_position: f32,
+    // Fractional samples carried over between ticks, so rates that don't divide evenly by 60
+    // don't drift.
+    sample_remainder: u32,
+    buffer: Vec<f32>,
+}
+
+impl Beeper {
+    pub fn new(config: AudioConfig, sink: Box<dyn AudioSink>) -> Beeper {
+        Beeper {
+            config,
+            sink,
+            phase: 0.0,
+            pattern_position: 0.0,
+            sample_remainder: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &AudioConfig {
+        &self.config
+    }
+
+    // Produces one 60 Hz tick's worth of samples.
+    pub fn tick(&mut self, tone: Tone) -> io::Result<()> {
+        let sample_rate = self.sink.sample_rate();
+        let total = sample_rate + self.sample_remainder;
+        let sample_count = (total / TIMER_HZ) as usize;
+        self.sample_remainder = total % TIMER_HZ;
+
+        self.buffer.clear();
+        match tone {
+            Tone::Silent => {
+                // Restart the wave from the beginning on the next beep so every beep sounds the same
+                self.phase = 0.0;
+                self.pattern_position = 0.0;
+                self.buffer.resize(sample_count, 0.0);
+            }
+            Tone::Beep => {
+                let step = self.config.frequency / sample_rate as f32;
+                for _ in 0..sample_count {
+                    self.buffer
+                        .push(self.config.waveform.sample(self.phase) * self.config.volume);
+                    self.phase = (self.phase + step).fract();
+                }
+            }
+            Tone::Pattern { pattern, pitch } => {
+                let step = pattern_playback_rate(pitch) / sample_rate as f32;
+                for _ in 0..sample_count {
+                    let bit = self.pattern_position as usize;
+                    let set = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                    let level = if set { 1.0 } else { -1.0 };
+                    self.buffer.push(level * self.config.volume);
+                    self.pattern_position = (self.pattern_position + step) % 128.0;
+                }
+            }
+        }
+
+        self.sink.write(&self.buffer)
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.sink.finish()
+    }
+}
@@ -0,0 +1,14 @@
+// Assembles a source file into a ROM that can be loaded at 0x200. The same as `chip8 asm`; see
+// --help for the options.
+//
+// Files ending in .8o are compiled as Octo, for the instruction set of --platform (xochip by
+// default). Anything else uses the chip8-disasm syntax. The ROM is written next to the source
+// with a .ch8 extension unless -o is given.
+
+use chip8::cli::AsmCli;
+use chip8::commands;
+use clap::Parser;
+
+fn main() {
+    commands::asm(AsmCli::parse().args);
+}
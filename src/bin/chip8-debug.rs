@@ -0,0 +1,265 @@
+// A terminal debugger. Loads a ROM paused at the first instruction and reads commands from stdin;
+// type `help` for the list. Pressing enter on an empty line repeats the last command. See --help
+// for the options.
+
+use chip8::cli::{parse_address, DebugArgs};
+use chip8::commands::exit_with_error;
+use chip8::cpu::DEFAULT_LOAD_ADDRESS;
+use chip8::debugger::{Debugger, StopReason, Watchpoint};
+use chip8::scheduler::DEFAULT_INSTRUCTIONS_PER_SECOND;
+use chip8::{disassembler, headless, octo, Quirks, CPU};
+use clap::Parser;
+use std::io::{self, BufRead, Write};
+
+// Instructions shown either side of the PC by `list`
+const LIST_CONTEXT: u16 = 5;
+const DEFAULT_DUMP_LENGTH: usize = 64;
+// How long `continue` and `next` run before giving up, in emulated seconds
+const RUN_LIMIT_SECONDS: u64 = 60;
+
+const HELP: &str = "\
+Commands:
+  s, step [n]          run one (or n) instructions
+  n, next              step, running 2NNN calls through to their return
+  c, continue          run until a breakpoint, watchpoint, key wait or exit
+  b, break <addr>      set a breakpoint
+  d, delete <addr>     remove a breakpoint
+  w, watch <target>    stop when V0-VF, I or a memory address changes
+  u, unwatch <target>  remove a watchpoint
+  i, info              list breakpoints and watchpoints
+  r, regs              show registers, index, PC, stack and timers
+  l, list [addr]       show the instructions around the PC (or addr)
+  x, mem <addr> [len]  dump memory
+  k, key <key> [up]    press (or release) a keypad key 0-F
+  t, timers            tick the delay and sound timers once
+  f, frame             show the framebuffer
+  h, help              show this message
+  q, quit              exit";
+
+fn main() {
+    let args = DebugArgs::parse();
+    let instructions_per_second = args.ips.unwrap_or(DEFAULT_INSTRUCTIONS_PER_SECOND);
+    let mut debugger = Debugger::new(instructions_per_second);
+    for &addr in &args.breakpoints {
+        debugger.add_breakpoint(addr);
+    }
+
+    let mut cpu = match args.quirks {
+        Some(platform) => CPU::with_platform(platform, platform.quirks()),
+        None => CPU::new(Quirks::default()),
+    };
+    cpu.set_random(args.random, args.seed);
+    let rom = octo::read_program(&args.rom, cpu.platform())
+        .unwrap_or_else(|err| exit_with_error(&err.to_string()));
+    let load_address = args.load_address.unwrap_or(DEFAULT_LOAD_ADDRESS);
+    if let Err(err) = cpu.load_rom_at(rom, load_address) {
+        exit_with_error(&format!("Unable to load {}: {}", args.rom.display(), err));
+    }
+
+    let run_limit = instructions_per_second as u64 * RUN_LIMIT_SECONDS;
+    print!("{}", list(&cpu, &debugger, cpu.pc()));
+
+    let stdin = io::stdin();
+    let mut last_command = String::new();
+    loop {
+        print!("(chip8) ");
+        let _ = io::stdout().flush();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(err) => exit_with_error(&format!("Unable to read from stdin: {}", err)),
+        }
+        let line = line.trim();
+        let command = if line.is_empty() {
+            last_command.clone()
+        } else {
+            line.to_string()
+        };
+        if command.is_empty() {
+            continue;
+        }
+
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let result = match (words[0], &words[1..]) {
+            ("s" | "step", args) => parse_count(args).map(|count| {
+                let reason = debugger.step(&mut cpu, count);
+                stopped(&cpu, &debugger, reason)
+            }),
+            ("n" | "next", []) => {
+                let reason = debugger.step_over(&mut cpu, run_limit);
+                Ok(stopped(&cpu, &debugger, reason))
+            }
+            ("c" | "continue", []) => {
+                let reason = debugger.continue_execution(&mut cpu, run_limit);
+                Ok(stopped(&cpu, &debugger, reason))
+            }
+            ("b" | "break", [addr]) => parse_address(addr).map(|addr| {
+                debugger.add_breakpoint(addr);
+                format!("Breakpoint at {:#05X}\n", addr)
+            }),
+            ("d" | "delete", [addr]) => parse_address(addr).and_then(|addr| {
+                if debugger.remove_breakpoint(addr) {
+                    Ok(format!("Removed breakpoint at {:#05X}\n", addr))
+                } else {
+                    Err(format!("no breakpoint at {:#05X}", addr))
+                }
+            }),
+            ("w" | "watch", [target]) => parse_watchpoint(target).map(|watchpoint| {
+                debugger.add_watchpoint(watchpoint);
+                format!("Watching {}\n", watchpoint)
+            }),
+            ("u" | "unwatch", [target]) => parse_watchpoint(target).and_then(|watchpoint| {
+                if debugger.remove_watchpoint(watchpoint) {
+                    Ok(format!("Stopped watching {}\n", watchpoint))
+                } else {
+                    Err(format!("not watching {}", watchpoint))
+                }
+            }),
+            ("i" | "info", []) => Ok(info(&debugger)),
+            ("r" | "regs", []) => Ok(headless::registers_dump(&cpu)),
+            ("l" | "list", []) => Ok(list(&cpu, &debugger, cpu.pc())),
+            ("l" | "list", [addr]) => parse_address(addr).map(|addr| list(&cpu, &debugger, addr)),
+            ("x" | "mem", [addr]) => {
+                parse_address(addr).map(|addr| dump_memory(&cpu, addr, DEFAULT_DUMP_LENGTH))
+            }
+            ("x" | "mem", [addr, len]) => parse_address(addr).and_then(|addr| {
+                let len = len.parse().map_err(|_| "length must be a number")?;
+                Ok(dump_memory(&cpu, addr, len))
+            }),
+            ("k" | "key", [key]) => parse_key(key).map(|key| {
+                cpu.key_press(key);
+                format!("Key {:X} down\n", key)
+            }),
+            ("k" | "key", [key, "up"]) => parse_key(key).map(|key| {
+                cpu.key_release(key);
+                format!("Key {:X} up\n", key)
+            }),
+            ("t" | "timers", []) => {
+                cpu.decrement_timers();
+                Ok(format!(
+                    "DT: {}  ST: {}\n",
+                    cpu.delay_timer(),
+                    cpu.sound_timer()
+                ))
+            }
+            ("f" | "frame", []) => Ok(headless::framebuffer_ascii(&cpu)),
+            ("h" | "help", []) => Ok(format!("{}\n", HELP)),
+            ("q" | "quit", []) => break,
+            _ => Err(format!("unknown command '{}', try help", command)),
+        };
+
+        match result {
+            Ok(output) => print!("{}", output),
+            Err(err) => println!("Error: {}", err),
+        }
+        last_command = command;
+    }
+}
+
+// Reports why execution stopped followed by the code around the PC.
+fn stopped(cpu: &CPU, debugger: &Debugger, reason: StopReason) -> String {
+    let mut text = String::new();
+    if reason != StopReason::Stepped {
+        text.push_str(&format!("Stopped: {}\n", reason));
+    }
+    text.push_str(&list(cpu, debugger, cpu.pc()));
+    text
+}
+
+// The disassembled instructions either side of `addr`. `>` marks the PC and `*` marks breakpoints.
+fn list(cpu: &CPU, debugger: &Debugger, addr: u16) -> String {
+    let breakpoints: Vec<u16> = debugger.breakpoints().collect();
+    let memory = cpu.memory();
+    let mut addr = addr.saturating_sub(LIST_CONTEXT * 2) as usize;
+    let mut text = String::new();
+    for _ in 0..LIST_CONTEXT * 2 + 1 {
+        if addr >= memory.len() {
+            break;
+        }
+        let line = disassembler::decode_at(memory, addr, cpu.platform());
+        let pc_marker = if line.addr == cpu.pc() { '>' } else { ' ' };
+        let breakpoint_marker = if breakpoints.contains(&line.addr) {
+            '*'
+        } else {
+            ' '
+        };
+        text.push_str(&format!(
+            "{}{} {:#05X}: {:04X}  {}\n",
+            pc_marker,
+            breakpoint_marker,
+            line.addr,
+            line.word,
+            line.text()
+        ));
+        addr += line.size;
+    }
+    text
+}
+
+fn dump_memory(cpu: &CPU, addr: u16, len: usize) -> String {
+    let memory = cpu.memory();
+    let start = (addr as usize).min(memory.len());
+    let end = (start + len).min(memory.len());
+    let mut text = String::new();
+    for (row, bytes) in memory[start..end].chunks(16).enumerate() {
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        text.push_str(&format!("{:#05X}: {}\n", start + row * 16, hex.join(" ")));
+    }
+    text
+}
+
+fn info(debugger: &Debugger) -> String {
+    let breakpoints: Vec<String> = debugger
+        .breakpoints()
+        .map(|addr| format!("{:#05X}", addr))
+        .collect();
+    let watchpoints: Vec<String> = debugger.watchpoints().map(|w| w.to_string()).collect();
+    format!(
+        "Breakpoints: {}\nWatchpoints: {}\n",
+        none_if_empty(&breakpoints),
+        none_if_empty(&watchpoints)
+    )
+}
+
+fn none_if_empty(items: &[String]) -> String {
+    if items.is_empty() {
+        "none".to_string()
+    } else {
+        items.join(" ")
+    }
+}
+
+fn parse_count(args: &[&str]) -> Result<u32, String> {
+    match args {
+        [] => Ok(1),
+        [count] => count
+            .parse()
+            .map_err(|_| "count must be a number".to_string()),
+        _ => Err("usage: step [n]".to_string()),
+    }
+}
+
+fn parse_watchpoint(text: &str) -> Result<Watchpoint, String> {
+    if text.eq_ignore_ascii_case("i") {
+        return Ok(Watchpoint::Index);
+    }
+    if let Some(digit) = text.strip_prefix(['v', 'V']) {
+        if digit.len() == 1 {
+            if let Ok(register) = u8::from_str_radix(digit, 16) {
+                return Ok(Watchpoint::Register(register as usize));
+            }
+        }
+    }
+    parse_address(text)
+        .map(Watchpoint::Memory)
+        .map_err(|_| format!("'{}' is not a register, I or an address", text))
+}
+
+fn parse_key(text: &str) -> Result<usize, String> {
+    match u8::from_str_radix(text, 16) {
+        Ok(key) if key <= 0xF => Ok(key as usize),
+        _ => Err("key must be a hex digit 0-F".to_string()),
+    }
+}
@@ -0,0 +1,10 @@
+// Prints a ROM as an assembly listing: address, raw bytes and instruction, with labels on jump
+// and call targets. The same as `chip8 disasm`; see --help for the options.
+
+use chip8::cli::DisasmCli;
+use chip8::commands;
+use clap::Parser;
+
+fn main() {
+    commands::disasm(DisasmCli::parse().args);
+}
@@ -0,0 +1,11 @@
+// Runs a ROM without a window and dumps the final state: registers, a hash of memory and the
+// framebuffer as ASCII art (and optionally a PNG). The same as `chip8 headless`; see --help for
+// the options.
+
+use chip8::cli::HeadlessCli;
+use chip8::commands;
+use clap::Parser;
+
+fn main() {
+    commands::headless(HeadlessCli::parse().args);
+}
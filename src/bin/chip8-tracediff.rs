@@ -0,0 +1,125 @@
+// Compares our execution trace with one from another emulator and reports the first instruction
+// where the PC, registers, index, timers or framebuffer differ, with the instructions leading up
+// to it. See --help for the options.
+//
+// Record our side with `--trace <file> --trace-format export` (or binary). --columns describes
+// the reference trace, e.g. `pc,op,v0-vf,i,skip,dt,st`, and defaults to the export format.
+// --ref-state before is for emulators that log the state before each instruction runs. The
+// traces are lined up at the first reference instruction with our starting PC unless --ref-skip
+// says how many reference instructions to drop.
+//
+// Exits with 0 if the traces match, 1 if they diverge and 2 on errors, like diff.
+
+use chip8::cli::TraceDiffArgs;
+use chip8::commands::exit_with_status;
+use chip8::tracediff::{self, ColumnFormat, Divergence, Step};
+use chip8::{Instruction, Opcode, Platform};
+use clap::Parser;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+const ERROR_STATUS: i32 = 2;
+
+fn main() {
+    let args = TraceDiffArgs::parse();
+    let columns = args.columns.unwrap_or_default();
+
+    let ours = read_trace(&args.ours, &ColumnFormat::export());
+    let mut reference = read_trace(&args.reference, &columns);
+    if args.ref_state == "before" {
+        reference = tracediff::state_before_to_after(&reference);
+    }
+
+    let offset = match args.ref_skip {
+        Some(skip) => skip.min(reference.len()),
+        None => tracediff::align(&ours, &reference).unwrap_or_else(|| {
+            exit_with_status(
+                ERROR_STATUS,
+                &format!(
+                    "The reference trace never reaches our first PC ({:04X}), use --ref-skip",
+                    ours.first().map_or(0, |step| step.pc)
+                ),
+            )
+        }),
+    };
+    let reference = &reference[offset..];
+    if offset > 0 {
+        println!(
+            "Skipped {} reference instructions to line the traces up",
+            offset
+        );
+    }
+
+    let Some(Divergence {
+        position,
+        differences,
+    }) = tracediff::first_divergence(&ours, reference)
+    else {
+        println!(
+            "Traces match for {} instructions (ours has {}, the reference {})",
+            ours.len().min(reference.len()),
+            ours.len(),
+            reference.len()
+        );
+        return;
+    };
+
+    let (a, b) = (&ours[position], &reference[position]);
+    println!(
+        "Traces diverge at instruction {} (our line {}, reference line {}):",
+        position + 1,
+        a.line,
+        b.line
+    );
+    for difference in &differences {
+        println!(
+            "  {:<11} ours {}, reference {}",
+            difference.field, difference.ours, difference.reference
+        );
+    }
+
+    let start = position.saturating_sub(args.context);
+    println!("\nOurs:\n{}", HEADER);
+    for step in &ours[start..=position] {
+        println!("{}", describe(step, args.platform));
+    }
+    println!("\nReference:\n{}", HEADER);
+    for step in &reference[start..=position] {
+        println!("{}", describe(step, args.platform));
+    }
+
+    if let (Some(ours_fb), Some(reference_fb)) = (&a.framebuffer, &b.framebuffer) {
+        if ours_fb != reference_fb {
+            println!("\nFramebuffers (ours, then reference with differences as X):");
+            print!(
+                "{}",
+                tracediff::framebuffers_side_by_side(ours_fb, reference_fb)
+            );
+        }
+    }
+    process::exit(1);
+}
+
+const HEADER: &str = "  line: PC   OP   V0 V1 V2 V3 V4 V5 V6 V7 V8 V9 VA VB VC VD VE VF  I";
+
+fn describe(step: &Step, platform: Platform) -> String {
+    let mnemonic = step
+        .opcode
+        .and_then(|opcode| Opcode::decode(&Instruction::from_bytes(opcode), platform))
+        .map(|opcode| opcode.to_string())
+        .unwrap_or_default();
+    format!("{}  {}", step, mnemonic)
+}
+
+fn read_trace(path: &Path, columns: &ColumnFormat) -> Vec<Step> {
+    let data = fs::read(path).unwrap_or_else(|err| {
+        exit_with_status(
+            ERROR_STATUS,
+            &format!("Unable to read {}: {}", path.display(), err),
+        )
+    });
+    tracediff::parse(&data, columns).unwrap_or_else(|err| {
+        exit_with_status(ERROR_STATUS, &format!("{}: {}", path.display(), err))
+    })
+}
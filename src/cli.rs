@@ -0,0 +1,414 @@
+// The command line. `chip8 <rom>` is short for `chip8 run <rom>`. The standalone binaries take
+// the same options as the subcommands.
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::audio::Waveform;
+use crate::palette::Palette;
+use crate::platform::Platform;
+use crate::random::{self, RandomMode};
+use crate::trace::TraceFormat;
+use crate::tracediff::ColumnFormat;
+
+pub const DEFAULT_FRAMES: u64 = 600;
+pub const DEFAULT_PNG_SCALE: u32 = 4;
+pub const DEFAULT_CONTEXT: usize = 5;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "chip8",
+    version,
+    about = "A CHIP-8, SUPER-CHIP and XO-CHIP interpreter",
+    after_help = "Run `chip8 <rom>` to play a ROM, the same as `chip8 run <rom>`."
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    #[command(about = "Play a ROM in a window")]
+    Run(RunArgs),
+    #[command(about = "Run a ROM without a window and print the final state")]
+    Headless(HeadlessArgs),
+    #[command(about = "Print a ROM as an assembly listing")]
+    Disasm(DisasmArgs),
+    #[command(about = "Assemble a source file into a ROM")]
+    Asm(AsmArgs),
+    #[command(about = "Show information about a ROM")]
+    Info(InfoArgs),
+}
+
+// `chip8 headless` as a binary of its own.
+#[derive(Debug, Parser)]
+#[command(
+    name = "chip8-headless",
+    version,
+    about = "Run a ROM without a window and print the final state"
+)]
+pub struct HeadlessCli {
+    #[command(flatten)]
+    pub args: HeadlessArgs,
+}
+
+// `chip8 disasm` as a binary of its own.
+#[derive(Debug, Parser)]
+#[command(
+    name = "chip8-disasm",
+    version,
+    about = "Print a ROM as an assembly listing"
+)]
+pub struct DisasmCli {
+    #[command(flatten)]
+    pub args: DisasmArgs,
+}
+
+// `chip8 asm` as a binary of its own.
+#[derive(Debug, Parser)]
+#[command(
+    name = "chip8-asm",
+    version,
+    about = "Assemble a source file into a ROM"
+)]
+pub struct AsmCli {
+    #[command(flatten)]
+    pub args: AsmArgs,
+}
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "chip8-debug",
+    version,
+    about = "Debug a ROM from the terminal",
+    after_help = "Type `help` at the (chip8) prompt for the commands."
+)]
+pub struct DebugArgs {
+    #[arg(help = "The ROM, or Octo source (.8o) to compile")]
+    pub rom: PathBuf,
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Instructions per second, for how often the timers tick [default: 840]"
+    )]
+    pub ips: Option<u32>,
+    #[arg(
+        long,
+        value_name = "PLATFORM",
+        help = "Quirk preset and instruction set: vip, chip48, schip or xochip [default: modern quirks on a COSMAC VIP]"
+    )]
+    pub quirks: Option<Platform>,
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = random::DEFAULT_SEED,
+        help = "Seed for CXNN random numbers"
+    )]
+    pub seed: u64,
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value_t = RandomMode::Seeded,
+        help = "Random number generator: seeded"
+    )]
+    pub random: RandomMode,
+    #[arg(
+        long,
+        value_name = "ADDR",
+        value_parser = parse_address,
+        help = "Where to load the ROM, in hex [default: 0x200]"
+    )]
+    pub load_address: Option<u16>,
+    #[arg(
+        long = "break",
+        value_name = "ADDR",
+        value_parser = parse_address,
+        help = "Set a breakpoint, in hex. Can be given more than once."
+    )]
+    pub breakpoints: Vec<u16>,
+}
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "chip8-tracediff",
+    version,
+    about = "Find where our trace and another emulator's first disagree",
+    after_help = "Exits with 0 if the traces match, 1 if they diverge and 2 on errors."
+)]
+pub struct TraceDiffArgs {
+    #[arg(help = "Our trace, recorded with --trace-format export or binary")]
+    pub ours: PathBuf,
+    #[arg(help = "The other emulator's trace")]
+    pub reference: PathBuf,
+    #[arg(
+        long,
+        value_name = "LIST",
+        help = "The reference trace's columns, e.g. pc,op,v0-vf,i,skip,dt,st [default: the export format]"
+    )]
+    pub columns: Option<ColumnFormat>,
+    #[arg(
+        long,
+        value_name = "WHEN",
+        value_parser = ["before", "after"],
+        default_value = "after",
+        help = "Whether the reference logs the state before or after each instruction"
+    )]
+    pub ref_state: String,
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Reference instructions to drop [default: up to the first with our starting PC]"
+    )]
+    pub ref_skip: Option<usize>,
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_CONTEXT,
+        help = "Instructions to show before the divergence"
+    )]
+    pub context: usize,
+    #[arg(
+        long,
+        value_name = "PLATFORM",
+        default_value_t = Platform::XoChip,
+        help = "Instruction set to decode"
+    )]
+    pub platform: Platform,
+}
+
+// The options shared by everything that runs a ROM.
+#[derive(Debug, Args)]
+pub struct MachineArgs {
+    #[arg(help = "The ROM, or Octo source (.8o) to compile")]
+    pub rom: PathBuf,
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Instructions per second [default: the ROM database's, or 840]"
+    )]
+    pub ips: Option<u32>,
+    #[arg(
+        long,
+        value_name = "PLATFORM",
+        help = "Quirk preset and instruction set: vip, chip48, schip or xochip [default: the ROM database's, or modern quirks on a COSMAC VIP]"
+    )]
+    pub quirks: Option<Platform>,
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Seed for CXNN random numbers [default: random, or 0 when headless]"
+    )]
+    pub seed: Option<u64>,
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value_t = RandomMode::Seeded,
+        help = "Random number generator: seeded"
+    )]
+    pub random: RandomMode,
+    #[arg(
+        long,
+        value_name = "ADDR",
+        value_parser = parse_address,
+        help = "Where to load the ROM, in hex, e.g. 0x600 for ETI-660 programs [default: the ROM database's, or 0x200]"
+    )]
+    pub load_address: Option<u16>,
+    #[arg(
+        long,
+        value_name = "PALETTE",
+        help = "Colours: default, amber, green, octo, or 2 or 4 comma separated RRGGBB colours [default: the ROM database's, or default]"
+    )]
+    pub palette: Option<Palette>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Look ROMs up in FILE, a programs.json from the CHIP-8 database, instead of the bundled one"
+    )]
+    pub database: Option<PathBuf>,
+    #[arg(
+        long,
+        conflicts_with = "database",
+        help = "Don't look the ROM up in a database"
+    )]
+    pub no_database: bool,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Read settings from FILE [default: chip8/config.toml in the config directory]"
+    )]
+    pub config: Option<PathBuf>,
+    #[arg(long, value_name = "FILE", help = "Write a WAV file of the audio")]
+    pub wav: Option<PathBuf>,
+    #[arg(long, value_name = "FILE", help = "Record every instruction to FILE")]
+    pub trace: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value_t = TraceFormat::Text,
+        help = "Trace format: text, export or binary"
+    )]
+    pub trace_format: TraceFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub machine: MachineArgs,
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..=40),
+        help = "Window pixels per CHIP-8 pixel [default: 10]"
+    )]
+    pub scale: Option<u32>,
+    #[arg(long, help = "Start in fullscreen")]
+    pub fullscreen: bool,
+    #[arg(long, help = "Start paused; P pauses and resumes")]
+    pub paused: bool,
+    #[arg(long, value_name = "FILE", help = "Read key bindings from FILE")]
+    pub keymap: Option<PathBuf>,
+    #[arg(long, value_name = "HZ", help = "Pitch of the beep [default: 440]")]
+    pub frequency: Option<f32>,
+    #[arg(
+        long,
+        value_name = "0.0-1.0",
+        help = "Volume of the beep [default: 0.25]"
+    )]
+    pub volume: Option<f32>,
+    #[arg(
+        long,
+        value_name = "WAVEFORM",
+        help = "Waveform of the beep: square, triangle, sawtooth or sine [default: square]"
+    )]
+    pub waveform: Option<Waveform>,
+    #[arg(long, help = "Don't play sound")]
+    pub mute: bool,
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "play",
+        help = "Record the keypad input to a movie file"
+    )]
+    pub record: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["ips", "quirks", "seed", "random", "load_address"],
+        help = "Play a movie file back, using its settings"
+    )]
+    pub play: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct HeadlessArgs {
+    #[command(flatten)]
+    pub machine: MachineArgs,
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with = "cycles",
+        help = "Frames (60 Hz ticks) to run for [default: 600, or until the movie's recording stopped]"
+    )]
+    pub frames: Option<u64>,
+    #[arg(long, value_name = "N", help = "Instructions to run for")]
+    pub cycles: Option<u64>,
+    #[arg(long, value_name = "FILE", help = "Scripted key input")]
+    pub keys: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "movie",
+        help = "Record the keypad input to a movie file"
+    )]
+    pub record: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["ips", "quirks", "seed", "random", "load_address", "keys"],
+        help = "Play a movie file back, using its settings, and check the final state matches the recording"
+    )]
+    pub movie: Option<PathBuf>,
+    #[arg(long, value_name = "FILE", help = "Also write the screen as a PNG")]
+    pub png: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_PNG_SCALE,
+        help = "PNG pixels per CHIP-8 pixel"
+    )]
+    pub scale: u32,
+}
+
+#[derive(Debug, Args)]
+pub struct DisasmArgs {
+    pub rom: PathBuf,
+    #[arg(
+        long,
+        value_name = "PLATFORM",
+        default_value_t = Platform::XoChip,
+        help = "Instruction set to decode"
+    )]
+    pub platform: Platform,
+    #[arg(
+        long,
+        value_name = "ADDR",
+        value_parser = parse_address,
+        help = "Load address, in hex [default: 0x200]"
+    )]
+    pub org: Option<u16>,
+}
+
+#[derive(Debug, Args)]
+pub struct AsmArgs {
+    #[arg(help = "Assembly, or Octo source (.8o)")]
+    pub source: PathBuf,
+    #[arg(
+        short,
+        value_name = "FILE",
+        help = "The ROM to write [default: the source with .ch8]"
+    )]
+    pub output: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "PLATFORM",
+        default_value_t = Platform::XoChip,
+        help = "Instruction set for Octo source"
+    )]
+    pub platform: Platform,
+}
+
+#[derive(Debug, Args)]
+pub struct InfoArgs {
+    pub rom: PathBuf,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Look the ROM up in FILE, a programs.json from the CHIP-8 database, instead of the bundled one"
+    )]
+    pub database: Option<PathBuf>,
+}
+
+pub fn parse_address(text: &str) -> Result<u16, String> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("'{}' is not a hex address", text))
+}
+
+// Inserts `run` when the first argument isn't a subcommand or an option, so `chip8 game.ch8`
+// keeps working.
+pub fn with_default_command(mut args: Vec<OsString>) -> Vec<OsString> {
+    let commands = ["run", "headless", "disasm", "asm", "info", "help"];
+    if let Some(first) = args.get(1).and_then(|arg| arg.to_str()) {
+        if !first.starts_with('-') && !commands.contains(&first) {
+            args.insert(1, "run".into());
+        }
+    }
+    args
+}
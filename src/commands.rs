@@ -0,0 +1,363 @@
+// The subcommands that don't open a window, which the standalone binaries also run.
+
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use std::process;
+
+use crate::assembler;
+use crate::audio::{AudioConfig, Beeper, WavSink, DEFAULT_SAMPLE_RATE};
+use crate::cli::{AsmArgs, DisasmArgs, HeadlessArgs, InfoArgs, MachineArgs, DEFAULT_FRAMES};
+use crate::config::{Config, Settings};
+use crate::cpu::{CPU, DEFAULT_LOAD_ADDRESS};
+use crate::database::{Database, RomSettings};
+use crate::disassembler;
+use crate::headless::{self, KeyScript, RunEnd, RunLimit};
+use crate::instruction::Instruction;
+use crate::movie::Movie;
+use crate::octo;
+use crate::opcode::Opcode;
+use crate::palette::Palette;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::random;
+use crate::scheduler::DEFAULT_INSTRUCTIONS_PER_SECOND;
+use crate::trace::TraceWriter;
+
+// A CPU with the ROM loaded, and the settings to run it with.
+pub struct Machine {
+    pub cpu: CPU,
+    pub instructions_per_second: u32,
+    pub palette: Palette,
+    // The ROM's database entry, if it has one
+    pub rom: Option<RomSettings>,
+    // The config file's settings for the ROM
+    pub config: Settings,
+}
+
+// Builds the CPU and loads the ROM. Settings come from the command line, then `movie` if there is
+// one, then the ROM's section of the config file, then the ROM database, then the rest of the
+// config file, then the defaults. `default_seed` is used when there's no --seed.
+pub fn machine(
+    args: &MachineArgs,
+    default_seed: impl FnOnce() -> u64,
+    movie: Option<&Movie>,
+) -> Machine {
+    let config = Config::load(args.config.as_deref()).unwrap_or_else(|err| exit_with_error(&err));
+    // Octo source is compiled for the platform, so only ROMs can be looked up
+    let rom = (!octo::is_source(&args.rom)).then(|| read(&args.rom));
+    let sha1 = rom
+        .as_ref()
+        .map(|rom| sha1_smol::Sha1::from(rom).digest().to_string());
+    let section = config.rom_section(&args.rom, sha1.as_deref());
+    let entry = match &sha1 {
+        Some(sha1) if !args.no_database => database(args.database.as_deref()).lookup_hash(sha1),
+        _ => None,
+    };
+    let preset = args.quirks.or(section.and_then(|section| section.quirks));
+    let from_entry = entry
+        .as_ref()
+        .and_then(|entry| entry.platform.zip(entry.quirks));
+    let mut cpu = match (movie, preset, from_entry, config.settings.quirks) {
+        (Some(movie), ..) => movie.cpu(),
+        (None, Some(platform), ..) => CPU::with_platform(platform, platform.quirks()),
+        (None, None, Some((platform, quirks)), _) => CPU::with_platform(platform, quirks),
+        (None, None, None, Some(platform)) => CPU::with_platform(platform, platform.quirks()),
+        (None, None, None, None) => CPU::new(Quirks::default()),
+    };
+    if movie.is_none() {
+        cpu.set_random(args.random, args.seed.unwrap_or_else(default_seed));
+    }
+
+    let rom = rom.unwrap_or_else(|| {
+        octo::compile_file(&args.rom, cpu.platform())
+            .unwrap_or_else(|err| exit_with_error(&err.to_string()))
+    });
+    let load_address = match movie {
+        Some(movie) => movie.load_address,
+        None => args
+            .load_address
+            .or(entry.as_ref().and_then(|entry| entry.load_address))
+            .unwrap_or(DEFAULT_LOAD_ADDRESS),
+    };
+    if let Err(err) = cpu.load_rom_at(rom, load_address) {
+        exit_with_error(&format!("Unable to load {}: {}", args.rom.display(), err));
+    }
+    if let Some(movie) = movie {
+        if movie.rom_hash != cpu.rom_hash() {
+            eprintln!("Warning: the movie was recorded with a different ROM");
+        }
+    }
+
+    if let Some(path) = &args.trace {
+        let file = File::create(path).unwrap_or_else(|err| {
+            exit_with_error(&format!("Unable to create {}: {}", path.display(), err))
+        });
+        let platform = cpu.platform();
+        cpu.set_tracer(Box::new(TraceWriter::new(
+            BufWriter::new(file),
+            args.trace_format,
+            platform,
+        )));
+    }
+
+    let instructions_per_second = match movie {
+        Some(movie) => movie.instructions_per_second,
+        None => first([
+            args.ips,
+            section.and_then(|section| section.ips),
+            entry
+                .as_ref()
+                .and_then(|entry| entry.instructions_per_second),
+            config.settings.ips,
+        ])
+        .unwrap_or(DEFAULT_INSTRUCTIONS_PER_SECOND),
+    };
+    let palette = first([
+        args.palette,
+        section.and_then(|section| section.palette),
+        entry.as_ref().and_then(|entry| entry.palette),
+        config.settings.palette,
+    ])
+    .unwrap_or_default();
+    Machine {
+        cpu,
+        instructions_per_second,
+        palette,
+        rom: entry,
+        config: config.for_rom(&args.rom, sha1.as_deref()),
+    }
+}
+
+// The first setting that's been given, in order of precedence.
+fn first<T>(settings: [Option<T>; 4]) -> Option<T> {
+    settings.into_iter().flatten().next()
+}
+
+// The database in `path`, or the bundled one.
+fn database(path: Option<&Path>) -> Database {
+    match path {
+        Some(path) => Database::from_json(&read_to_string(path)).unwrap_or_else(|err| {
+            exit_with_error(&format!("Invalid database {}: {}", path.display(), err))
+        }),
+        None => Database::bundled(),
+    }
+}
+
+pub fn headless(args: HeadlessArgs) {
+    let movie = args.movie.as_deref().map(read_movie);
+    let Machine {
+        mut cpu,
+        instructions_per_second,
+        palette,
+        ..
+    } = machine(&args.machine, || random::DEFAULT_SEED, movie.as_ref());
+    let limit = match (args.cycles, args.frames) {
+        (Some(cycles), _) => Some(RunLimit::Cycles(cycles)),
+        (None, Some(frames)) => Some(RunLimit::Frames(frames)),
+        (None, None) => None,
+    };
+    let script = match &args.keys {
+        Some(path) => read_to_string(path)
+            .parse::<KeyScript>()
+            .unwrap_or_else(|err| {
+                exit_with_error(&format!("Invalid key script {}: {}", path.display(), err))
+            }),
+        None => KeyScript::default(),
+    };
+    let mut beeper = args.machine.wav.as_ref().map(|path| {
+        let sink = File::create(path)
+            .and_then(|file| WavSink::new(BufWriter::new(file), DEFAULT_SAMPLE_RATE))
+            .unwrap_or_else(|err| {
+                exit_with_error(&format!("Unable to create {}: {}", path.display(), err))
+            });
+        Beeper::new(AudioConfig::default(), Box::new(sink))
+    });
+
+    let mut playback = None;
+    let summary = match (&movie, &args.record) {
+        (Some(movie), _) => {
+            let result = headless::play(&mut cpu, movie, limit, beeper.as_mut())
+                .unwrap_or_else(|err| exit_with_error(&err.to_string()));
+            let summary = result.summary;
+            playback = Some(result);
+            summary
+        }
+        (None, Some(path)) => {
+            let (summary, movie) = headless::record(
+                &mut cpu,
+                instructions_per_second,
+                limit.unwrap_or(RunLimit::Frames(DEFAULT_FRAMES)),
+                &script,
+                beeper.as_mut(),
+            )
+            .unwrap_or_else(|err| exit_with_error(&err));
+            if let Err(err) = fs::write(path, movie.to_string()) {
+                exit_with_error(&format!("Unable to write {}: {}", path.display(), err));
+            }
+            summary
+        }
+        (None, None) => headless::run(
+            &mut cpu,
+            instructions_per_second,
+            limit.unwrap_or(RunLimit::Frames(DEFAULT_FRAMES)),
+            &script,
+            beeper.as_mut(),
+        )
+        .unwrap_or_else(|err| exit_with_error(&err.to_string())),
+    };
+
+    if let Some(beeper) = beeper.as_mut() {
+        if let Err(err) = beeper.finish() {
+            eprintln!("Failed to finish audio output: {}", err);
+        }
+    }
+    if let Some(mut tracer) = cpu.take_tracer() {
+        if let Err(err) = tracer.finish() {
+            eprintln!("Failed to write trace: {}", err);
+        }
+    }
+
+    println!("Ran {} frames ({} cycles)", summary.frames, summary.cycles);
+    if let Some(mode) = cpu.random_mode() {
+        println!("Random seed: {} ({})", cpu.random_seed(), mode);
+    }
+    if let Some(playback) = &playback {
+        if let Some(desync) = playback.desync {
+            println!("Movie {}", desync);
+        }
+        match playback.matches_end {
+            Some(true) => println!("Movie playback matches the recording"),
+            Some(false) => println!("Movie playback doesn't match the recording"),
+            None => (),
+        }
+    }
+    match summary.end {
+        RunEnd::LimitReached => (),
+        RunEnd::Exited => println!("Program exited"),
+        RunEnd::Fault(err) => println!("CPU halted: {}", err),
+    }
+    print!("{}", headless::registers_dump(&cpu));
+    println!("Memory SHA-1: {}", headless::memory_hash(&cpu));
+    print!("{}", headless::framebuffer_ascii(&cpu));
+
+    if let Some(path) = &args.png {
+        let result = File::create(path)
+            .and_then(|file| headless::write_png(&cpu, BufWriter::new(file), &palette, args.scale));
+        if let Err(err) = result {
+            exit_with_error(&format!("Unable to write {}: {}", path.display(), err));
+        }
+    }
+
+    if let RunEnd::Fault(_) = summary.end {
+        process::exit(2);
+    }
+    let playback_failed = playback
+        .as_ref()
+        .is_some_and(|playback| playback.desync.is_some() || playback.matches_end == Some(false));
+    if playback_failed {
+        process::exit(3);
+    }
+}
+
+pub fn disasm(args: DisasmArgs) {
+    let rom = read(&args.rom);
+    let org = args.org.unwrap_or(DEFAULT_LOAD_ADDRESS);
+    print!("{}", disassembler::listing(&rom, org, args.platform));
+}
+
+pub fn asm(args: AsmArgs) {
+    let output = args
+        .output
+        .unwrap_or_else(|| args.source.with_extension("ch8"));
+    let rom = if octo::is_source(&args.source) {
+        octo::compile_file(&args.source, args.platform)
+    } else {
+        assembler::assemble_file(&args.source)
+    }
+    .unwrap_or_else(|err| exit_with_error(&format!("error: {}", err)));
+    fs::write(&output, &rom).unwrap_or_else(|err| {
+        exit_with_error(&format!("Unable to write {}: {}", output.display(), err))
+    });
+    println!("Wrote {} bytes to {}", rom.len(), output.display());
+}
+
+pub fn info(args: InfoArgs) {
+    let rom = read(&args.rom);
+    let database = database(args.database.as_deref());
+    println!("File: {}", args.rom.display());
+    println!("Size: {} bytes", rom.len());
+    println!("SHA-1: {}", sha1_smol::Sha1::from(&rom).digest());
+    match database.lookup(&rom) {
+        Some(settings) => {
+            println!("Title: {}", settings.title);
+            if !settings.authors.is_empty() {
+                println!("Authors: {}", settings.authors.join(", "));
+            }
+            if let Some(platform) = settings.platform {
+                println!("Platform: {}", platform);
+            }
+            if let Some(ips) = settings.instructions_per_second {
+                println!("Instructions per second: {}", ips);
+            }
+        }
+        None => println!("Not in the ROM database"),
+    }
+
+    let fits: Vec<&str> = Platform::ALL
+        .iter()
+        .filter(|platform| rom.len() <= platform.memory_size() - DEFAULT_LOAD_ADDRESS as usize)
+        .map(|platform| platform.name())
+        .collect();
+    if fits.is_empty() {
+        println!("Fits in memory on: none");
+    } else {
+        println!("Fits in memory on: {}", fits.join(", "));
+    }
+
+    // A linear sweep also decodes sprites and other data, so this is only a hint
+    let count = |platform: Platform, base: Platform| {
+        rom.chunks_exact(2)
+            .map(|word| Instruction::from_bytes((word[0] as u16) << 8 | word[1] as u16))
+            .filter(|instruction| {
+                Opcode::decode(instruction, platform).is_some()
+                    && Opcode::decode(instruction, base).is_none()
+            })
+            .count()
+    };
+    println!(
+        "SUPER-CHIP instructions: {}",
+        count(Platform::SuperChip, Platform::CosmacVip)
+    );
+    println!(
+        "XO-CHIP instructions: {}",
+        count(Platform::XoChip, Platform::SuperChip)
+    );
+}
+
+pub fn read_movie(path: &Path) -> Movie {
+    read_to_string(path).parse::<Movie>().unwrap_or_else(|err| {
+        exit_with_error(&format!("Invalid movie {}: {}", path.display(), err))
+    })
+}
+
+fn read(path: &Path) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|err| {
+        exit_with_error(&format!("Unable to read {}: {}", path.display(), err))
+    })
+}
+
+fn read_to_string(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_else(|err| {
+        exit_with_error(&format!("Unable to read {}: {}", path.display(), err))
+    })
+}
+
+pub fn exit_with_error(message: &str) -> ! {
+    exit_with_status(1, message);
+}
+
+pub fn exit_with_status(status: i32, message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(status);
+}
@@ -0,0 +1,265 @@
+// The config file, `chip8/config.toml` in the user's config directory (`~/.config` on Linux) or
+// the file given with --config. Every setting is optional, and `[rom."<name>"]` sections
+// override them for one ROM, named by its file name or SHA-1:
+//
+//     ips = 700
+//     quirks = "schip"
+//     palette = "amber"
+//     scale = 12
+//     fullscreen = false
+//
+//     # A keymap file, and the layout to use without one
+//     keymap = "keymap.txt"
+//     layout = "positional"
+//
+//     # Bindings on top of the keymap: a CHIP-8 key and one or more host keys
+//     [keys]
+//     5 = ["Up", "W"]
+//     8 = "scancode:31"
+//
+//     [audio]
+//     frequency = 440
+//     volume = 0.25
+//     waveform = "square"
+//     mute = false
+//
+//     [hotkeys]
+//     quit = "Escape"
+//     pause = "P"
+//     fullscreen = "F11"
+//     rebind = "F10"
+//
+//     [rom."tetris.ch8"]
+//     ips = 1200
+//
+// Options on the command line win over the ROM's section, which wins over the ROM database,
+// which wins over the rest of the file. A relative keymap path is relative to the config file.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::de::{Deserializer, Error};
+use serde::Deserialize;
+use winit::event::VirtualKeyCode;
+
+use crate::audio::Waveform;
+use crate::keyboard::{self, HostKey, Layout};
+use crate::palette::Palette;
+use crate::platform::Platform;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub ips: Option<u32>,
+    #[serde(deserialize_with = "parsed")]
+    pub quirks: Option<Platform>,
+    #[serde(deserialize_with = "parsed")]
+    pub palette: Option<Palette>,
+    pub scale: Option<u32>,
+    pub fullscreen: Option<bool>,
+    pub keymap: Option<PathBuf>,
+    #[serde(deserialize_with = "parsed")]
+    pub layout: Option<Layout>,
+    #[serde(deserialize_with = "key_bindings")]
+    pub keys: Vec<(usize, Vec<HostKey>)>,
+    pub audio: AudioSettings,
+    pub hotkeys: HotkeySettings,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioSettings {
+    pub frequency: Option<f32>,
+    pub volume: Option<f32>,
+    #[serde(deserialize_with = "parsed")]
+    pub waveform: Option<Waveform>,
+    pub mute: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HotkeySettings {
+    #[serde(deserialize_with = "host_key")]
+    pub quit: Option<VirtualKeyCode>,
+    #[serde(deserialize_with = "host_key")]
+    pub pause: Option<VirtualKeyCode>,
+    #[serde(deserialize_with = "host_key")]
+    pub fullscreen: Option<VirtualKeyCode>,
+    #[serde(deserialize_with = "host_key")]
+    pub rebind: Option<VirtualKeyCode>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    pub settings: Settings,
+    // By ROM file name or SHA-1
+    roms: BTreeMap<String, Settings>,
+}
+
+impl Config {
+    // Reads `path`, or the default config file if there is one.
+    pub fn load(path: Option<&Path>) -> Result<Config, String> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                let mut config = Config::parse(&text)
+                    .map_err(|err| format!("Invalid config {}: {}", path.display(), err))?;
+                let dir = path.parent().unwrap_or(Path::new(""));
+                for settings in config.roms.values_mut().chain([&mut config.settings]) {
+                    settings.keymap = settings.keymap.as_ref().map(|keymap| dir.join(keymap));
+                }
+                Ok(config)
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound && !required => Ok(Config::default()),
+            Err(err) => Err(format!("Unable to read {}: {}", path.display(), err)),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut table: toml::Table = text
+            .parse()
+            .map_err(|err: toml::de::Error| err.to_string().trim_end().to_string())?;
+        let roms = match table.remove("rom") {
+            Some(roms) => BTreeMap::<String, Settings>::deserialize(roms)
+                .map_err(|err| format!("in [rom]: {}", err.to_string().trim_end()))?,
+            None => BTreeMap::new(),
+        };
+        let settings = Settings::deserialize(toml::Value::Table(table))
+            .map_err(|err| err.to_string().trim_end().to_string())?;
+        for settings in roms.values().chain([&settings]) {
+            if settings.ips == Some(0) {
+                return Err("ips must be at least 1".to_string());
+            }
+            if settings
+                .scale
+                .is_some_and(|scale| !(1..=40).contains(&scale))
+            {
+                return Err("scale must be between 1 and 40".to_string());
+            }
+        }
+        Ok(Config {
+            settings,
+            roms: roms
+                .into_iter()
+                .map(|(name, settings)| (name.to_ascii_lowercase(), settings))
+                .collect(),
+        })
+    }
+
+    // The settings for a ROM: its own section over the rest of the file. `sha1` is missing for
+    // Octo source, which can only be named by its file name.
+    pub fn for_rom(&self, path: &Path, sha1: Option<&str>) -> Settings {
+        match self.rom_section(path, sha1) {
+            Some(section) => self.settings.merged(section),
+            None => self.settings.clone(),
+        }
+    }
+
+    pub fn rom_section(&self, path: &Path, sha1: Option<&str>) -> Option<&Settings> {
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_ascii_lowercase());
+        let sha1 = sha1.map(str::to_ascii_lowercase);
+        [file_name, sha1]
+            .into_iter()
+            .flatten()
+            .find_map(|name| self.roms.get(&name))
+    }
+}
+
+impl Settings {
+    // These settings with anything set in `other` replaced. Key bindings are added to.
+    pub fn merged(&self, other: &Settings) -> Settings {
+        Settings {
+            ips: other.ips.or(self.ips),
+            quirks: other.quirks.or(self.quirks),
+            palette: other.palette.or(self.palette),
+            scale: other.scale.or(self.scale),
+            fullscreen: other.fullscreen.or(self.fullscreen),
+            keymap: other.keymap.clone().or(self.keymap.clone()),
+            layout: other.layout.or(self.layout),
+            keys: self.keys.iter().chain(&other.keys).cloned().collect(),
+            audio: AudioSettings {
+                frequency: other.audio.frequency.or(self.audio.frequency),
+                volume: other.audio.volume.or(self.audio.volume),
+                waveform: other.audio.waveform.or(self.audio.waveform),
+                mute: other.audio.mute.or(self.audio.mute),
+            },
+            hotkeys: HotkeySettings {
+                quit: other.hotkeys.quit.or(self.hotkeys.quit),
+                pause: other.hotkeys.pause.or(self.hotkeys.pause),
+                fullscreen: other.hotkeys.fullscreen.or(self.hotkeys.fullscreen),
+                rebind: other.hotkeys.rebind.or(self.hotkeys.rebind),
+            },
+        }
+    }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("chip8").join("config.toml"))
+}
+
+// The keymap used when nothing else says which, and where the rebinding screen saves to.
+pub fn default_keymap_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("chip8").join("keymap"))
+}
+
+// A string setting read with the type's FromStr, as on the command line.
+fn parsed<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|text| text.parse().map_err(D::Error::custom))
+        .transpose()
+}
+
+fn host_key<'de, D>(deserializer: D) -> Result<Option<VirtualKeyCode>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|name| keyboard::host_key_named(&name).map_err(D::Error::custom))
+        .transpose()
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HostKeys {
+    One(String),
+    Many(Vec<String>),
+}
+
+fn key_bindings<'de, D>(deserializer: D) -> Result<Vec<(usize, Vec<HostKey>)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    BTreeMap::<String, HostKeys>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(key, host_keys)| {
+            let host_keys = match host_keys {
+                HostKeys::One(host_key) => vec![host_key],
+                HostKeys::Many(host_keys) => host_keys,
+            };
+            Ok((
+                keyboard::chip8_key(&key).map_err(D::Error::custom)?,
+                host_keys
+                    .iter()
+                    .map(|host_key| host_key.parse().map_err(D::Error::custom))
+                    .collect::<Result<_, _>>()?,
+            ))
+        })
+        .collect()
+}
@@ -1,12 +1,51 @@
+use crate::audio::Tone;
+use crate::error::{Chip8Error, RomError, StepOutcome};
 use crate::instruction::Instruction;
-use rand::Rng;
+use crate::opcode::Opcode;
+use crate::platform::Platform;
+use crate::quirks::{LoadStoreQuirk, Quirks};
+use crate::random::{self, RandomMode, RandomSource};
+use crate::savestate::{self, SaveStateError, StateReader, StateWriter};
+use crate::trace::{TraceRecord, Tracer};
 
-const DISPLAY_WIDTH: u8 = 64;
-const DISPLAY_HEIGHT: u8 = 32;
+pub const DISPLAY_WIDTH: usize = 64;
+pub const DISPLAY_HEIGHT: usize = 32;
+// SUPER-CHIP high resolution mode
+pub const HIRES_DISPLAY_WIDTH: usize = 128;
+pub const HIRES_DISPLAY_HEIGHT: usize = 64;
+// Where programs are loaded and start running
+pub const DEFAULT_LOAD_ADDRESS: u16 = 0x200;
+// ETI-660 programs start at 0x600 instead
+pub const ETI_660_LOAD_ADDRESS: u16 = 0x600;
 const FONTSET_START_ADDRESS: usize = 0x50;
+// The SUPER-CHIP large font goes straight after the small one
+const LARGE_FONTSET_START_ADDRESS: usize = 0xA0;
+// The 4x5 hex digits FX29 points at, one row per byte using the high 4 bits
+pub const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+// XO-CHIP pitch register value that plays audio patterns at 4000 Hz
+const DEFAULT_PITCH: u8 = 64;
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
-    memory: [u8; 4096],
+    // 4 KiB, or 64 KiB on XO-CHIP
+    memory: Vec<u8>,
     registers: [u8; 16],
     index: u16,
     pc: u16,
@@ -15,22 +54,73 @@ pub struct CPU {
     delay_timer: u8,
     sound_timer: u8,
     keys: [bool; 16],
-    pub display: [bool; DISPLAY_WIDTH as usize * DISPLAY_HEIGHT as usize],
+    // DISPLAY_WIDTH x DISPLAY_HEIGHT, or HIRES_DISPLAY_WIDTH x HIRES_DISPLAY_HEIGHT in hires mode.
+    // Each pixel is a bitmask of the XO-CHIP planes that are lit; without XO-CHIP only plane 1
+    // is ever drawn to.
+    display: Vec<u8>,
+    hires: bool,
+    // XO-CHIP FN01 plane mask. Drawing, clearing and scrolling only affect these planes.
+    selected_planes: u8,
+    // XO-CHIP F002 audio pattern. None until a program loads one, in which case the plain
+    // buzzer is used.
+    audio_pattern: Option<[u8; 16]>,
+    // XO-CHIP FX3A pitch register
+    pitch: u8,
+    // SUPER-CHIP "RPL user flags", saved and restored by FX75/FX85
+    rpl_flags: [u8; 16],
+    platform: Platform,
+    quirks: Quirks,
+    // SHA-1 of the ROM passed to load_rom, used to check save states belong to the same game
+    rom_hash: [u8; 20],
+    // Set by every 60 Hz timer tick and cleared by DXYN when the display wait quirk is enabled.
+    vblank: bool,
+    // The CXNN generator. The mode is None for a generator supplied with set_random_source.
+    random: Box<dyn RandomSource>,
+    random_mode: Option<RandomMode>,
+    random_seed: u64,
+    // Receives every completed instruction when tracing is on
+    tracer: Option<Box<dyn Tracer>>,
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new(Quirks::default())
+    }
 }
 
 impl CPU {
-    pub fn new() -> CPU {
+    // A CPU with the original CHIP-8 instruction set.
+    pub fn new(quirks: Quirks) -> CPU {
+        CPU::with_platform(Platform::CosmacVip, quirks)
+    }
+
+    // A CPU with the instruction set of `platform`. The quirks are independent of the platform so
+    // that ROMs relying on unusual combinations can still be run.
+    pub fn with_platform(platform: Platform, quirks: Quirks) -> CPU {
         let mut cpu = CPU {
-            memory: [0; 4096],
+            memory: vec![0; platform.memory_size()],
             registers: [0; 16],
             index: 0,
-            pc: 0x200,
+            pc: DEFAULT_LOAD_ADDRESS,
             stack: [0; 16],
             sp: 0,
             delay_timer: 0,
             sound_timer: 0,
             keys: [false; 16],
-            display: [false; 64 * 32],
+            display: vec![0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            hires: false,
+            selected_planes: 1,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            rpl_flags: [0; 16],
+            platform,
+            quirks,
+            rom_hash: [0; 20],
+            vblank: false,
+            random: RandomMode::Seeded.source(random::DEFAULT_SEED),
+            random_mode: Some(RandomMode::Seeded),
+            random_seed: random::DEFAULT_SEED,
+            tracer: None,
         };
 
         cpu.load_fonts();
@@ -39,34 +129,192 @@ impl CPU {
 
     fn load_fonts(&mut self) {
         // Fonts should be loaded from 0x50
-        let fonts = [
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+        self.memory[FONTSET_START_ADDRESS..FONTSET_START_ADDRESS + FONT.len()]
+            .copy_from_slice(&FONT);
+
+        // SUPER-CHIP 8x10 digits, used by FX30
+        let large_fonts = [
+            0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+            0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+            0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+            0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+            0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
         ];
 
-        for (i, font) in fonts.iter().enumerate() {
-            self.memory[FONTSET_START_ADDRESS + i] = *font;
+        for (i, font) in large_fonts.iter().enumerate() {
+            self.memory[LARGE_FONTSET_START_ADDRESS + i] = *font;
+        }
+    }
+
+    // Copies `rom` into memory at 0x200, where programs start.
+    pub fn load_rom(&mut self, rom: Vec<u8>) -> Result<(), RomError> {
+        self.load_rom_at(rom, DEFAULT_LOAD_ADDRESS)
+    }
+
+    // Copies `rom` into memory at `address` and starts the program there.
+    pub fn load_rom_at(&mut self, rom: Vec<u8>, address: u16) -> Result<(), RomError> {
+        let start = address as usize;
+        if address < DEFAULT_LOAD_ADDRESS || start >= self.memory.len() {
+            return Err(RomError::InvalidLoadAddress { address });
+        }
+        if rom.is_empty() {
+            return Err(RomError::Empty);
+        }
+        let max = self.memory.len() - start;
+        if rom.len() > max {
+            return Err(RomError::TooLarge {
+                size: rom.len(),
+                max,
+                address,
+            });
+        }
+
+        self.rom_hash = sha1_smol::Sha1::from(&rom).digest().bytes();
+        self.memory[start..start + rom.len()].copy_from_slice(&rom);
+        self.pc = address;
+        Ok(())
+    }
+
+    // The SHA-1 of the loaded ROM.
+    pub fn rom_hash(&self) -> [u8; 20] {
+        self.rom_hash
+    }
+
+    // Snapshots the complete machine state. See `savestate` for the format.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+        writer.bytes(savestate::MAGIC);
+        writer.u16(savestate::VERSION);
+        writer.bytes(&self.rom_hash);
+        writer.platform(self.platform);
+        writer.quirks(&self.quirks);
+        writer.bytes(&self.registers);
+        writer.u16(self.index);
+        writer.u16(self.pc);
+        for address in self.stack {
+            writer.u16(address);
         }
+        writer.u8(self.sp);
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+        let keys = (0..16).fold(0u16, |keys, i| keys | (self.keys[i] as u16) << i);
+        writer.u16(keys);
+        writer.bool(self.vblank);
+        writer.bool(self.hires);
+        writer.u8(self.selected_planes);
+        writer.bytes(&self.rpl_flags);
+        writer.bool(self.audio_pattern.is_some());
+        writer.bytes(&self.audio_pattern.unwrap_or_default());
+        writer.u8(self.pitch);
+        writer.block(&self.memory);
+        writer.block(&self.display);
+        writer.random_mode(self.random_mode);
+        writer.u64(self.random_seed);
+        writer.u64(self.random.state());
+        writer.into_bytes()
     }
 
-    pub fn load_rom(&mut self, rom: Vec<u8>) {
-        for (i, byte) in rom.iter().enumerate() {
-            self.memory[0x200 + i] = *byte;
+    // Restores a snapshot made by `save_state`. The state has to have been saved with the ROM
+    // that is currently loaded. Nothing is changed if the state can't be restored.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = StateReader::new(state);
+        if reader.bytes(savestate::MAGIC.len()).ok() != Some(&savestate::MAGIC[..]) {
+            return Err(SaveStateError::NotASaveState);
+        }
+        let version = reader.u16()?;
+        if !(savestate::MIN_VERSION..=savestate::VERSION).contains(&version) {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        if reader.array::<20>()? != self.rom_hash {
+            return Err(SaveStateError::RomMismatch);
+        }
+
+        let platform = reader.platform()?;
+        let quirks = reader.quirks()?;
+        let registers = reader.array::<16>()?;
+        let index = reader.u16()?;
+        let pc = reader.u16()?;
+        let mut stack = [0; 16];
+        for address in stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        let sp = reader.u8()?;
+        if sp as usize > stack.len() {
+            return Err(SaveStateError::Invalid("stack pointer"));
+        }
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let key_bits = reader.u16()?;
+        let vblank = reader.bool("vblank flag")?;
+        let hires = reader.bool("hires flag")?;
+        let selected_planes = reader.u8()?;
+        if selected_planes > 0b11 {
+            return Err(SaveStateError::Invalid("plane selection"));
+        }
+        let rpl_flags = reader.array::<16>()?;
+        let has_audio_pattern = reader.bool("audio pattern flag")?;
+        let audio_pattern = reader.array::<16>()?;
+        let pitch = reader.u8()?;
+        let memory = reader.block()?;
+        if memory.len() != platform.memory_size() {
+            return Err(SaveStateError::Invalid("memory size"));
         }
+        let display = reader.block()?;
+        let (width, height) = if hires {
+            (HIRES_DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT)
+        } else {
+            (DISPLAY_WIDTH, DISPLAY_HEIGHT)
+        };
+        if display.len() != width * height {
+            return Err(SaveStateError::Invalid("framebuffer size"));
+        }
+        let random = if version >= 2 {
+            Some((reader.random_mode()?, reader.u64()?, reader.u64()?))
+        } else {
+            None
+        };
+
+        self.platform = platform;
+        self.quirks = quirks;
+        self.registers = registers;
+        self.index = index;
+        self.pc = pc;
+        self.stack = stack;
+        self.sp = sp;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        for (i, key) in self.keys.iter_mut().enumerate() {
+            *key = key_bits & (1 << i) != 0;
+        }
+        self.vblank = vblank;
+        self.hires = hires;
+        self.selected_planes = selected_planes;
+        self.rpl_flags = rpl_flags;
+        self.audio_pattern = has_audio_pattern.then_some(audio_pattern);
+        self.pitch = pitch;
+        self.memory = memory.to_vec();
+        self.display = display.to_vec();
+        if let Some((mode, seed, state)) = random {
+            // A generator supplied by the embedding program can't be recreated, so the one in
+            // use picks up the saved state instead
+            if let Some(mode) = mode {
+                self.set_random(mode, seed);
+            }
+            self.random_seed = seed;
+            self.random.set_state(state);
+        }
+        Ok(())
     }
 
     pub fn key_press(&mut self, key_index: usize) {
@@ -77,26 +325,205 @@ impl CPU {
         self.keys[key_index] = false;
     }
 
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    // The return addresses currently on the stack, oldest first.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    // The buzzer sounds for as long as the sound timer is non-zero.
+    pub fn is_sound_playing(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    // What the audio output should be playing right now.
+    pub fn tone(&self) -> Tone<'_> {
+        match (&self.audio_pattern, self.is_sound_playing()) {
+            (_, false) => Tone::Silent,
+            (None, true) => Tone::Beep,
+            (Some(pattern), true) => Tone::Pattern {
+                pattern,
+                pitch: self.pitch,
+            },
+        }
+    }
+
+    // The XO-CHIP planes selected by FN01.
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub fn rpl_flags(&self) -> &[u8; 16] {
+        &self.rpl_flags
+    }
+
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    // Replaces the CXNN generator with a built-in one, starting from `seed`.
+    pub fn set_random(&mut self, mode: RandomMode, seed: u64) {
+        self.random = mode.source(seed);
+        self.random_mode = Some(mode);
+        self.random_seed = seed;
+    }
+
+    // Uses `source` for CXNN, e.g. to share a generator between emulators. Save states record its
+    // state but can only restore it into the same kind of generator.
+    pub fn set_random_source(&mut self, source: Box<dyn RandomSource>) {
+        self.random = source;
+        self.random_mode = None;
+    }
+
+    // The built-in generator in use, or None for one supplied with set_random_source.
+    pub fn random_mode(&self) -> Option<RandomMode> {
+        self.random_mode
+    }
+
+    pub fn random_seed(&self) -> u64 {
+        self.random_seed
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    // Copies `bytes` into memory at `addr`, e.g. to patch a ROM or set the byte at 0x1FF that
+    // test ROMs read to pick a mode. Nothing is written if it doesn't all fit.
+    pub fn write_memory(&mut self, addr: usize, bytes: &[u8]) -> Result<(), Chip8Error> {
+        let Some(end) = addr.checked_add(bytes.len()) else {
+            return Err(Chip8Error::MemoryOutOfBounds { addr });
+        };
+        if end > self.memory.len() {
+            return Err(Chip8Error::MemoryOutOfBounds { addr: end - 1 });
+        }
+        self.memory[addr..end].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn is_key_pressed(&self, key_index: usize) -> bool {
+        self.keys[key_index]
+    }
+
+    // True in SUPER-CHIP 128x64 mode.
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    // The width of the active resolution.
+    pub fn display_width(&self) -> usize {
+        if self.hires {
+            HIRES_DISPLAY_WIDTH
+        } else {
+            DISPLAY_WIDTH
+        }
+    }
+
+    // The height of the active resolution.
+    pub fn display_height(&self) -> usize {
+        if self.hires {
+            HIRES_DISPLAY_HEIGHT
+        } else {
+            DISPLAY_HEIGHT
+        }
+    }
+
     pub fn get_display_pixel_index(&self, x: usize, y: usize) -> usize {
-        y as usize * DISPLAY_WIDTH as usize + x as usize
+        y * self.display_width() + x
     }
 
+    // True if the pixel is lit in any plane.
     pub fn get_display_pixel(&self, index: usize) -> bool {
-        self.display[index]
+        self.display[index] != 0
+    }
+
+    // The framebuffer is stored row by row, one byte per pixel, display_width() pixels per row.
+    // Each byte is a bitmask of the planes lit at that pixel: bit 0 is plane 1 and bit 1 is
+    // plane 2, so it can be used directly as an index into a four colour palette. Without
+    // XO-CHIP pixels are only ever 0 or 1.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.display
     }
 
     pub fn set_carry_flag(&mut self, value: u8) {
         self.registers[0xF] = value;
     }
 
-    pub fn update_display_pixel(&mut self, index: usize, value: bool) {
-        self.display[index] = value;
+    // Setters for putting the machine into a known state, e.g. in tests or tools. They don't
+    // check the state makes sense for the program that's loaded.
+
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.registers[x] = value;
+    }
+
+    pub fn set_index(&mut self, index: u16) {
+        self.index = index;
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    // Replaces the stack with `stack`, oldest return address first. Panics if there are more
+    // than 16 addresses.
+    pub fn set_stack(&mut self, stack: &[u16]) {
+        assert!(
+            stack.len() <= self.stack.len(),
+            "the stack holds 16 addresses"
+        );
+        self.stack = [0; 16];
+        self.stack[..stack.len()].copy_from_slice(stack);
+        self.sp = stack.len() as u8;
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    // Sets the planes lit at (x, y) in the current resolution, 1 for an ordinary lit pixel.
+    pub fn set_display_pixel(&mut self, x: usize, y: usize, planes: u8) {
+        let index = self.get_display_pixel_index(x, y);
+        self.display[index] = planes;
     }
 
     // CHIP-8 has two timers. They bouth count down at 60 hertz, until they reach 0.
     // Delay timer: This timer is intended to be used for timing events of games. Its value can be set and read.
     // Sound timer: This timer is used for sound effects. When its value is nonzero, a beeping sound is made. Its value can only be set.
+    // This is also the vertical blank that DXYN waits for when the display wait quirk is enabled.
     pub fn decrement_timers(&mut self) {
+        self.vblank = true;
+        self.random.tick();
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
@@ -105,197 +532,359 @@ impl CPU {
         }
     }
 
-    pub fn execute_instruction(&mut self) {
-        let instruction_bytes = self.fetch_instruction_bytes();
-        // println!("Instruction: #{:#018b}", instruction_bytes);
+    // Fetches, decodes and executes the instruction at the PC. If the instruction faults the PC is
+    // rewound to point at it again so the frontend can show where execution stopped.
+    pub fn execute_instruction(&mut self) -> Result<StepOutcome, Chip8Error> {
+        let pc = self.pc;
+        let mut opcode = 0;
+        let result = self.fetch_instruction_bytes().and_then(|bytes| {
+            opcode = bytes;
+            self.execute(pc, bytes)
+        });
+        match result {
+            Ok(StepOutcome::Executed) => self.trace(pc, opcode),
+            Err(_) => self.pc = pc,
+            _ => (),
+        }
+        result
+    }
 
+    // Starts sending every completed instruction to `tracer`, replacing any previous tracer.
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer>) {
+        self.tracer = Some(tracer);
+    }
+
+    // Stops tracing and hands the tracer back so it can be finished.
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer>> {
+        self.tracer.take()
+    }
+
+    fn trace(&mut self, pc: u16, opcode: u16) {
+        // Taken out while it runs so it can look at the rest of the CPU
+        let Some(mut tracer) = self.tracer.take() else {
+            return;
+        };
+        let record = TraceRecord {
+            pc,
+            opcode,
+            registers: self.registers,
+            index: self.index,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        };
+        tracer.record(&record, self);
+        self.tracer = Some(tracer);
+    }
+
+    fn execute(&mut self, pc: u16, instruction_bytes: u16) -> Result<StepOutcome, Chip8Error> {
         let instruction = Instruction::from_bytes(instruction_bytes);
+        let unknown_opcode = Chip8Error::UnknownOpcode {
+            pc,
+            opcode: instruction_bytes,
+        };
+        let Some(opcode) = Opcode::decode(&instruction, self.platform) else {
+            return Err(unknown_opcode);
+        };
+
+        match opcode {
+            Opcode::Sys(_) => {
+                // Machine code routines can't be run
+                return Err(unknown_opcode);
+            }
 
-        match instruction.op_code {
-            0x00 => match instruction.nn {
-                0xE0 => self.display = [false; 64 * 32], // Clear screen
-                0xEE => {
-                    // Return from a subroutine
-                    self.sp -= 1;
-                    self.pc = self.stack[self.sp as usize];
+            Opcode::Cls => {
+                // Clear screen (only the selected planes on XO-CHIP)
+                for pixel in self.display.iter_mut() {
+                    *pixel &= !self.selected_planes;
                 }
-                _ => println!("Instruction not implemented."),
-            },
+            }
+
+            Opcode::Ret => {
+                // Return from a subroutine
+                if self.sp == 0 {
+                    return Err(Chip8Error::StackUnderflow { pc });
+                }
+                self.sp -= 1;
+                self.pc = self.stack[self.sp as usize];
+            }
+
+            Opcode::ScrollDown(n) => {
+                // Scroll the display down n pixels
+                self.scroll(0, n as isize);
+            }
+
+            Opcode::ScrollUp(n) => {
+                // Scroll the display up n pixels
+                self.scroll(0, -(n as isize));
+            }
+
+            Opcode::ScrollRight => {
+                // Scroll the display right 4 pixels
+                self.scroll(4, 0);
+            }
+
+            Opcode::ScrollLeft => {
+                // Scroll the display left 4 pixels
+                self.scroll(-4, 0);
+            }
+
+            Opcode::Exit => {
+                // Exit the interpreter. The PC stays on this instruction so it keeps exiting.
+                self.pc = pc;
+                return Ok(StepOutcome::Exited);
+            }
+
+            Opcode::Low => {
+                // Switch to 64x32 low resolution mode
+                self.set_hires(false);
+            }
+
+            Opcode::High => {
+                // Switch to 128x64 high resolution mode
+                self.set_hires(true);
+            }
 
-            0x01 => {
+            Opcode::Jp(nnn) => {
                 // Jump to location nnn
-                self.pc = instruction.nnn;
+                self.pc = nnn;
             }
 
-            0x02 => {
+            Opcode::Call(nnn) => {
                 // Call subroutine at nnn
+                if self.sp as usize >= self.stack.len() {
+                    return Err(Chip8Error::StackOverflow { pc });
+                }
                 self.stack[self.sp as usize] = self.pc;
                 self.sp += 1;
-                self.pc = instruction.nnn;
+                self.pc = nnn;
             }
 
-            0x03 => {
+            Opcode::SeByte { x, nn } => {
                 // Skip next instruction if Vx == nn
-                if self.registers[instruction.x] == instruction.nn {
-                    self.pc += 2;
+                if self.registers[x] == nn {
+                    self.skip_next_instruction();
                 }
             }
 
-            0x04 => {
+            Opcode::SneByte { x, nn } => {
                 // Skip next instruction if Vx != nn
-                if self.registers[instruction.x] != instruction.nn {
-                    self.pc += 2;
+                if self.registers[x] != nn {
+                    self.skip_next_instruction();
                 }
             }
 
-            0x05 => {
+            Opcode::SeReg { x, y } => {
                 // Skip next instruction if Vx == Vy
-                if self.registers[instruction.x] == self.registers[instruction.y] {
-                    self.pc += 2;
+                if self.registers[x] == self.registers[y] {
+                    self.skip_next_instruction();
                 }
             }
 
-            0x06 => {
+            Opcode::SaveRange { x, y } => {
+                // Store registers Vx through Vy in memory starting at location I. The range
+                // runs backwards if x > y. I is not changed.
+                let registers = register_range(x, y);
+                self.check_memory_range(self.index as usize, registers.len())?;
+                for (offset, register_index) in registers.into_iter().enumerate() {
+                    self.memory[self.index as usize + offset] = self.registers[register_index];
+                }
+            }
+
+            Opcode::LoadRange { x, y } => {
+                // Read registers Vx through Vy from memory starting at location I.
+                let registers = register_range(x, y);
+                self.check_memory_range(self.index as usize, registers.len())?;
+                for (offset, register_index) in registers.into_iter().enumerate() {
+                    self.registers[register_index] = self.memory[self.index as usize + offset];
+                }
+            }
+
+            Opcode::LdByte { x, nn } => {
                 // Set register x to nn
-                self.registers[instruction.x] = instruction.nn;
+                self.registers[x] = nn;
             }
 
-            0x07 => {
+            Opcode::AddByte { x, nn } => {
                 // Add value nn to register x
-                self.registers[instruction.x] =
-                    self.registers[instruction.x].wrapping_add(instruction.nn);
+                self.registers[x] = self.registers[x].wrapping_add(nn);
             }
 
-            0x08 => {
-                match instruction.n {
-                    0x00 => {
-                        // Set register x to the value of register y
-                        self.registers[instruction.x] = self.registers[instruction.y]
-                    }
-                    0x01 => {
-                        // Set register x to the value of register x OR register y
-                        self.registers[instruction.x] =
-                            self.registers[instruction.x] | self.registers[instruction.y];
-                    }
-                    0x02 => {
-                        // Set register x to the value of register x AND register y
-                        self.registers[instruction.x] =
-                            self.registers[instruction.x] & self.registers[instruction.y];
-                    }
-                    0x03 => {
-                        // Set register x to the value of register x XOR register y
-                        self.registers[instruction.x] =
-                            self.registers[instruction.x] ^ self.registers[instruction.y];
-                    }
-                    0x04 => {
-                        // Set register x to the value of register x PLUS register y
-                        // If the result is greater than 8 bits (i.e., > 255,) the carry register is set to 1, otherwise 0
-                        let (sum, overflow) = self.registers[instruction.x]
-                            .overflowing_add(self.registers[instruction.y]);
-                        self.registers[instruction.x] = sum;
-                        self.set_carry_flag(overflow as u8)
-                    }
-                    0x05 => {
-                        // Set register x to the value of register x minus register y
-                        // If the register x is greater than register y set the carry register to 1, otherwise 0.
-                        let (diff, overflow) = self.registers[instruction.x]
-                            .overflowing_sub(self.registers[instruction.y]);
-                        self.registers[instruction.x] = diff;
-                        self.set_carry_flag(!overflow as u8)
-                    }
-                    0x06 => {
-                        // This uses the newer implementation
-                        // Set VX to the value of VY (older configuration, not implemented)
-                        // Shift the value of VX one bit to the right (8XY6) or left (8XYE)
-                        // Set VF to 1 if the bit that was shifted out was 1, or 0 if it was 0
-                        let shifted_bit = self.registers[instruction.x] & 0b00000001;
-                        self.registers[instruction.x] = self.registers[instruction.x] >> 1;
-                        self.set_carry_flag(shifted_bit);
-                    }
-                    0x07 => {
-                        // Set register x to the value of register y minus register x
-                        // If the register y is greater than register x set the carry register to 1, otherwise 0.
-                        let (diff, overflow) = self.registers[instruction.y]
-                            .overflowing_sub(self.registers[instruction.x]);
-                        self.registers[instruction.x] = diff;
-                        self.set_carry_flag(!overflow as u8)
-                    }
-                    0x0E => {
-                        // This uses the newer implementation
-                        // Set VX to the value of VY (older configuration, not implemented)
-                        // Shift the value of VX one bit to the left (8XYE)
-                        // Set VF to 1 if the bit that was shifted out was 1, or 0 if it was 0
-                        let shifted_bit: u8 = (self.registers[instruction.x] & 0b10000000) << 7;
-                        self.registers[instruction.x] = self.registers[instruction.x] << 1;
-                        self.set_carry_flag(shifted_bit);
-                    }
-                    _ => panic!("Unimplemented OP code"),
-                }
+            Opcode::LdReg { x, y } => {
+                // Set register x to the value of register y
+                self.registers[x] = self.registers[y]
             }
 
-            0x09 => {
+            Opcode::Or { x, y } => {
+                // Set register x to the value of register x OR register y
+                self.registers[x] |= self.registers[y];
+                self.reset_vf_quirk();
+            }
+
+            Opcode::And { x, y } => {
+                // Set register x to the value of register x AND register y
+                self.registers[x] &= self.registers[y];
+                self.reset_vf_quirk();
+            }
+
+            Opcode::Xor { x, y } => {
+                // Set register x to the value of register x XOR register y
+                self.registers[x] ^= self.registers[y];
+                self.reset_vf_quirk();
+            }
+
+            Opcode::AddReg { x, y } => {
+                // Set register x to the value of register x PLUS register y
+                // If the result is greater than 8 bits (i.e., > 255,) the carry register is set to 1, otherwise 0
+                let (sum, overflow) = self.registers[x].overflowing_add(self.registers[y]);
+                self.registers[x] = sum;
+                self.set_carry_flag(overflow as u8)
+            }
+
+            Opcode::Sub { x, y } => {
+                // Set register x to the value of register x minus register y
+                // If the register x is greater than register y set the carry register to 1, otherwise 0.
+                let (diff, overflow) = self.registers[x].overflowing_sub(self.registers[y]);
+                self.registers[x] = diff;
+                self.set_carry_flag(!overflow as u8)
+            }
+
+            Opcode::Shr { x, y } => {
+                // Set VX to the value of VY (original behaviour, skipped with the shift quirk)
+                // Shift the value of VX one bit to the right (8XY6)
+                // Set VF to 1 if the bit that was shifted out was 1, or 0 if it was 0
+                self.load_shift_source(x, y);
+                let shifted_bit = self.registers[x] & 0b00000001;
+                self.registers[x] >>= 1;
+                self.set_carry_flag(shifted_bit);
+            }
+
+            Opcode::Subn { x, y } => {
+                // Set register x to the value of register y minus register x
+                // If the register y is greater than register x set the carry register to 1, otherwise 0.
+                let (diff, overflow) = self.registers[y].overflowing_sub(self.registers[x]);
+                self.registers[x] = diff;
+                self.set_carry_flag(!overflow as u8)
+            }
+
+            Opcode::Shl { x, y } => {
+                // Set VX to the value of VY (original behaviour, skipped with the shift quirk)
+                // Shift the value of VX one bit to the left (8XYE)
+                // Set VF to 1 if the bit that was shifted out was 1, or 0 if it was 0
+                self.load_shift_source(x, y);
+                let shifted_bit: u8 = (self.registers[x] & 0b10000000) >> 7;
+                self.registers[x] <<= 1;
+                self.set_carry_flag(shifted_bit);
+            }
+
+            Opcode::SneReg { x, y } => {
                 // Skip next instruction if Vx != Vy
-                if self.registers[instruction.x] != self.registers[instruction.y] {
-                    self.pc += 2;
+                if self.registers[x] != self.registers[y] {
+                    self.skip_next_instruction();
                 }
             }
 
-            0x0A => {
+            Opcode::LdI(nnn) => {
                 // Set index register to nnn
-                self.index = instruction.nnn;
+                self.index = nnn;
             }
 
-            0x0B => {
+            Opcode::JpV0(nnn) => {
                 // Set PC to nnn plus register[0]. This is the original implementation.
-                // The newer implementation sets the PC nnn + registers[instruction.x]. The BNNN instruction was not widely used,
-                // so it is recommended to use the original behaviour.
-                self.pc = self.registers[0] as u16 + instruction.nnn;
+                // The CHIP-48/SUPER-CHIP implementation (the jump quirk) reads the instruction as BXNN
+                // and sets the PC to xnn + registers[instruction.x].
+                let offset_register = if self.quirks.jump { instruction.x } else { 0 };
+                self.pc = self.registers[offset_register] as u16 + nnn;
             }
 
-            0x0C => {
+            Opcode::Rnd { x, nn } => {
                 // Generates a random number, binary ANDs it with the value NN, and puts the result in VX.
-                let random_number: u8 = rand::thread_rng().gen();
-                self.registers[instruction.x] = random_number & instruction.nn;
+                let random_number = self.random.next_byte();
+                self.registers[x] = random_number & nn;
             }
 
-            0x0D => {
+            Opcode::Drw { x, y, n } => {
                 // Draws a sprite at coordinate (VX, VY) that has a width of 8 pixels and a height of N pixels.
                 // The sprite memory location is stored in the index register. The display location is updated
                 // with the result of the current pixel XOR'd with the new pixel value stored in the sprite.
                 // The carry flag register (VF) is set to 1 if any screen pixels are flipped from set to unset when the
                 // sprite is drawn.
+                // On SUPER-CHIP, DXY0 draws a 16x16 sprite stored as 2 bytes per row.
+                // On XO-CHIP the sprite is drawn once into each selected plane, with the data for
+                // each plane following straight on from the previous one.
+                let (sprite_width, sprite_height) = if n == 0 && self.platform.supports_superchip()
+                {
+                    (16, 16)
+                } else {
+                    (8, n as usize)
+                };
+                let bytes_per_row = sprite_width / 8;
+                let sprite_len = bytes_per_row * sprite_height;
+                let plane_count = self.selected_planes.count_ones() as usize;
+                self.check_memory_range(self.index as usize, sprite_len * plane_count)?;
+
+                // The original interpreter only drew during the vertical blank, so wait for the next
+                // timer tick before drawing.
+                if self.quirks.display_wait {
+                    if !self.vblank {
+                        self.pc = pc;
+                        return Ok(StepOutcome::WaitingForVBlank);
+                    }
+                    self.vblank = false;
+                }
+
+                let display_width = self.display_width();
+                let display_height = self.display_height();
 
                 // Wrap position
-                let x_position = self.registers[instruction.x] % DISPLAY_WIDTH as u8;
-                let y_position = self.registers[instruction.y] % DISPLAY_HEIGHT as u8;
+                let x_position = self.registers[x] as usize % display_width;
+                let y_position = self.registers[y] as usize % display_height;
                 self.set_carry_flag(0);
                 let mut collision = false;
+                let mut sprite_start = self.index as usize;
 
-                for row in 0..instruction.n {
-                    let sprite_byte = self.memory[self.index as usize + row as usize];
-                    let y = y_position + row;
-
-                    if y >= 32 {
-                        break;
+                for plane in [0b01, 0b10] {
+                    if self.selected_planes & plane == 0 {
+                        continue;
                     }
 
-                    for col in 0..8 {
-                        let x = x_position + col;
-                        if x >= 64 {
-                            break;
+                    for row in 0..sprite_height {
+                        let mut y = y_position + row;
+
+                        // Sprites that run off the edge are either clipped or wrapped to the other side
+                        if y >= display_height {
+                            if self.quirks.clipping {
+                                break;
+                            }
+                            y %= display_height;
                         }
 
-                        let current_pixel_index =
-                            self.get_display_pixel_index(x as usize, y as usize);
-                        let current_pixel = self.get_display_pixel(current_pixel_index);
-                        // Example of the below:
-                        // sprite_byte = 0011 1000
-                        // compare_value = 1000 0000 (1 shifted to the left 7 - col)
-                        // 0011 1000 & 1000 0000 = 0000 0000
-                        let new_pixel = (sprite_byte & (1 << (7 - col))) != 0;
-                        self.update_display_pixel(current_pixel_index, current_pixel ^ new_pixel);
-                        collision = collision || (current_pixel && new_pixel);
+                        for col in 0..sprite_width {
+                            let mut x = x_position + col;
+                            if x >= display_width {
+                                if self.quirks.clipping {
+                                    break;
+                                }
+                                x %= display_width;
+                            }
+
+                            let sprite_byte =
+                                self.memory[sprite_start + row * bytes_per_row + col / 8];
+                            let current_pixel_index = self.get_display_pixel_index(x, y);
+                            let current_pixel = self.display[current_pixel_index] & plane != 0;
+                            // Example of the below:
+                            // sprite_byte = 0011 1000
+                            // compare_value = 1000 0000 (1 shifted to the left 7 - col)
+                            // 0011 1000 & 1000 0000 = 0000 0000
+                            let new_pixel = (sprite_byte & (1 << (7 - col % 8))) != 0;
+                            if new_pixel {
+                                self.display[current_pixel_index] ^= plane;
+                            }
+                            collision = collision || (current_pixel && new_pixel);
+                        }
                     }
+
+                    sprite_start += sprite_len;
                 }
 
                 if collision {
@@ -305,123 +894,256 @@ impl CPU {
                 }
             }
 
-            0x0E => {
-                //
-                match instruction.nn {
-                    0x9E => {
-                        // Skip next instruction if key with the value of Vx is pressed.
-                        // Checks the keyboard, and if the key corresponding to the value of Vx
-                        // is currently in the pressed, PC is increased by 2.
-                        let key_index = self.registers[instruction.x];
-                        let pressed = self.keys[key_index as usize];
-                        if pressed {
-                            self.pc += 2;
-                        }
-                    }
-                    0xA1 => {
-                        // Skip next instruction if key with the value of Vx is not pressed.
-                        // Checks the keyboard, and if the key corresponding to the value of Vx
-                        // is currently not pressed, PC is increased by 2.
-                        let key_index = self.registers[instruction.x];
-                        let pressed = self.keys[key_index as usize];
-                        if !pressed {
-                            self.pc += 2;
-                        }
-                    }
-                    _ => unimplemented!(),
+            Opcode::Skp(x) => {
+                // Skip next instruction if key with the value of Vx is pressed.
+                // Checks the keyboard, and if the key corresponding to the value of Vx
+                // is currently in the pressed, PC is increased by 2.
+                let key_index = self.registers[x] & 0xF;
+                let pressed = self.keys[key_index as usize];
+                if pressed {
+                    self.skip_next_instruction();
                 }
             }
 
-            0x0F => {
-                match instruction.nn {
-                    0x07 => {
-                        // sets register X to the current value of the delay timer
-                        self.registers[instruction.x] = self.delay_timer;
-                    }
-                    0x15 => {
-                        // sets the delay timer to the value in register X
-                        self.delay_timer = self.registers[instruction.x];
-                    }
-                    0x18 => {
-                        // sets the sound timer to the value in register X
-                        self.sound_timer = self.registers[instruction.x];
-                    }
-                    0x1E => {
-                        // The index register I will get the value in VX added to it.
-                        self.index = self.index + self.registers[instruction.x] as u16;
-                    }
-                    0x0A => {
-                        // Wait for a key press, store the value of the key in Vx.
-                        // All execution stops until a key is pressed, then the value of that key is stored in Vx.
-                        let mut pressed = false;
-
-                        for (i, key) in self.keys.into_iter().enumerate() {
-                            if key {
-                                pressed = true;
-                                self.registers[instruction.x] = i as u8;
-                                break;
-                            }
-                        }
+            Opcode::Sknp(x) => {
+                // Skip next instruction if key with the value of Vx is not pressed.
+                // Checks the keyboard, and if the key corresponding to the value of Vx
+                // is currently not pressed, PC is increased by 2.
+                let key_index = self.registers[x] & 0xF;
+                let pressed = self.keys[key_index as usize];
+                if !pressed {
+                    self.skip_next_instruction();
+                }
+            }
 
-                        if !pressed {
-                            self.pc -= 2;
-                        }
-                    }
-                    0x29 => {
-                        // Set I to location of sprite for digit Vx.
+            Opcode::LdLongI => {
+                // F000 NNNN: load the 16-bit address in the following word into I.
+                self.check_memory_range(self.pc as usize, 2)?;
+                self.index = (self.memory[self.pc as usize] as u16) << 8
+                    | self.memory[self.pc as usize + 1] as u16;
+                self.pc = self.pc.wrapping_add(2);
+            }
 
-                        // Returns the font digit, not the start location of the font
-                        let digit = self.registers[instruction.x] as u16;
+            Opcode::Plane(planes) => {
+                // FN01: select the planes that drawing, clearing and scrolling affect.
+                self.selected_planes = planes & 0b11;
+            }
 
-                        // Each digit is 5 bytes long, so we can multiple the digit by 5 to get the
-                        // start location of the font digit
-                        self.index = FONTSET_START_ADDRESS as u16 + 5 * digit;
-                    }
-                    0x33 => {
-                        // The interpreter takes the decimal value of Vx, and  places
-                        // the hundreds digit in memory at location in I, the tens
-                        // digit at location I+1, and the ones digit at location I+2.
-
-                        let value_x = self.registers[instruction.x];
-                        let hundreds_digit = value_x / 100;
-                        let tens_digit = (value_x % 100) / 10;
-                        let ones_digit = value_x % 10;
-
-                        self.memory[self.index as usize] = hundreds_digit;
-                        self.memory[self.index as usize + 1 as usize] = tens_digit;
-                        self.memory[self.index as usize + 2 as usize] = ones_digit;
-                    }
-                    0x55 => {
-                        // Store registers V0 through Vx in memory starting at location I.
-                        // The interpreter copies the values of registers V0 through Vx
-                        // into memory, starting at the address in I.
-                        for register_index in 0..=instruction.x {
-                            let memory_location = self.index as usize + register_index;
-                            self.memory[memory_location] = self.registers[register_index];
-                        }
-                    }
-                    0x65 => {
-                        // Read registers V0 through Vx from memory starting at location I.
-                        // The interpreter reads values from memory starting at location I
-                        // into registers V0 through Vx.
-                        for register_index in 0..=instruction.x {
-                            let memory_location = self.index as usize + register_index;
-                            self.registers[register_index] = self.memory[memory_location];
-                        }
+            Opcode::Audio => {
+                // F002: load the 16 byte audio pattern starting at I.
+                self.check_memory_range(self.index as usize, 16)?;
+                let start = self.index as usize;
+                let mut pattern = [0; 16];
+                pattern.copy_from_slice(&self.memory[start..start + 16]);
+                self.audio_pattern = Some(pattern);
+            }
+
+            Opcode::Pitch(x) => {
+                // Set the audio pattern playback pitch to Vx.
+                self.pitch = self.registers[x];
+            }
+
+            Opcode::LdVxDt(x) => {
+                // sets register X to the current value of the delay timer
+                self.registers[x] = self.delay_timer;
+            }
+
+            Opcode::LdDtVx(x) => {
+                // sets the delay timer to the value in register X
+                self.delay_timer = self.registers[x];
+            }
+
+            Opcode::LdStVx(x) => {
+                // sets the sound timer to the value in register X
+                self.sound_timer = self.registers[x];
+            }
+
+            Opcode::AddI(x) => {
+                // The index register I will get the value in VX added to it.
+                self.index = self.index.wrapping_add(self.registers[x] as u16);
+            }
+
+            Opcode::LdVxK(x) => {
+                // Wait for a key press, store the value of the key in Vx.
+                // All execution stops until a key is pressed, then the value of that key is stored in Vx.
+                let mut pressed = false;
+
+                for (i, key) in self.keys.into_iter().enumerate() {
+                    if key {
+                        pressed = true;
+                        self.registers[x] = i as u8;
+                        break;
                     }
-                    _ => unimplemented!(),
                 }
+
+                if !pressed {
+                    self.pc = pc;
+                    return Ok(StepOutcome::WaitingForKey);
+                }
+            }
+
+            Opcode::LdF(x) => {
+                // Set I to location of sprite for digit Vx.
+
+                // Returns the font digit, not the start location of the font. Only the low
+                // nibble is used, like the original interpreter.
+                let digit = (self.registers[x] & 0xF) as u16;
+
+                // Each digit is 5 bytes long, so we can multiple the digit by 5 to get the
+                // start location of the font digit
+                self.index = FONTSET_START_ADDRESS as u16 + 5 * digit;
+            }
+
+            Opcode::LdHf(x) => {
+                // Set I to the location of the large (8x10) sprite for digit Vx.
+                let digit = (self.registers[x] & 0xF) as u16;
+                self.index = LARGE_FONTSET_START_ADDRESS as u16 + 10 * digit;
+            }
+
+            Opcode::LdB(x) => {
+                // The interpreter takes the decimal value of Vx, and  places
+                // the hundreds digit in memory at location in I, the tens
+                // digit at location I+1, and the ones digit at location I+2.
+
+                let value_x = self.registers[x];
+                let hundreds_digit = value_x / 100;
+                let tens_digit = (value_x % 100) / 10;
+                let ones_digit = value_x % 10;
+
+                self.check_memory_range(self.index as usize, 3)?;
+                self.memory[self.index as usize] = hundreds_digit;
+                self.memory[self.index as usize + 1] = tens_digit;
+                self.memory[self.index as usize + 2] = ones_digit;
+            }
+
+            Opcode::LdIVx(x) => {
+                // Store registers V0 through Vx in memory starting at location I.
+                // The interpreter copies the values of registers V0 through Vx
+                // into memory, starting at the address in I.
+                self.check_memory_range(self.index as usize, x + 1)?;
+                for register_index in 0..=x {
+                    let memory_location = self.index as usize + register_index;
+                    self.memory[memory_location] = self.registers[register_index];
+                }
+                self.apply_load_store_quirk(x);
+            }
+
+            Opcode::LdVxI(x) => {
+                // Read registers V0 through Vx from memory starting at location I.
+                // The interpreter reads values from memory starting at location I
+                // into registers V0 through Vx.
+                self.check_memory_range(self.index as usize, x + 1)?;
+                for register_index in 0..=x {
+                    let memory_location = self.index as usize + register_index;
+                    self.registers[register_index] = self.memory[memory_location];
+                }
+                self.apply_load_store_quirk(x);
+            }
+
+            Opcode::LdRVx(x) => {
+                // Save registers V0 through Vx to the RPL user flags.
+                self.rpl_flags[..=x].copy_from_slice(&self.registers[..=x]);
+            }
+
+            Opcode::LdVxR(x) => {
+                // Load registers V0 through Vx from the RPL user flags.
+                self.registers[..=x].copy_from_slice(&self.rpl_flags[..=x]);
             }
-            _ => println!("Instruction not implemented"),
         }
+
+        Ok(StepOutcome::Executed)
     }
 
     // An instruction is two bytes. A big-endian system stores the most significant byte of a word
     // at the smallest memory address and the least significant byte at the largest.
-    fn fetch_instruction_bytes(&mut self) -> u16 {
+    fn fetch_instruction_bytes(&mut self) -> Result<u16, Chip8Error> {
+        if self.pc as usize + 1 >= self.memory.len() {
+            return Err(Chip8Error::PcOutOfBounds { pc: self.pc });
+        }
         let instruction = (self.memory[self.pc as usize] as u16) << 8
             | (self.memory[self.pc as usize + 1] as u16);
-        self.pc += 2;
-        instruction
+        self.pc = self.pc.wrapping_add(2);
+        Ok(instruction)
+    }
+
+    // Switching resolution clears the display.
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.display = vec![0; self.display_width() * self.display_height()];
+    }
+
+    // Moves the selected planes right by dx and down by dy pixels (negative to go left or up).
+    // Pixels scrolled off the edge are lost and the space left behind is blank.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let width = self.display_width() as isize;
+        let height = self.display_height() as isize;
+        let planes = self.selected_planes;
+        let previous = self.display.clone();
+
+        for y in 0..height {
+            for x in 0..width {
+                let (source_x, source_y) = (x - dx, y - dy);
+                let moved = if (0..width).contains(&source_x) && (0..height).contains(&source_y) {
+                    previous[(source_y * width + source_x) as usize] & planes
+                } else {
+                    0
+                };
+                let index = (y * width + x) as usize;
+                self.display[index] = (previous[index] & !planes) | moved;
+            }
+        }
+    }
+
+    // Skips over the next instruction. F000 NNNN is 4 bytes long, so XO-CHIP skips both words.
+    fn skip_next_instruction(&mut self) {
+        let next = self.pc as usize;
+        let long_instruction = self.platform.supports_xochip()
+            && next + 1 < self.memory.len()
+            && self.memory[next] == 0xF0
+            && self.memory[next + 1] == 0x00;
+        let length = if long_instruction { 4 } else { 2 };
+        self.pc = self.pc.wrapping_add(length);
+    }
+
+    // 8XY1/8XY2/8XY3 reset VF on the original interpreter as a side effect of how they were
+    // implemented.
+    fn reset_vf_quirk(&mut self) {
+        if self.quirks.vf_reset {
+            self.set_carry_flag(0);
+        }
+    }
+
+    // The original 8XY6/8XYE shift VY and store the result in VX.
+    fn load_shift_source(&mut self, x: usize, y: usize) {
+        if !self.quirks.shift {
+            self.registers[x] = self.registers[y];
+        }
+    }
+
+    fn apply_load_store_quirk(&mut self, x: usize) {
+        self.index = match self.quirks.load_store {
+            LoadStoreQuirk::Unchanged => self.index,
+            LoadStoreQuirk::IncrementByX => self.index.wrapping_add(x as u16),
+            LoadStoreQuirk::IncrementByXPlusOne => self.index.wrapping_add(x as u16 + 1),
+        };
+    }
+
+    // Checks that `len` bytes starting at `addr` are all inside memory.
+    fn check_memory_range(&self, addr: usize, len: usize) -> Result<(), Chip8Error> {
+        if addr + len > self.memory.len() {
+            return Err(Chip8Error::MemoryOutOfBounds {
+                addr: addr.max(self.memory.len()),
+            });
+        }
+        Ok(())
+    }
+}
+
+// Vx through Vy inclusive, counting down if x > y.
+fn register_range(x: usize, y: usize) -> Vec<usize> {
+    if x <= y {
+        (x..=y).collect()
+    } else {
+        (y..=x).rev().collect()
     }
 }
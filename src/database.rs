@@ -0,0 +1,218 @@
+// Per-ROM settings, looked up by the SHA-1 of the ROM. The format is the `programs.json` of the
+// community CHIP-8 database (https://github.com/chip-8/chip-8-database): a list of programs, each
+// with the ROMs that exist of it keyed by their hash. Fields this interpreter has no use for are
+// ignored, so the complete database can be used in place of the bundled one.
+//
+//     [{
+//       "title": "Space Invaders",
+//       "authors": ["David Winter"],
+//       "roms": {
+//         "5c28a5f85289c9d859f95fd5eadbdcb1c30bb08b": {
+//           "platforms": ["originalChip8"],
+//           "quirkyPlatforms": { "originalChip8": { "shift": true } },
+//           "tickrate": 15,
+//           "keys": { "left": 4, "right": 6, "a": 5 },
+//           "colors": { "pixels": ["#000000", "#ffffff"] }
+//         }
+//       }
+//     }]
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Deserialize;
+
+use crate::palette::Palette;
+use crate::platform::Platform;
+use crate::quirks::{LoadStoreQuirk, Quirks};
+use crate::scheduler::TIMER_HZ;
+
+// The database for the ROMs in `roms/`.
+const BUNDLED: &str = include_str!("../data/programs.json");
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, QuirkOverrides>,
+    // Instructions per frame
+    tickrate: Option<u32>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+    colors: Option<Colors>,
+    start_address: Option<u16>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QuirkOverrides {
+    shift: Option<bool>,
+    memory_increment_by_x: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    wrap: Option<bool>,
+    jump: Option<bool>,
+    vblank: Option<bool>,
+    logic: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+// What the database says about a ROM. Everything but the title is optional.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomSettings {
+    pub title: String,
+    pub authors: Vec<String>,
+    // The first of the ROM's platforms this interpreter supports, and its quirks. Both are set
+    // or neither is.
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub instructions_per_second: Option<u32>,
+    // The CHIP-8 key for each game action: up, down, left, right, a, b, and the same for
+    // player 2 (player2Up ...).
+    pub keys: BTreeMap<String, usize>,
+    pub palette: Option<Palette>,
+    pub load_address: Option<u16>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Database {
+    programs: Vec<Program>,
+    // SHA-1 (lowercase hex) to the index of its program
+    hashes: HashMap<String, usize>,
+}
+
+impl Database {
+    pub fn bundled() -> Database {
+        Database::from_json(BUNDLED).expect("the bundled ROM database is valid")
+    }
+
+    pub fn from_json(text: &str) -> Result<Database, String> {
+        let programs: Vec<Program> = serde_json::from_str(text).map_err(|err| err.to_string())?;
+        let mut hashes = HashMap::new();
+        for (index, program) in programs.iter().enumerate() {
+            for hash in program.roms.keys() {
+                hashes.insert(hash.to_ascii_lowercase(), index);
+            }
+        }
+        Ok(Database { programs, hashes })
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomSettings> {
+        self.lookup_hash(&sha1_smol::Sha1::from(rom).digest().to_string())
+    }
+
+    pub fn lookup_hash(&self, sha1: &str) -> Option<RomSettings> {
+        let sha1 = sha1.to_ascii_lowercase();
+        let program = &self.programs[*self.hashes.get(&sha1)?];
+        let rom = program
+            .roms
+            .iter()
+            .find(|(hash, _)| hash.eq_ignore_ascii_case(&sha1))
+            .map(|(_, rom)| rom)?;
+
+        let supported = rom
+            .platforms
+            .iter()
+            .find_map(|id| platform(id).map(|(platform, quirks)| (id, platform, quirks)));
+        let (platform, quirks) = match supported {
+            Some((id, platform, quirks)) => {
+                let overrides = rom.quirky_platforms.get(id).cloned().unwrap_or_default();
+                (Some(platform), Some(overrides.apply(quirks)))
+            }
+            None => (None, None),
+        };
+
+        Some(RomSettings {
+            title: program.title.clone(),
+            authors: program.authors.clone(),
+            platform,
+            quirks,
+            instructions_per_second: rom.tickrate.map(|tickrate| tickrate * TIMER_HZ),
+            keys: rom
+                .keys
+                .iter()
+                .filter(|(_, &key)| key <= 0xF)
+                .map(|(action, &key)| (action.clone(), key as usize))
+                .collect(),
+            palette: rom.colors.as_ref().and_then(Colors::palette),
+            load_address: rom.start_address,
+        })
+    }
+}
+
+impl QuirkOverrides {
+    fn apply(&self, quirks: Quirks) -> Quirks {
+        // The two memory quirks are one setting here, and turning one off goes back to the
+        // original behaviour
+        let load_store = match (self.memory_leave_i_unchanged, self.memory_increment_by_x) {
+            (Some(true), _) => LoadStoreQuirk::Unchanged,
+            (_, Some(true)) => LoadStoreQuirk::IncrementByX,
+            (Some(false), _) if quirks.load_store == LoadStoreQuirk::Unchanged => {
+                LoadStoreQuirk::IncrementByXPlusOne
+            }
+            (_, Some(false)) if quirks.load_store == LoadStoreQuirk::IncrementByX => {
+                LoadStoreQuirk::IncrementByXPlusOne
+            }
+            _ => quirks.load_store,
+        };
+        Quirks {
+            shift: self.shift.unwrap_or(quirks.shift),
+            jump: self.jump.unwrap_or(quirks.jump),
+            load_store,
+            vf_reset: self.logic.unwrap_or(quirks.vf_reset),
+            clipping: self.wrap.map_or(quirks.clipping, |wrap| !wrap),
+            display_wait: self.vblank.unwrap_or(quirks.display_wait),
+        }
+    }
+}
+
+impl Colors {
+    // The first 2 or 4 pixel colours. XO-CHIP ROMs can list more, for planes this interpreter
+    // doesn't have.
+    fn palette(&self) -> Option<Palette> {
+        let pixels = &self.pixels[..self.pixels.len().min(4)];
+        pixels.join(",").parse().ok()
+    }
+}
+
+// The database's platform ids, and the quirks it gives each of them.
+fn platform(id: &str) -> Option<(Platform, Quirks)> {
+    match id {
+        "originalChip8" | "hybridVIP" => Some((Platform::CosmacVip, Quirks::cosmac_vip())),
+        "modernChip8" => Some((
+            Platform::CosmacVip,
+            Quirks {
+                vf_reset: false,
+                display_wait: false,
+                ..Quirks::cosmac_vip()
+            },
+        )),
+        "chip48" => Some((Platform::Chip48, Quirks::chip48())),
+        "superchip1" | "superchip" => Some((Platform::SuperChip, Quirks::superchip())),
+        "xochip" => Some((Platform::XoChip, Quirks::xochip())),
+        _ => None,
+    }
+}
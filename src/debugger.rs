@@ -0,0 +1,226 @@
+// Execution control for debugging: breakpoints on PC addresses, watchpoints on memory and
+// registers, single-stepping and stepping over subroutine calls.
+//
+// The debugger drives the CPU itself, ticking the timers every instructions_per_second / 60
+// instructions so that programs waiting on the delay timer behave as they would at full speed.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::cpu::CPU;
+use crate::error::{Chip8Error, StepOutcome};
+use crate::scheduler::TIMER_HZ;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Watchpoint {
+    // A byte of memory
+    Memory(u16),
+    // One of V0-VF
+    Register(usize),
+    // The index register
+    Index,
+}
+
+impl Watchpoint {
+    fn read(&self, cpu: &CPU) -> u16 {
+        match *self {
+            Watchpoint::Memory(addr) => {
+                cpu.memory().get(addr as usize).copied().unwrap_or(0) as u16
+            }
+            Watchpoint::Register(register) => cpu.registers()[register] as u16,
+            Watchpoint::Index => cpu.index(),
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watchpoint::Memory(addr) => write!(f, "[{:#05X}]", addr),
+            Watchpoint::Register(register) => write!(f, "V{:X}", register),
+            Watchpoint::Index => write!(f, "I"),
+        }
+    }
+}
+
+// Why execution stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    // The requested number of instructions ran.
+    Stepped,
+    // The PC reached a breakpoint. The instruction at the breakpoint has not run yet.
+    Breakpoint(u16),
+    // A watched value changed from `old` to `new` during the instruction at `pc`.
+    Watchpoint {
+        watchpoint: Watchpoint,
+        pc: u16,
+        old: u16,
+        new: u16,
+    },
+    // FX0A is waiting for a key press. Use `CPU::key_press` and carry on.
+    WaitingForKey,
+    // The program exited with 00FD.
+    Exited,
+    // The CPU faulted.
+    Fault(Chip8Error),
+    // `continue_execution` ran for its whole budget without stopping.
+    LimitReached,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Stepped => write!(f, "stepped"),
+            StopReason::Breakpoint(addr) => write!(f, "breakpoint at {:#05X}", addr),
+            StopReason::Watchpoint {
+                watchpoint,
+                pc,
+                old,
+                new,
+            } => write!(
+                f,
+                "watchpoint {} changed {:#X} -> {:#X} at {:#05X}",
+                watchpoint, old, new, pc
+            ),
+            StopReason::WaitingForKey => write!(f, "waiting for a key press"),
+            StopReason::Exited => write!(f, "program exited"),
+            StopReason::Fault(err) => write!(f, "CPU halted: {}", err),
+            StopReason::LimitReached => write!(f, "instruction limit reached"),
+        }
+    }
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: BTreeSet<Watchpoint>,
+    cycles_per_tick: u32,
+    // Instructions run since the timers last ticked
+    cycles_since_tick: u32,
+}
+
+impl Debugger {
+    pub fn new(instructions_per_second: u32) -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            cycles_per_tick: (instructions_per_second / TIMER_HZ).max(1),
+            cycles_since_tick: 0,
+        }
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    // Returns false if there was already a breakpoint at the address.
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    // Returns false if there was no breakpoint at the address.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = Watchpoint> + '_ {
+        self.watchpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        self.watchpoints.insert(watchpoint)
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        self.watchpoints.remove(&watchpoint)
+    }
+
+    // Runs up to `count` instructions, stopping early at a watchpoint, key wait, exit or fault.
+    // Breakpoints are ignored so that stepping off one works.
+    pub fn step(&mut self, cpu: &mut CPU, count: u32) -> StopReason {
+        for _ in 0..count {
+            if let Some(reason) = self.execute(cpu) {
+                return reason;
+            }
+        }
+        StopReason::Stepped
+    }
+
+    // Steps one instruction, but runs a 2NNN call through to its return.
+    pub fn step_over(&mut self, cpu: &mut CPU, limit: u64) -> StopReason {
+        let pc = cpu.pc() as usize;
+        let is_call = cpu.memory().get(pc).is_some_and(|byte| byte >> 4 == 0x2);
+        if !is_call {
+            return self.step(cpu, 1);
+        }
+
+        let return_address = cpu.pc().wrapping_add(2);
+        let depth = cpu.sp();
+        self.run_until(cpu, limit, |cpu| {
+            cpu.pc() == return_address && cpu.sp() == depth
+        })
+        .unwrap_or(StopReason::Stepped)
+    }
+
+    // Runs until a breakpoint, watchpoint, exit or fault, or until `limit` instructions have run.
+    pub fn continue_execution(&mut self, cpu: &mut CPU, limit: u64) -> StopReason {
+        self.run_until(cpu, limit, |_| false)
+            .unwrap_or(StopReason::LimitReached)
+    }
+
+    // Returns None if `done` became true.
+    fn run_until(
+        &mut self,
+        cpu: &mut CPU,
+        limit: u64,
+        done: impl Fn(&CPU) -> bool,
+    ) -> Option<StopReason> {
+        for executed in 0..limit {
+            // Don't stop on the breakpoint we are starting from
+            if executed > 0 && self.breakpoints.contains(&cpu.pc()) {
+                return Some(StopReason::Breakpoint(cpu.pc()));
+            }
+            if let Some(reason) = self.execute(cpu) {
+                return Some(reason);
+            }
+            if done(cpu) {
+                return None;
+            }
+        }
+        Some(StopReason::LimitReached)
+    }
+
+    // Runs one instruction and ticks the timers when due. Returns why execution should stop, if
+    // it should.
+    fn execute(&mut self, cpu: &mut CPU) -> Option<StopReason> {
+        let pc = cpu.pc();
+        let before: Vec<u16> = self.watchpoints.iter().map(|w| w.read(cpu)).collect();
+
+        let outcome = cpu.execute_instruction();
+
+        self.cycles_since_tick += 1;
+        if self.cycles_since_tick >= self.cycles_per_tick {
+            self.cycles_since_tick = 0;
+            cpu.decrement_timers();
+        }
+
+        match outcome {
+            Ok(StepOutcome::Exited) => return Some(StopReason::Exited),
+            Ok(StepOutcome::WaitingForKey) => return Some(StopReason::WaitingForKey),
+            Ok(_) => (),
+            Err(err) => return Some(StopReason::Fault(err)),
+        }
+
+        for (watchpoint, old) in self.watchpoints.iter().zip(before) {
+            let new = watchpoint.read(cpu);
+            if new != old {
+                return Some(StopReason::Watchpoint {
+                    watchpoint: *watchpoint,
+                    pc,
+                    old,
+                    new,
+                });
+            }
+        }
+        None
+    }
+}
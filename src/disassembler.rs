@@ -0,0 +1,154 @@
+// Turns ROMs back into assembly.
+//
+// The disassembler does a linear sweep from the load address, so sprite data and other bytes
+// that are never executed come out as (usually nonsensical) instructions. Words that don't
+// decode to anything on the chosen platform are written as DW so the listing can still be
+// reassembled.
+
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+
+use crate::instruction::Instruction;
+use crate::opcode::{Opcode, Operand};
+use crate::platform::Platform;
+
+// One decoded instruction or data word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16,
+    // The instruction word, or the single byte left over at the end of a ROM with an odd length.
+    pub word: u16,
+    pub opcode: Option<Opcode>,
+    // The address word following F000, if the ROM doesn't end first.
+    pub long_address: Option<u16>,
+    // How many bytes the line covers: 2, 4 for F000 NNNN or 1 for a trailing byte.
+    pub size: usize,
+}
+
+impl Line {
+    // The instruction with no labels, e.g. `LD V0, 0x12`.
+    pub fn text(&self) -> String {
+        self.text_with_labels(&BTreeSet::new())
+    }
+
+    // The instruction with jump and call targets replaced by labels from `labels`.
+    fn text_with_labels(&self, labels: &BTreeSet<u16>) -> String {
+        let Some(opcode) = self.opcode else {
+            return match self.size {
+                1 => format!("DB {:#04X}", self.word),
+                _ => format!("DW {:#06X}", self.word),
+            };
+        };
+        match (opcode, self.long_address) {
+            (Opcode::LdLongI, Some(addr)) => return format!("LD I, LONG {:#06X}", addr),
+            // F000 at the very end, with no address after it
+            (Opcode::LdLongI, None) => return format!("DW {:#06X}", self.word),
+            _ => (),
+        }
+
+        let operands: Vec<String> = opcode
+            .operands()
+            .into_iter()
+            .map(|operand| match (operand, opcode.branch_target()) {
+                (Operand::Number(addr), Some(target))
+                    if addr == target && labels.contains(&addr) =>
+                {
+                    label(addr)
+                }
+                _ => operand.to_string(),
+            })
+            .collect();
+        if operands.is_empty() {
+            opcode.mnemonic().to_string()
+        } else {
+            format!("{} {}", opcode.mnemonic(), operands.join(", "))
+        }
+    }
+}
+
+// Decodes the instruction at `addr`, which must be inside `memory`.
+pub fn decode_at(memory: &[u8], addr: usize, platform: Platform) -> Line {
+    let word = match memory.get(addr + 1) {
+        Some(&low) => (memory[addr] as u16) << 8 | low as u16,
+        None => memory[addr] as u16,
+    };
+    let opcode = if addr + 1 < memory.len() {
+        Opcode::decode(&Instruction::from_bytes(word), platform)
+    } else {
+        None
+    };
+    let long_address = match (opcode, memory.get(addr + 2..addr + 4)) {
+        (Some(Opcode::LdLongI), Some(&[high, low])) => Some((high as u16) << 8 | low as u16),
+        _ => None,
+    };
+    let size = match (opcode, long_address) {
+        (Some(Opcode::LdLongI), Some(_)) => 4,
+        _ if addr + 1 < memory.len() => 2,
+        _ => 1,
+    };
+    Line {
+        addr: addr as u16,
+        word,
+        opcode,
+        long_address,
+        size,
+    }
+}
+
+// Decodes a whole ROM loaded at `load_address`.
+pub fn disassemble(rom: &[u8], load_address: u16, platform: Platform) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let mut line = decode_at(rom, offset, platform);
+        line.addr = load_address.wrapping_add(offset as u16);
+        offset += line.size;
+        lines.push(line);
+    }
+    lines
+}
+
+// A listing with the instruction on each line followed by its address and raw bytes as a
+// comment, and a label before every jump or call target. It assembles back to `rom`.
+pub fn listing(rom: &[u8], load_address: u16, platform: Platform) -> String {
+    let lines = disassemble(rom, load_address, platform);
+    let starts: BTreeSet<u16> = lines.iter().map(|line| line.addr).collect();
+    let labels: BTreeSet<u16> = lines
+        .iter()
+        .filter_map(|line| line.opcode.and_then(|opcode| opcode.branch_target()))
+        .filter(|target| starts.contains(target))
+        .collect();
+
+    let rom_end = load_address as usize + rom.len();
+    let mut text = String::new();
+    for line in &lines {
+        if labels.contains(&line.addr) {
+            let _ = writeln!(text, "{}:", label(line.addr));
+        }
+        let offset = (line.addr.wrapping_sub(load_address)) as usize;
+        let bytes: Vec<String> = rom[offset..(offset + line.size).min(rom.len())]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let mut text_line = format!(
+            "    {:<24}  ; {:#05X}  {}",
+            line.text_with_labels(&labels),
+            line.addr,
+            bytes.join(" ")
+        );
+        // Jumps into the middle of an instruction mean the sweep is misaligned here
+        if let Some(target) = line.opcode.and_then(|opcode| opcode.branch_target()) {
+            let in_rom = (load_address as usize..rom_end).contains(&(target as usize));
+            if in_rom && !starts.contains(&target) {
+                let _ = write!(text_line, "  target is mid-instruction");
+            }
+        }
+        text.push_str(text_line.trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+fn label(addr: u16) -> String {
+    format!("L{:03X}", addr)
+}
@@ -1,31 +1,46 @@
 use pixels::{Pixels, SurfaceTexture};
 use winit::{
-    dpi::LogicalSize,
+    dpi::{LogicalSize, PhysicalSize},
     event_loop::EventLoop,
-    window::{Window, WindowBuilder},
+    window::{Fullscreen, Window, WindowBuilder},
 };
 // use winit_input_helper::WinitInputHelper;
 
-use crate::cpu::CPU;
+use chip8::cpu::FONT;
+use chip8::keyboard::{Keymap, KEYPAD};
+use chip8::palette::Palette;
+use chip8::{CPU, DISPLAY_HEIGHT, DISPLAY_WIDTH};
 
-const WIDTH: u32 = 640;
-const HEIGHT: u32 = 320;
+pub const DEFAULT_SCALE: u32 = 10;
 
 pub struct Display {
     // pub input: WinitInputHelper,
     pub window: Window,
     pub pixels: Pixels,
+    pub palette: Palette,
+    // The size of the pixel buffer, which is DISPLAY_WIDTH x DISPLAY_HEIGHT times the scale
+    width: u32,
+    height: u32,
 }
 
 impl Display {
-    pub fn new(event_loop: &EventLoop<()>) -> Display {
-        let window_size = LogicalSize::new(WIDTH as f64, HEIGHT as f64);
+    // `scale` is the number of window pixels for each low resolution CHIP-8 pixel.
+    pub fn new(
+        event_loop: &EventLoop<()>,
+        scale: u32,
+        fullscreen: bool,
+        palette: Palette,
+    ) -> Display {
+        let width = DISPLAY_WIDTH as u32 * scale;
+        let height = DISPLAY_HEIGHT as u32 * scale;
+        let window_size = LogicalSize::new(width as f64, height as f64);
         let window = WindowBuilder::new()
             .with_title("CHIP-8")
             .with_resizable(false)
             .with_inner_size(window_size)
             .with_min_inner_size(window_size)
-            .build(&event_loop)
+            .with_fullscreen(fullscreen.then_some(Fullscreen::Borderless(None)))
+            .build(event_loop)
             .unwrap();
 
         let surface_texture = SurfaceTexture::new(
@@ -33,30 +48,89 @@ impl Display {
             window.inner_size().height,
             &window,
         );
-        let pixels = Pixels::new(WIDTH, HEIGHT, surface_texture).unwrap();
+        let pixels = Pixels::new(width, height, surface_texture).unwrap();
 
         Display {
             // input: WinitInputHelper::new(),
-            window: window,
-            pixels: pixels,
+            window,
+            pixels,
+            palette,
+            width,
+            height,
+        }
+    }
+
+    pub fn toggle_fullscreen(&self) {
+        let fullscreen = match self.window.fullscreen() {
+            Some(_) => None,
+            None => Some(Fullscreen::Borderless(None)),
+        };
+        self.window.set_fullscreen(fullscreen);
+    }
+
+    // Called when the window changes size, e.g. going fullscreen.
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        if let Err(err) = self.pixels.resize_surface(size.width, size.height) {
+            eprintln!("Unable to resize the display: {}", err);
         }
     }
 
     pub fn draw(&mut self, cpu: &CPU) {
+        let display_width = cpu.display_width();
+        let framebuffer = cpu.framebuffer();
+        self.present(display_width, cpu.display_height(), |x, y| {
+            framebuffer[y * display_width + x]
+        });
+    }
+
+    // The rebinding screen: the keypad, with the key being bound lit up and keys without any
+    // host keys greyed out.
+    pub fn draw_keypad(&mut self, selected: usize, keymap: &Keymap) {
+        let unbound: Vec<usize> = KEYPAD
+            .into_iter()
+            .filter(|&key| keymap.host_keys(key).is_empty())
+            .collect();
+        // Each key gets a 16x8 cell with its 4x5 digit in the middle
+        self.present(DISPLAY_WIDTH, DISPLAY_HEIGHT, |x, y| {
+            let key = KEYPAD[y / 8 * 4 + x / 16];
+            let (column, row) = (x % 16, y % 8);
+            let lit = (6..10).contains(&column)
+                && (1..6).contains(&row)
+                && FONT[key * 5 + row - 1] & (0x80 >> (column - 6)) != 0;
+            if key == selected {
+                u8::from(!lit)
+            } else if unbound.contains(&key) {
+                if lit {
+                    2
+                } else {
+                    0
+                }
+            } else {
+                u8::from(lit)
+            }
+        });
+    }
+
+    // Fills the window from a `display_width` x `display_height` picture, given as the XO-CHIP
+    // planes lit at each position.
+    fn present(
+        &mut self,
+        display_width: usize,
+        display_height: usize,
+        planes: impl Fn(usize, usize) -> u8,
+    ) {
+        let (width, height) = (self.width as usize, self.height as usize);
         let frame = self.pixels.frame_mut();
 
-        // Draw to screen
         // Each pixel is represented by 4 bytes in the frame buffer: R, G, B, and A.
         for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
-            let x = i % WIDTH as usize / 10;
-            let y = i / WIDTH as usize / 10;
-
-            let display_pixel_index = cpu.get_display_pixel_index(x, y);
-            let pixel_enabled = cpu.get_display_pixel(display_pixel_index);
+            // Scale whichever resolution is active up to fill the window
+            let x = i % width * display_width / width;
+            let y = i / width * display_height / height;
 
-            let rgba = if pixel_enabled { [0xFF; 4] } else { [0x00; 4] };
+            let [r, g, b] = self.palette.color(planes(x, y));
 
-            pixel.copy_from_slice(&rgba);
+            pixel.copy_from_slice(&[r, g, b, 0xFF]);
         }
 
         self.pixels.render().unwrap();
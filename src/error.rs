@@ -0,0 +1,91 @@
+use std::fmt;
+
+// What happened when the CPU ran a single instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    // The instruction ran and the PC moved on.
+    Executed,
+    // FX0A is blocking until a key is pressed. The PC has been left on the FX0A instruction.
+    WaitingForKey,
+    // DXYN is waiting for the next timer tick because of the display wait quirk. The PC has been
+    // left on the DXYN instruction.
+    WaitingForVBlank,
+    // SUPER-CHIP 00FD asked the interpreter to exit. The PC has been left on the 00FD instruction.
+    Exited,
+}
+
+// A fault raised while executing an instruction. When an error is returned the PC is left on
+// the faulting instruction and no other state has been modified, so the machine can be inspected.
+// Headless runs also refuse to start with ZeroInstructionsPerSecond, before anything has run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Error {
+    // The instruction at pc does not decode to anything the interpreter supports.
+    UnknownOpcode { pc: u16, opcode: u16 },
+    // 2NNN was called with all 16 stack slots in use.
+    StackOverflow { pc: u16 },
+    // 00EE was called with an empty stack.
+    StackUnderflow { pc: u16 },
+    // An instruction tried to read or write memory past the end of the address space.
+    MemoryOutOfBounds { addr: usize },
+    // The PC points past the last full instruction in memory.
+    PcOutOfBounds { pc: u16 },
+    // A headless run was asked for 0 instructions per second, so would never reach a cycle limit.
+    ZeroInstructionsPerSecond,
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode {:04X} at {:#05X}", opcode, pc)
+            }
+            Chip8Error::StackOverflow { pc } => write!(f, "stack overflow at {:#05X}", pc),
+            Chip8Error::StackUnderflow { pc } => write!(f, "stack underflow at {:#05X}", pc),
+            Chip8Error::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at {:#X}", addr)
+            }
+            Chip8Error::PcOutOfBounds { pc } => {
+                write!(f, "program counter out of bounds: {:#X}", pc)
+            }
+            Chip8Error::ZeroInstructionsPerSecond => {
+                write!(f, "instructions per second must be at least 1")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
+
+// Why a ROM couldn't be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomError {
+    Empty,
+    // The ROM doesn't fit between the load address and the end of the platform's memory.
+    TooLarge {
+        size: usize,
+        max: usize,
+        address: u16,
+    },
+    // Programs can't be loaded over the fonts below 0x200 or past the end of memory.
+    InvalidLoadAddress {
+        address: u16,
+    },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::Empty => write!(f, "the ROM is empty"),
+            RomError::TooLarge { size, max, address } => write!(
+                f,
+                "the ROM is {} bytes but only {} fit in memory from {:#05X}",
+                size, max, address
+            ),
+            RomError::InvalidLoadAddress { address } => {
+                write!(f, "{:#05X} is outside program memory", address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
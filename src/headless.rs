@@ -0,0 +1,357 @@
+// Running ROMs without a window: a frame-based run loop driven by an optional key script, and
+// helpers to dump the final state as text, PNG and hashes.
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::Instant;
+
+use crate::audio::Beeper;
+use crate::cpu::CPU;
+use crate::error::{Chip8Error, StepOutcome};
+use crate::movie::{Desync, Movie};
+use crate::palette::Palette;
+use crate::scheduler::Scheduler;
+
+// A key press or release at the start of a 60 Hz frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: usize,
+    pub pressed: bool,
+}
+
+// Scripted keypad input. The text format has one event per line:
+//
+//     # frame  action   key
+//     120      press    5
+//     130      release  5
+//
+// Keys are hex digits 0-F. Blank lines and anything after a `#` are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyScript {
+    // Sorted by frame
+    events: Vec<KeyEvent>,
+}
+
+impl KeyScript {
+    pub fn new(mut events: Vec<KeyEvent>) -> KeyScript {
+        events.sort_by_key(|event| event.frame);
+        KeyScript { events }
+    }
+
+    pub fn events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    // The events that happen at the start of `frame`.
+    pub fn events_at(&self, frame: u64) -> impl Iterator<Item = &KeyEvent> {
+        let start = self.events.partition_point(|event| event.frame < frame);
+        self.events[start..]
+            .iter()
+            .take_while(move |event| event.frame == frame)
+    }
+}
+
+impl FromStr for KeyScript {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut events = Vec::new();
+        for (line_number, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {}", line_number + 1, message);
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [frame, action, key] = fields[..] else {
+                return Err(error("expected <frame> <press|release> <key>"));
+            };
+            let frame = frame
+                .parse::<u64>()
+                .map_err(|_| error("frame must be a number"))?;
+            let pressed = match action {
+                "press" | "down" => true,
+                "release" | "up" => false,
+                _ => return Err(error("action must be press or release")),
+            };
+            let key = match u8::from_str_radix(key, 16) {
+                Ok(key) if key <= 0xF => key as usize,
+                _ => return Err(error("key must be a hex digit 0-F")),
+            };
+
+            events.push(KeyEvent {
+                frame,
+                key,
+                pressed,
+            });
+        }
+        Ok(KeyScript::new(events))
+    }
+}
+
+// How long to run for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunLimit {
+    // Stop after this many instructions.
+    Cycles(u64),
+    // Stop after this many 60 Hz frames.
+    Frames(u64),
+}
+
+// How a headless run finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunEnd {
+    // The cycle or frame limit was reached.
+    LimitReached,
+    // The program exited with SUPER-CHIP 00FD.
+    Exited,
+    // The CPU faulted.
+    Fault(Chip8Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunSummary {
+    pub frames: u64,
+    pub cycles: u64,
+    pub end: RunEnd,
+}
+
+// Runs the CPU as fast as possible, frame by frame. Each frame applies the scripted key events for
+// that frame, runs instructions_per_second / 60 instructions, then ticks the timers (and the
+// beeper, if there is one). Fails before running anything if instructions_per_second is 0.
+pub fn run(
+    cpu: &mut CPU,
+    instructions_per_second: u32,
+    limit: RunLimit,
+    script: &KeyScript,
+    beeper: Option<&mut Beeper>,
+) -> Result<RunSummary, Chip8Error> {
+    run_frames(
+        cpu,
+        instructions_per_second,
+        limit,
+        beeper,
+        |cpu, frame, _| apply_key_events(cpu, script.events_at(frame)),
+    )
+}
+
+fn apply_key_events<'a>(cpu: &mut CPU, events: impl Iterator<Item = &'a KeyEvent>) {
+    for event in events {
+        if event.pressed {
+            cpu.key_press(event.key);
+        } else {
+            cpu.key_release(event.key);
+        }
+    }
+}
+
+// Like `run`, but also records the session as a movie. `cpu` should have its ROM loaded and not
+// have run yet.
+pub fn record(
+    cpu: &mut CPU,
+    instructions_per_second: u32,
+    limit: RunLimit,
+    script: &KeyScript,
+    beeper: Option<&mut Beeper>,
+) -> Result<(RunSummary, Movie), String> {
+    if instructions_per_second == 0 {
+        return Err(Chip8Error::ZeroInstructionsPerSecond.to_string());
+    }
+    let mut movie = Movie::new(cpu, instructions_per_second)?;
+    let summary = run_frames(
+        cpu,
+        instructions_per_second,
+        limit,
+        beeper,
+        |cpu, frame, cycle| {
+            for event in script.events_at(frame) {
+                movie.record(frame, cycle, event.key, event.pressed);
+            }
+            apply_key_events(cpu, script.events_at(frame));
+        },
+    )
+    .map_err(|err| err.to_string())?;
+    movie.finish(summary.frames, summary.cycles, cpu);
+    Ok((summary, movie))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Playback {
+    pub summary: RunSummary,
+    pub desync: Option<Desync>,
+    // Whether the final state matches the end of the recording, if the movie has one
+    pub matches_end: Option<bool>,
+}
+
+// Plays a movie back on `cpu`, which should be the movie's `cpu()` with the ROM loaded. Runs until
+// the end of the recording, or for `limit` if given. Fails if the movie has 0 instructions per
+// second.
+pub fn play(
+    cpu: &mut CPU,
+    movie: &Movie,
+    limit: Option<RunLimit>,
+    beeper: Option<&mut Beeper>,
+) -> Result<Playback, Chip8Error> {
+    // Stopping after the recorded number of instructions also stops where the recording did if
+    // the program exited or faulted part way through a frame.
+    let limit = match (limit, &movie.end) {
+        (Some(limit), _) => limit,
+        (None, Some(end)) => RunLimit::Cycles(end.cycle),
+        (None, None) => RunLimit::Frames(movie.events.last().map_or(0, |event| event.frame + 1)),
+    };
+
+    let mut desync = None;
+    let summary = run_frames(
+        cpu,
+        movie.instructions_per_second,
+        limit,
+        beeper,
+        |cpu, frame, cycle| {
+            if let Err(err) = movie.apply(cpu, frame, cycle) {
+                desync.get_or_insert(err);
+            }
+        },
+    )?;
+
+    let matches_end = movie.matches_end(cpu, summary.frames, summary.cycles);
+    Ok(Playback {
+        summary,
+        desync,
+        matches_end,
+    })
+}
+
+// The run loop shared by `run`, `record` and `play`. `on_frame` is called at the start of every frame with
+// the frame number and the instructions run so far.
+fn run_frames(
+    cpu: &mut CPU,
+    instructions_per_second: u32,
+    limit: RunLimit,
+    mut beeper: Option<&mut Beeper>,
+    mut on_frame: impl FnMut(&mut CPU, u64, u64),
+) -> Result<RunSummary, Chip8Error> {
+    if instructions_per_second == 0 {
+        return Err(Chip8Error::ZeroInstructionsPerSecond);
+    }
+    // Only used to spread the instructions across frames, so the clock doesn't matter
+    let mut scheduler = Scheduler::new(instructions_per_second, Instant::now());
+    let mut frames = 0;
+    let mut cycles = 0;
+
+    let limit_reached = |frames: u64, cycles: u64| match limit {
+        RunLimit::Cycles(limit) => cycles >= limit,
+        RunLimit::Frames(limit) => frames >= limit,
+    };
+
+    while !limit_reached(frames, cycles) {
+        on_frame(cpu, frames, cycles);
+
+        for _ in 0..scheduler.cycles_for_next_tick() {
+            if limit_reached(frames, cycles) {
+                break;
+            }
+            cycles += 1;
+            let end = match cpu.execute_instruction() {
+                Ok(StepOutcome::Exited) => RunEnd::Exited,
+                Ok(_) => continue,
+                Err(err) => RunEnd::Fault(err),
+            };
+            return Ok(RunSummary {
+                frames,
+                cycles,
+                end,
+            });
+        }
+
+        cpu.decrement_timers();
+        if let Some(beeper) = beeper.as_deref_mut() {
+            if let Err(err) = beeper.tick(cpu.tone()) {
+                eprintln!("Failed to write audio: {}", err);
+            }
+        }
+        frames += 1;
+    }
+
+    Ok(RunSummary {
+        frames,
+        cycles,
+        end: RunEnd::LimitReached,
+    })
+}
+
+// Characters for each combination of lit planes: off, plane 1, plane 2, both.
+const ASCII_PIXELS: [char; 4] = ['.', '#', '+', '@'];
+
+// The framebuffer as one line of text per row.
+pub fn framebuffer_ascii(cpu: &CPU) -> String {
+    let mut text = String::new();
+    for row in cpu.framebuffer().chunks(cpu.display_width()) {
+        text.extend(
+            row.iter()
+                .map(|&planes| ASCII_PIXELS[planes as usize & 0b11]),
+        );
+        text.push('\n');
+    }
+    text
+}
+
+// Writes the framebuffer as an RGB PNG, with each pixel scaled up to `scale` x `scale`.
+pub fn write_png<W: Write>(cpu: &CPU, writer: W, palette: &Palette, scale: u32) -> io::Result<()> {
+    let scale = scale.max(1) as usize;
+    let width = cpu.display_width() * scale;
+    let height = cpu.display_height() * scale;
+
+    let mut data = Vec::with_capacity(width * height * 3);
+    for row in cpu.framebuffer().chunks(cpu.display_width()) {
+        let mut line = Vec::with_capacity(width * 3);
+        for &planes in row {
+            for _ in 0..scale {
+                line.extend_from_slice(&palette.color(planes));
+            }
+        }
+        for _ in 0..scale {
+            data.extend_from_slice(&line);
+        }
+    }
+
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut png_writer = encoder.write_header().map_err(png_error)?;
+    png_writer.write_image_data(&data).map_err(png_error)
+}
+
+fn png_error(err: png::EncodingError) -> io::Error {
+    match err {
+        png::EncodingError::IoError(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+// The SHA-1 of the whole of memory as a hex string.
+pub fn memory_hash(cpu: &CPU) -> String {
+    sha1_smol::Sha1::from(cpu.memory()).digest().to_string()
+}
+
+// Registers, index, PC, stack and timers in a readable form.
+pub fn registers_dump(cpu: &CPU) -> String {
+    let mut text = String::new();
+    let _ = writeln!(
+        text,
+        "PC: {:#05X}  I: {:#05X}  SP: {}  DT: {}  ST: {}",
+        cpu.pc(),
+        cpu.index(),
+        cpu.sp(),
+        cpu.delay_timer(),
+        cpu.sound_timer()
+    );
+    for (i, value) in cpu.registers().iter().enumerate() {
+        let _ = write!(text, "V{:X}: {:02X}", i, value);
+        text.push_str(if i % 8 == 7 { "\n" } else { "  " });
+    }
+    let _ = writeln!(text, "Stack: {:03X?}", cpu.stack());
+    text
+}
@@ -1,4 +1,5 @@
-// Input mapping:
+// The keypad mapping: which host keys press which CHIP-8 keys. The default layout puts the
+// COSMAC VIP's keypad on the left of the keyboard:
 // Emulator     Chip8
 // +-+-+-+-+    +-+-+-+-+
 // |1|2|3|4|    |1|2|3|C|
@@ -6,27 +7,516 @@
 // |A|S|D|F|    |7|8|9|E|
 // |Z|X|C|V|    |A|0|B|F|
 // +-+-+-+-+    +-+-+-+-+
+// Keys are bound by name, which follows the keyboard layout, or by scancode, which is the physical
+// key. The positional layout binds the same grid by scancode, so it stays in place on AZERTY or
+// Dvorak keyboards.
 
-use winit::event::VirtualKeyCode;
-
-pub fn key_code_to_index(virtual_keycode: Option<VirtualKeyCode>) -> Option<usize> {
-    return match virtual_keycode {
-        Some(VirtualKeyCode::Key1) => Some(0x1),
-        Some(VirtualKeyCode::Key2) => Some(0x2),
-        Some(VirtualKeyCode::Key3) => Some(0x3),
-        Some(VirtualKeyCode::Key4) => Some(0xC),
-        Some(VirtualKeyCode::Q) => Some(0x4),
-        Some(VirtualKeyCode::W) => Some(0x5),
-        Some(VirtualKeyCode::E) => Some(0x6),
-        Some(VirtualKeyCode::R) => Some(0xD),
-        Some(VirtualKeyCode::A) => Some(0x7),
-        Some(VirtualKeyCode::S) => Some(0x8),
-        Some(VirtualKeyCode::D) => Some(0x9),
-        Some(VirtualKeyCode::F) => Some(0xE),
-        Some(VirtualKeyCode::Z) => Some(0xA),
-        Some(VirtualKeyCode::X) => Some(0x0),
-        Some(VirtualKeyCode::C) => Some(0xB),
-        Some(VirtualKeyCode::V) => Some(0xF),
-        _ => None,
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+use winit::event::{KeyboardInput, VirtualKeyCode};
+
+// The CHIP-8 keys in the order they're laid out on the keypad.
+pub const KEYPAD: [usize; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+// The default grid by name, in keypad order.
+const QWERTY: [VirtualKeyCode; 16] = [
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::W,
+    VirtualKeyCode::E,
+    VirtualKeyCode::R,
+    VirtualKeyCode::A,
+    VirtualKeyCode::S,
+    VirtualKeyCode::D,
+    VirtualKeyCode::F,
+    VirtualKeyCode::Z,
+    VirtualKeyCode::X,
+    VirtualKeyCode::C,
+    VirtualKeyCode::V,
+];
+
+// The same keys by scancode. These are the PC scancodes winit reports on Linux and Windows; macOS
+// numbers its keys differently.
+const POSITIONAL: [u32; 16] = [2, 3, 4, 5, 16, 17, 18, 19, 30, 31, 32, 33, 44, 45, 46, 47];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum HostKey {
+    Named(VirtualKeyCode),
+    Scancode(u32),
+}
+
+// What a keymap starts from before its own bindings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    #[default]
+    Qwerty,
+    Positional,
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    layout: Layout,
+    bindings: HashMap<HostKey, usize>,
+}
+
+impl Keymap {
+    pub fn new(layout: Layout) -> Keymap {
+        let bindings = match layout {
+            Layout::Qwerty => QWERTY.into_iter().map(HostKey::Named).zip(KEYPAD).collect(),
+            Layout::Positional => POSITIONAL
+                .into_iter()
+                .map(HostKey::Scancode)
+                .zip(KEYPAD)
+                .collect(),
+            Layout::None => HashMap::new(),
+        };
+        Keymap { layout, bindings }
+    }
+
+    // The CHIP-8 key a key press is for, if any. Scancode bindings are checked first.
+    pub fn key(&self, input: &KeyboardInput) -> Option<usize> {
+        self.bindings
+            .get(&HostKey::Scancode(input.scancode))
+            .or_else(|| self.bindings.get(&HostKey::Named(input.virtual_keycode?)))
+            .copied()
+    }
+
+    pub fn host_keys(&self, key: usize) -> Vec<HostKey> {
+        let mut host_keys: Vec<HostKey> = self
+            .bindings
+            .iter()
+            .filter(|(_, &bound)| bound == key)
+            .map(|(&host_key, _)| host_key)
+            .collect();
+        host_keys.sort();
+        host_keys
+    }
+
+    // Binds `host_keys` to `key` in place of its current ones. Host keys bound to other CHIP-8
+    // keys are moved.
+    pub fn bind(&mut self, key: usize, host_keys: &[HostKey]) {
+        self.bindings.retain(|_, bound| *bound != key);
+        for &host_key in host_keys {
+            self.bindings.insert(host_key, key);
+        }
+    }
+
+    // How a key press is written down when rebinding: by scancode with the positional layout,
+    // otherwise by name if it has one.
+    pub fn host_key_for(&self, input: &KeyboardInput) -> HostKey {
+        match input.virtual_keycode {
+            Some(virtual_keycode)
+                if self.layout != Layout::Positional && key_name(virtual_keycode).is_some() =>
+            {
+                HostKey::Named(virtual_keycode)
+            }
+            _ => HostKey::Scancode(input.scancode),
+        }
+    }
+
+    // Adds host keys for the game actions in a ROM's database entry: the arrow keys, Space and
+    // Return for player 1, and IJKL, U and O for player 2. The keypad bindings stay as they are.
+    pub fn bind_actions(&mut self, actions: &BTreeMap<String, usize>) {
+        for (action, &key) in actions {
+            let host_key = match action.as_str() {
+                "up" => VirtualKeyCode::Up,
+                "down" => VirtualKeyCode::Down,
+                "left" => VirtualKeyCode::Left,
+                "right" => VirtualKeyCode::Right,
+                "a" => VirtualKeyCode::Space,
+                "b" => VirtualKeyCode::Return,
+                "player2Up" => VirtualKeyCode::I,
+                "player2Down" => VirtualKeyCode::K,
+                "player2Left" => VirtualKeyCode::J,
+                "player2Right" => VirtualKeyCode::L,
+                "player2A" => VirtualKeyCode::U,
+                "player2B" => VirtualKeyCode::O,
+                _ => continue,
+            };
+            self.bindings.insert(HostKey::Named(host_key), key);
+        }
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::new(Layout::Qwerty)
+    }
+}
+
+// Keys for the frontend itself rather than the CHIP-8 keypad. They're checked before the keymap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hotkeys {
+    pub quit: VirtualKeyCode,
+    pub pause: VirtualKeyCode,
+    pub fullscreen: VirtualKeyCode,
+    pub rebind: VirtualKeyCode,
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        Hotkeys {
+            quit: VirtualKeyCode::Escape,
+            pause: VirtualKeyCode::P,
+            fullscreen: VirtualKeyCode::F11,
+            rebind: VirtualKeyCode::F10,
+        }
+    }
+}
+
+// The rebinding screen goes through the keypad in order, collecting host keys for each CHIP-8
+// key until Return moves on to the next one. Backspace starts the key again and Escape gives up,
+// so those three can't be bound here.
+#[derive(Debug, Clone)]
+pub struct Rebinding {
+    keymap: Keymap,
+    // Index into KEYPAD
+    position: usize,
+    pending: Vec<HostKey>,
+}
+
+pub enum RebindStep {
+    Continue,
+    Done(Keymap),
+    Cancelled,
+}
+
+impl Rebinding {
+    pub fn new(keymap: &Keymap) -> Rebinding {
+        Rebinding {
+            keymap: keymap.clone(),
+            position: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    // The CHIP-8 key being bound.
+    pub fn key(&self) -> usize {
+        KEYPAD[self.position]
+    }
+
+    // The keymap so far.
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    pub fn press(&mut self, input: &KeyboardInput) -> RebindStep {
+        match input.virtual_keycode {
+            Some(VirtualKeyCode::Escape) => return RebindStep::Cancelled,
+            Some(VirtualKeyCode::Back) => self.pending.clear(),
+            Some(VirtualKeyCode::Return) => {
+                // Nothing pressed keeps the current keys
+                if !self.pending.is_empty() {
+                    self.keymap.bind(self.key(), &self.pending);
+                    self.pending.clear();
+                }
+                self.position += 1;
+                if self.position == KEYPAD.len() {
+                    return RebindStep::Done(self.keymap.clone());
+                }
+            }
+            _ => {
+                let host_key = self.keymap.host_key_for(input);
+                if !self.pending.contains(&host_key) {
+                    self.pending.push(host_key);
+                }
+            }
+        }
+        RebindStep::Continue
+    }
+
+    // What to do next, for the window title.
+    pub fn prompt(&self) -> String {
+        let host_keys = if self.pending.is_empty() {
+            self.keymap.host_keys(self.key())
+        } else {
+            self.pending.clone()
+        };
+        let names: Vec<String> = host_keys.iter().map(HostKey::to_string).collect();
+        let names = if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(" ")
+        };
+        format!(
+            "CHIP-8 - press the keys for {:X} then Return ({}), Backspace to clear, Esc to cancel",
+            self.key(),
+            names
+        )
+    }
+}
+
+impl fmt::Display for HostKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostKey::Named(virtual_keycode) => match key_name(*virtual_keycode) {
+                Some(name) => f.write_str(name),
+                None => write!(f, "{:?}", virtual_keycode),
+            },
+            HostKey::Scancode(scancode) => write!(f, "scancode:{}", scancode),
+        }
+    }
+}
+
+// A winit key name, or `scancode:<n>` in decimal or hex.
+impl FromStr for HostKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(scancode) = s.strip_prefix("scancode:") else {
+            return host_key_named(s).map(HostKey::Named);
+        };
+        let parsed = match scancode.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => scancode.parse(),
+        };
+        parsed
+            .map(HostKey::Scancode)
+            .map_err(|_| format!("invalid scancode '{}'", scancode))
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Layout::Qwerty => "qwerty",
+            Layout::Positional => "positional",
+            Layout::None => "none",
+        })
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "qwerty" => Ok(Layout::Qwerty),
+            "positional" => Ok(Layout::Positional),
+            "none" => Ok(Layout::None),
+            _ => Err(format!(
+                "unknown layout '{}', expected qwerty, positional or none",
+                s
+            )),
+        }
+    }
+}
+
+// A keymap file has a CHIP-8 key (hex) per line followed by its host keys, which replace the
+// key's bindings from the layout. A key on its own is left unbound. `layout` picks the layout
+// the file starts from:
+//
+//     layout positional
+//     # CHIP-8 key  host keys
+//     5             scancode:17 Up
+//     8             scancode:31 Down
+//
+// Blank lines and anything after a `#` are ignored.
+impl FromStr for Keymap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut layout = Layout::default();
+        let mut bindings = Vec::new();
+        for (index, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", index + 1, message);
+
+            let mut fields = line.split_whitespace();
+            let first = fields.next().unwrap_or_default();
+            if first == "layout" {
+                let name = fields.next().unwrap_or_default();
+                layout = name.parse().map_err(error)?;
+                continue;
+            }
+            let key = chip8_key(first).map_err(error)?;
+            let host_keys = fields
+                .map(|field| field.parse::<HostKey>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(error)?;
+            bindings.push((key, host_keys));
+        }
+
+        let mut keymap = Keymap::new(layout);
+        for (key, host_keys) in bindings {
+            keymap.bind(key, &host_keys);
+        }
+        Ok(keymap)
+    }
+}
+
+// Written out in full, so it reads back the same whatever the layout.
+impl fmt::Display for Keymap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# CHIP-8 keymap")?;
+        writeln!(f, "layout {}", self.layout)?;
+        for key in KEYPAD {
+            write!(f, "{:X}", key)?;
+            for host_key in self.host_keys(key) {
+                write!(f, " {}", host_key)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+macro_rules! key_names {
+    ($($key:ident),* $(,)?) => {
+        // The host keys that can be bound, by their winit names.
+        const KEY_NAMES: &[(&str, VirtualKeyCode)] = &[$((stringify!($key), VirtualKeyCode::$key)),*];
     };
 }
+
+key_names!(
+    Key1,
+    Key2,
+    Key3,
+    Key4,
+    Key5,
+    Key6,
+    Key7,
+    Key8,
+    Key9,
+    Key0,
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    F10,
+    F11,
+    F12,
+    Insert,
+    Home,
+    Delete,
+    End,
+    PageDown,
+    PageUp,
+    Left,
+    Up,
+    Right,
+    Down,
+    Back,
+    Return,
+    Space,
+    Numpad0,
+    Numpad1,
+    Numpad2,
+    Numpad3,
+    Numpad4,
+    Numpad5,
+    Numpad6,
+    Numpad7,
+    Numpad8,
+    Numpad9,
+    NumpadAdd,
+    NumpadDivide,
+    NumpadDecimal,
+    NumpadComma,
+    NumpadEnter,
+    NumpadEquals,
+    NumpadMultiply,
+    NumpadSubtract,
+    Apostrophe,
+    Backslash,
+    Comma,
+    Equals,
+    Grave,
+    LAlt,
+    LBracket,
+    LControl,
+    LShift,
+    Minus,
+    Period,
+    RAlt,
+    RBracket,
+    RControl,
+    RShift,
+    Semicolon,
+    Slash,
+    Tab,
+    Escape,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+);
+
+// The name a host key is written with.
+fn key_name(virtual_keycode: VirtualKeyCode) -> Option<&'static str> {
+    KEY_NAMES
+        .iter()
+        .find(|&&(_, key_code)| key_code == virtual_keycode)
+        .map(|&(name, _)| name)
+}
+
+// Looks up a host key by its winit name, ignoring case.
+pub fn host_key_named(name: &str) -> Result<VirtualKeyCode, String> {
+    KEY_NAMES
+        .iter()
+        .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+        .map(|&(_, key_code)| key_code)
+        .ok_or_else(|| format!("unknown host key '{}'", name))
+}
+
+// A CHIP-8 key written as a hex digit.
+pub fn chip8_key(text: &str) -> Result<usize, String> {
+    match u8::from_str_radix(text, 16) {
+        Ok(key) if key <= 0xF => Ok(key as usize),
+        _ => Err("the CHIP-8 key must be a hex digit 0-F".to_string()),
+    }
+}
+
+// F1-F9 select save state slots 1-9.
+pub fn save_slot(virtual_keycode: VirtualKeyCode) -> Option<u8> {
+    match virtual_keycode {
+        VirtualKeyCode::F1 => Some(1),
+        VirtualKeyCode::F2 => Some(2),
+        VirtualKeyCode::F3 => Some(3),
+        VirtualKeyCode::F4 => Some(4),
+        VirtualKeyCode::F5 => Some(5),
+        VirtualKeyCode::F6 => Some(6),
+        VirtualKeyCode::F7 => Some(7),
+        VirtualKeyCode::F8 => Some(8),
+        VirtualKeyCode::F9 => Some(9),
+        _ => None,
+    }
+}
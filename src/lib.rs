@@ -0,0 +1,44 @@
+// A CHIP-8 interpreter core with no windowing or audio dependencies.
+//
+// The `CPU` owns memory, registers, timers, the keypad and a 64x32 (or SUPER-CHIP 128x64)
+// framebuffer. A frontend feeds key presses in, calls `execute_instruction` and
+// `decrement_timers` at whatever rate it likes, and reads the framebuffer back out to draw it.
+//
+// The window feature adds what the binaries share: the command line, the subcommands that don't
+// open a window, the config file and the keyboard mapping, which is in terms of winit keys.
+
+pub mod assembler;
+pub mod audio;
+#[cfg(feature = "window")]
+pub mod cli;
+#[cfg(feature = "window")]
+pub mod commands;
+#[cfg(feature = "window")]
+pub mod config;
+pub mod cpu;
+pub mod database;
+pub mod debugger;
+pub mod disassembler;
+pub mod error;
+pub mod headless;
+pub mod instruction;
+#[cfg(feature = "window")]
+pub mod keyboard;
+pub mod movie;
+pub mod octo;
+pub mod opcode;
+pub mod palette;
+pub mod platform;
+pub mod quirks;
+pub mod random;
+pub mod savestate;
+pub mod scheduler;
+pub mod trace;
+pub mod tracediff;
+
+pub use cpu::{CPU, DISPLAY_HEIGHT, DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT, HIRES_DISPLAY_WIDTH};
+pub use error::{Chip8Error, RomError, StepOutcome};
+pub use instruction::Instruction;
+pub use opcode::Opcode;
+pub use platform::Platform;
+pub use quirks::{LoadStoreQuirk, Quirks};
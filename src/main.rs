@@ -1,60 +1,468 @@
-use crate::cpu::CPU;
-use crate::display::Display;
-use std::{env, fs};
-use winit::event::{ElementState, Event, VirtualKeyCode, WindowEvent};
+use crate::display::{Display, DEFAULT_SCALE};
+use chip8::audio::{AudioConfig, AudioSink, Beeper, NullSink, WavSink, DEFAULT_SAMPLE_RATE};
+use chip8::cli::{self, Cli, Command, RunArgs};
+use chip8::commands::{self, exit_with_error, read_movie, Machine};
+use chip8::config;
+use chip8::headless;
+use chip8::keyboard::{self, Hotkeys, Keymap, RebindStep, Rebinding};
+use chip8::movie::Movie;
+use chip8::scheduler::Scheduler;
+use chip8::{Chip8Error, StepOutcome, CPU};
+use clap::Parser;
+use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Instant;
+use winit::event::{ElementState, Event, ModifiersState, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 
-mod cpu;
 mod display;
-mod instruction;
-mod keyboard;
-
-const CYCLES_PER_FRAME: u8 = 14;
+#[cfg(feature = "audio")]
+mod sound;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let file_path = &args[1];
-    let cycles_per_frame = match args.get(2) {
-        Some(c) => c
-            .parse::<u8>()
-            .expect("Unable to convert cycles arg to a u8"),
-        None => CYCLES_PER_FRAME,
+    let cli = Cli::parse_from(cli::with_default_command(env::args_os().collect()));
+    match cli.command {
+        Command::Run(args) => run(args),
+        Command::Headless(args) => commands::headless(args),
+        Command::Disasm(args) => commands::disasm(args),
+        Command::Asm(args) => commands::asm(args),
+        Command::Info(args) => commands::info(args),
+    }
+}
+
+fn run(args: RunArgs) {
+    let mut playing = args.play.as_deref().map(read_movie);
+    let rom_path = args.machine.rom.display().to_string();
+    // A different seed every time unless one is given
+    let Machine {
+        mut cpu,
+        instructions_per_second,
+        palette,
+        rom,
+        config,
+    } = commands::machine(&args.machine, rand::random, playing.as_ref());
+    if let Some(rom) = &rom {
+        if rom.authors.is_empty() {
+            println!("{}", rom.title);
+        } else {
+            println!("{} by {}", rom.title, rom.authors.join(", "));
+        }
+    }
+    // A keymap file replaces the database's key bindings, and --keymap the config file's too
+    let keymap_path = args
+        .keymap
+        .clone()
+        .or(config.keymap.clone())
+        .or_else(|| config::default_keymap_path().filter(|path| path.exists()));
+    let mut keymap = match &keymap_path {
+        Some(path) => read_keymap(path),
+        None => {
+            let mut keymap = Keymap::new(config.layout.unwrap_or_default());
+            if let Some(rom) = &rom {
+                keymap.bind_actions(&rom.keys);
+            }
+            keymap
+        }
+    };
+    if args.keymap.is_none() {
+        for (key, host_keys) in &config.keys {
+            keymap.bind(*key, host_keys);
+        }
+    }
+    // Where the rebinding screen saves to
+    let keymap_path = keymap_path.or_else(config::default_keymap_path);
+    let mut rebinding: Option<Rebinding> = None;
+    let defaults = Hotkeys::default();
+    let hotkeys = Hotkeys {
+        quit: config.hotkeys.quit.unwrap_or(defaults.quit),
+        pause: config.hotkeys.pause.unwrap_or(defaults.pause),
+        fullscreen: config.hotkeys.fullscreen.unwrap_or(defaults.fullscreen),
+        rebind: config.hotkeys.rebind.unwrap_or(defaults.rebind),
     };
-    let rom: Vec<u8> = fs::read(file_path).expect("Failed to read rom file");
-    let mut cpu = CPU::new();
 
-    cpu.load_rom(rom);
+    let defaults = AudioConfig::default();
+    let audio_config = AudioConfig {
+        frequency: args
+            .frequency
+            .or(config.audio.frequency)
+            .unwrap_or(defaults.frequency),
+        volume: args
+            .volume
+            .or(config.audio.volume)
+            .unwrap_or(defaults.volume),
+        waveform: args
+            .waveform
+            .or(config.audio.waveform)
+            .unwrap_or(defaults.waveform),
+    };
+    let mute = args.mute || config.audio.mute.unwrap_or(false);
+    let audio_sink = match (&args.machine.wav, mute) {
+        (Some(path), _) => wav_sink(path),
+        (None, true) => Box::new(NullSink),
+        (None, false) => {
+            let sound_options =
+                args.frequency.is_some() || args.volume.is_some() || args.waveform.is_some();
+            sound_card_sink(sound_options)
+        }
+    };
+    let mut beeper = Beeper::new(audio_config, audio_sink);
+    if let Some(movie) = &playing {
+        if movie.rom_hash != cpu.rom_hash() {
+            eprintln!("Warning: the movie was recorded with a different ROM");
+        }
+    }
+    // Started before anything runs so the recording has the initial state
+    let mut recording = args.record.as_ref().map(|_| {
+        Movie::new(&cpu, instructions_per_second).unwrap_or_else(|err| exit_with_error(&err))
+    });
 
     let event_loop = EventLoop::new();
-    let mut display = Display::new(&event_loop);
+    let mut display = Display::new(
+        &event_loop,
+        args.scale.or(config.scale).unwrap_or(DEFAULT_SCALE),
+        args.fullscreen || config.fullscreen.unwrap_or(false),
+        palette,
+    );
+
+    let mut scheduler = Scheduler::new(instructions_per_second, Instant::now());
+
+    // Set when the CPU faults. Execution stops but the window stays open so the final
+    // screen can be inspected alongside the state dump printed to stderr.
+    let mut halted = false;
+    let mut paused = args.paused;
+    if paused {
+        display.window.set_title("CHIP-8 - paused");
+    }
+    let mut modifiers = ModifiersState::empty();
+    // Ticks run and instructions executed, for movies
+    let mut frame: u64 = 0;
+    let mut cycles: u64 = 0;
 
     event_loop.run(move |event, _, control_flow| {
         match event {
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                WindowEvent::KeyboardInput { input, .. } => match input.virtual_keycode {
-                    Some(VirtualKeyCode::Escape) => *control_flow = ControlFlow::Exit,
-                    virtual_keycode => {
-                        if let Some(key_index) = keyboard::key_code_to_index(virtual_keycode) {
-                            if input.state == ElementState::Pressed {
-                                cpu.key_press(key_index);
+                WindowEvent::ModifiersChanged(state) => modifiers = state,
+                WindowEvent::Resized(size) => display.resize(size),
+                WindowEvent::KeyboardInput { input, .. } => {
+                    let virtual_keycode = input.virtual_keycode;
+                    let pressed = input.state == ElementState::Pressed;
+                    if let Some(screen) = &mut rebinding {
+                        if !pressed {
+                            return;
+                        }
+                        match screen.press(&input) {
+                            RebindStep::Continue => {
+                                display.window.set_title(&screen.prompt());
+                                display.window.request_redraw();
+                                return;
+                            }
+                            RebindStep::Done(rebound) => {
+                                keymap = rebound;
+                                if let Some(path) = &keymap_path {
+                                    save_keymap(&keymap, path);
+                                }
+                            }
+                            RebindStep::Cancelled => (),
+                        }
+                        rebinding = None;
+                        display
+                            .window
+                            .set_title(if paused { "CHIP-8 - paused" } else { "CHIP-8" });
+                        scheduler.reset(Instant::now());
+                        display.window.request_redraw();
+                    } else if virtual_keycode == Some(hotkeys.quit) {
+                        *control_flow = ControlFlow::Exit;
+                    } else if virtual_keycode == Some(hotkeys.pause) {
+                        if pressed {
+                            paused = !paused;
+                            if paused {
+                                display.window.set_title("CHIP-8 - paused");
                             } else {
-                                cpu.key_release(key_index);
+                                display.window.set_title("CHIP-8");
+                                // Don't try to catch up on the time spent paused
+                                scheduler.reset(Instant::now());
+                            }
+                        }
+                    } else if virtual_keycode == Some(hotkeys.fullscreen) {
+                        if pressed {
+                            display.toggle_fullscreen();
+                        }
+                    } else if virtual_keycode == Some(hotkeys.rebind) {
+                        if pressed {
+                            if recording.is_some() || playing.is_some() {
+                                eprintln!("Keys can't be rebound during a movie");
+                                return;
+                            }
+                            // Nothing stays held down while the keys change
+                            for key in 0..16 {
+                                cpu.key_release(key);
+                            }
+                            let screen = Rebinding::new(&keymap);
+                            display.window.set_title(&screen.prompt());
+                            rebinding = Some(screen);
+                            display.window.request_redraw();
+                        }
+                    } else if let Some(key_index) = keymap.key(&input) {
+                        // The keypad belongs to the movie until it finishes
+                        if playing.is_some() {
+                            return;
+                        }
+                        if let Some(movie) = &mut recording {
+                            if !halted {
+                                movie.record(frame, cycles, key_index, pressed);
+                            }
+                        }
+                        if pressed {
+                            cpu.key_press(key_index);
+                        } else {
+                            cpu.key_release(key_index);
+                        }
+                    } else if let Some(slot) = virtual_keycode.and_then(keyboard::save_slot) {
+                        // Shift+F1-F9 saves to a slot, F1-F9 loads from it
+                        if pressed {
+                            let path = save_state_path(&rom_path, slot);
+                            if modifiers.shift() {
+                                save_state(&cpu, &path);
+                            } else if recording.is_some() || playing.is_some() {
+                                eprintln!("Save states can't be loaded during a movie");
+                            } else if load_state(&mut cpu, &path) {
+                                halted = false;
+                                if !paused {
+                                    display.window.set_title("CHIP-8");
+                                }
+                                scheduler.reset(Instant::now());
+                                display.window.request_redraw();
                             }
                         }
                     }
-                },
+                }
                 _ => (),
             },
+            // All pending input has been handled, so run whatever 60 Hz ticks have come due
+            // since the last time round. Emulation speed only depends on the clock, not on how
+            // many events the window receives.
+            Event::MainEventsCleared => {
+                if halted || paused || rebinding.is_some() {
+                    control_flow.set_wait();
+                    return;
+                }
+
+                let catchup = scheduler.update(Instant::now());
+                for _ in 0..catchup.ticks {
+                    if let Some(movie) = &playing {
+                        if let Err(desync) = movie.apply(&mut cpu, frame, cycles) {
+                            eprintln!("Movie {}", desync);
+                        }
+                    }
+                    match run_tick(&mut cpu, &mut scheduler, &mut beeper, &mut cycles) {
+                        Ok(StepOutcome::Exited) => {
+                            control_flow.set_exit();
+                            return;
+                        }
+                        Ok(_) => frame += 1,
+                        Err(err) => {
+                            report_fault(&cpu, err);
+                            display
+                                .window
+                                .set_title(&format!("CHIP-8 - halted: {}", err));
+                            halted = true;
+                            break;
+                        }
+                    }
+                }
+
+                if let Some(movie) = &playing {
+                    if movie_finished(movie, frame, cycles) {
+                        report_playback(movie, &cpu, frame, cycles);
+                        playing = None;
+                    }
+                }
+
+                // Only the result of the last tick is drawn, skipping frames when behind
+                if catchup.ticks > 0 {
+                    display.window.request_redraw();
+                }
+                control_flow.set_wait_until(scheduler.next_tick_at());
+            }
+            Event::RedrawRequested(_) => match &rebinding {
+                Some(screen) => display.draw_keypad(screen.key(), screen.keymap()),
+                None => display.draw(&cpu),
+            },
+            Event::LoopDestroyed => {
+                if let Some(movie) = &playing {
+                    report_playback(movie, &cpu, frame, cycles);
+                }
+                if let (Some(movie), Some(path)) = (&mut recording, &args.record) {
+                    movie.finish(frame, cycles, &cpu);
+                    match fs::write(path, movie.to_string()) {
+                        Ok(()) => println!("Saved movie to {}", path.display()),
+                        Err(err) => eprintln!("Unable to save {}: {}", path.display(), err),
+                    }
+                }
+                if let Err(err) = beeper.finish() {
+                    eprintln!("Failed to finish audio output: {}", err);
+                }
+                if let Some(mut tracer) = cpu.take_tracer() {
+                    if let Err(err) = tracer.finish() {
+                        eprintln!("Failed to write trace: {}", err);
+                    }
+                }
+            }
             _ => (),
         };
+    })
+}
+
+// Save slots are kept next to the ROM, e.g. pong.ch8.state1
+fn save_state_path(rom_path: &str, slot: u8) -> String {
+    format!("{}.state{}", rom_path, slot)
+}
+
+fn save_state(cpu: &CPU, path: &str) {
+    match fs::write(path, cpu.save_state()) {
+        Ok(()) => println!("Saved state to {}", path),
+        Err(err) => eprintln!("Unable to save {}: {}", path, err),
+    }
+}
+
+// Returns true if the state was loaded.
+fn load_state(cpu: &mut CPU, path: &str) -> bool {
+    let result = fs::read(path)
+        .map_err(|err| err.to_string())
+        .and_then(|state| cpu.load_state(&state).map_err(|err| err.to_string()));
+    match result {
+        Ok(()) => {
+            println!("Loaded state from {}", path);
+            true
+        }
+        Err(err) => {
+            eprintln!("Unable to load {}: {}", path, err);
+            false
+        }
+    }
+}
+
+fn read_keymap(path: &Path) -> Keymap {
+    fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|text| text.parse::<Keymap>())
+        .unwrap_or_else(|err| {
+            exit_with_error(&format!("Invalid keymap {}: {}", path.display(), err))
+        })
+}
+
+fn save_keymap(keymap: &Keymap, path: &Path) {
+    let result = match path.parent() {
+        Some(dir) => fs::create_dir_all(dir),
+        None => Ok(()),
+    }
+    .and_then(|()| fs::write(path, keymap.to_string()));
+    match result {
+        Ok(()) => println!("Saved keymap to {}", path.display()),
+        Err(err) => eprintln!("Unable to save {}: {}", path.display(), err),
+    }
+}
+
+// Whether the movie has been played up to where the recording stopped, or past its last key
+// event if it wasn't finished.
+fn movie_finished(movie: &Movie, frame: u64, cycles: u64) -> bool {
+    match (&movie.end, movie.events.last()) {
+        (Some(end), _) => cycles >= end.cycle,
+        (None, Some(event)) => frame > event.frame,
+        (None, None) => true,
+    }
+}
+
+fn report_playback(movie: &Movie, cpu: &CPU, frame: u64, cycles: u64) {
+    match movie.matches_end(cpu, frame, cycles) {
+        Some(true) => println!("Movie finished, playback matches the recording"),
+        Some(false) => println!("Movie finished, playback doesn't match the recording"),
+        None => println!("Movie finished"),
+    }
+}
 
-        for _ in 0..cycles_per_frame {
-            cpu.execute_instruction();
+// Runs one 60 Hz tick: the instructions for this tick, then the timers and the buzzer. Stops
+// early if the program exits. `cycles` counts the instructions executed.
+fn run_tick(
+    cpu: &mut CPU,
+    scheduler: &mut Scheduler,
+    beeper: &mut Beeper,
+    cycles: &mut u64,
+) -> Result<StepOutcome, Chip8Error> {
+    for _ in 0..scheduler.cycles_for_next_tick() {
+        *cycles += 1;
+        if cpu.execute_instruction()? == StepOutcome::Exited {
+            return Ok(StepOutcome::Exited);
         }
+    }
+
+    cpu.decrement_timers();
+    if let Err(err) = beeper.tick(cpu.tone()) {
+        eprintln!("Failed to write audio: {}", err);
+    }
+    Ok(StepOutcome::Executed)
+}
 
-        cpu.decrement_timers();
+fn wav_sink(path: &Path) -> Box<dyn AudioSink> {
+    let sink =
+        File::create(path).and_then(|file| WavSink::new(BufWriter::new(file), DEFAULT_SAMPLE_RATE));
+    match sink {
+        Ok(sink) => Box::new(sink),
+        Err(err) => exit_with_error(&format!("Unable to create {}: {}", path.display(), err)),
+    }
+}
+
+#[cfg(feature = "audio")]
+fn sound_card_sink(_sound_options: bool) -> Box<dyn AudioSink> {
+    match sound::CpalSink::new() {
+        Ok(sink) => Box::new(sink),
+        Err(err) => {
+            eprintln!("Audio disabled: {}", err);
+            Box::new(NullSink)
+        }
+    }
+}
 
-        display.draw(&cpu);
+// Built without the `audio` feature, so there is no sound card output. Says so straight away if
+// sound options were given, otherwise the first time the program beeps.
+#[cfg(not(feature = "audio"))]
+fn sound_card_sink(sound_options: bool) -> Box<dyn AudioSink> {
+    if sound_options {
+        eprintln!("{}", NO_SOUND_CARD);
+    }
+    Box::new(NoSoundCard {
+        warned: sound_options,
     })
 }
+
+#[cfg(not(feature = "audio"))]
+const NO_SOUND_CARD: &str = "Warning: built without the audio feature, so there is no sound. \
+     Rebuild with --features audio, or write it to a file with --wav";
+
+#[cfg(not(feature = "audio"))]
+struct NoSoundCard {
+    warned: bool,
+}
+
+#[cfg(not(feature = "audio"))]
+impl AudioSink for NoSoundCard {
+    fn sample_rate(&self) -> u32 {
+        DEFAULT_SAMPLE_RATE
+    }
+
+    fn write(&mut self, samples: &[f32]) -> std::io::Result<()> {
+        if !self.warned && samples.iter().any(|&sample| sample != 0.0) {
+            eprintln!("{}", NO_SOUND_CARD);
+            self.warned = true;
+        }
+        Ok(())
+    }
+}
+
+fn report_fault(cpu: &CPU, err: Chip8Error) {
+    eprintln!("CPU halted: {}", err);
+    eprint!("{}", headless::registers_dump(cpu));
+}
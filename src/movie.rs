@@ -0,0 +1,390 @@
+// Input recordings ("movies"): every keypad press and release of a session together with
+// everything else that decides how the program runs (ROM, platform, quirks, random number
+// generator and speed), so the session can be played back exactly.
+//
+// Movies are text, one item per line, so they can be attached to bug reports and diffed:
+//
+//     # CHIP-8 movie
+//     version 1
+//     rom 0f4ba7d3b4ad9a8b3b3d8f8c1b4a5e6c7d8e9f00
+//     platform vip
+//     quirks shift=0 jump=0 load_store=x+1 vf_reset=1 clipping=1 display_wait=1
+//     random seeded 1234
+//     ips 840
+//     key 120 1680 press 5
+//     key 130 1820 release 5
+//     end 600 8400 9c1185a5c5e9fc54612808977ee8f548b2258d31
+//
+// Each key line has the frame (60 Hz timer tick) the event happened before, the number of
+// instructions run by then, the action and the key. The frontends only handle input between
+// ticks, so playback applies events at the start of their frame and uses the instruction count to
+// spot a desync. The end line has the length of the recording and a SHA-1 of the complete
+// machine state at the end, for checking that a playback finished the same way. ROMs loaded
+// somewhere other than 0x200 also have a `load <addr>` line. Blank lines and anything after a `#`
+// are ignored.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::cpu::{CPU, DEFAULT_LOAD_ADDRESS};
+use crate::platform::{Platform, UnknownPlatform};
+use crate::quirks::{LoadStoreQuirk, Quirks};
+use crate::random::RandomMode;
+
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovieEvent {
+    pub frame: u64,
+    pub cycle: u64,
+    pub key: usize,
+    pub pressed: bool,
+}
+
+// The first point where a playback didn't line up with its recording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Desync {
+    pub frame: u64,
+    // Instructions run before the frame, in the recording and in the playback
+    pub recorded_cycle: u64,
+    pub cycle: u64,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "desynced at frame {}: recorded after {} instructions, played after {}",
+            self.frame, self.recorded_cycle, self.cycle
+        )
+    }
+}
+
+// Where the recording stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovieEnd {
+    pub frame: u64,
+    pub cycle: u64,
+    // state_hash at the end
+    pub state_hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: [u8; 20],
+    pub load_address: u16,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub random_mode: RandomMode,
+    pub seed: u64,
+    pub instructions_per_second: u32,
+    // In the order they happened
+    pub events: Vec<MovieEvent>,
+    // None until the recording is finished
+    pub end: Option<MovieEnd>,
+}
+
+impl Movie {
+    // Starts a recording of `cpu`, which should have its ROM loaded and not have run yet.
+    pub fn new(cpu: &CPU, instructions_per_second: u32) -> Result<Movie, String> {
+        let random_mode = cpu.random_mode().ok_or_else(|| {
+            "movies can only be recorded with a built-in random number generator".to_string()
+        })?;
+        Ok(Movie {
+            rom_hash: cpu.rom_hash(),
+            // Nothing has run, so the program starts where it was loaded
+            load_address: cpu.pc(),
+            platform: cpu.platform(),
+            quirks: *cpu.quirks(),
+            random_mode,
+            seed: cpu.random_seed(),
+            instructions_per_second,
+            events: Vec::new(),
+            end: None,
+        })
+    }
+
+    pub fn record(&mut self, frame: u64, cycle: u64, key: usize, pressed: bool) {
+        self.events.push(MovieEvent {
+            frame,
+            cycle,
+            key,
+            pressed,
+        });
+    }
+
+    pub fn finish(&mut self, frame: u64, cycle: u64, cpu: &CPU) {
+        self.end = Some(MovieEnd {
+            frame,
+            cycle,
+            state_hash: state_hash(cpu),
+        });
+    }
+
+    // A CPU set up the way the recording's was, ready for the ROM to be loaded at `load_address`.
+    pub fn cpu(&self) -> CPU {
+        let mut cpu = CPU::with_platform(self.platform, self.quirks);
+        cpu.set_random(self.random_mode, self.seed);
+        cpu
+    }
+
+    // The events that happened before `frame`.
+    pub fn events_at(&self, frame: u64) -> impl Iterator<Item = &MovieEvent> {
+        let start = self.events.partition_point(|event| event.frame < frame);
+        self.events[start..]
+            .iter()
+            .take_while(move |event| event.frame == frame)
+    }
+
+    // Presses and releases the keys for `frame`, called before running it with the number of
+    // instructions run so far. Fails if the recording had run a different number by then.
+    pub fn apply(&self, cpu: &mut CPU, frame: u64, cycle: u64) -> Result<(), Desync> {
+        let mut result = Ok(());
+        for event in self.events_at(frame) {
+            if event.cycle != cycle && result.is_ok() {
+                result = Err(Desync {
+                    frame,
+                    recorded_cycle: event.cycle,
+                    cycle,
+                });
+            }
+            if event.pressed {
+                cpu.key_press(event.key);
+            } else {
+                cpu.key_release(event.key);
+            }
+        }
+        result
+    }
+
+    // Whether `cpu` finished in the same state as the recording, if the recording has an end and
+    // the playback stopped at the same point.
+    pub fn matches_end(&self, cpu: &CPU, frame: u64, cycle: u64) -> Option<bool> {
+        let end = self.end.as_ref()?;
+        (end.frame == frame && end.cycle == cycle).then(|| state_hash(cpu) == end.state_hash)
+    }
+}
+
+// A SHA-1 of the complete machine state, as saved in save states.
+pub fn state_hash(cpu: &CPU) -> String {
+    sha1_smol::Sha1::from(cpu.save_state()).digest().to_string()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn load_store_name(quirk: LoadStoreQuirk) -> &'static str {
+    match quirk {
+        LoadStoreQuirk::Unchanged => "unchanged",
+        LoadStoreQuirk::IncrementByX => "x",
+        LoadStoreQuirk::IncrementByXPlusOne => "x+1",
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let quirks = &self.quirks;
+        writeln!(f, "# CHIP-8 movie")?;
+        writeln!(f, "version {}", VERSION)?;
+        writeln!(f, "rom {}", hex(&self.rom_hash))?;
+        if self.load_address != DEFAULT_LOAD_ADDRESS {
+            writeln!(f, "load {:#05X}", self.load_address)?;
+        }
+        writeln!(f, "platform {}", self.platform)?;
+        writeln!(
+            f,
+            "quirks shift={} jump={} load_store={} vf_reset={} clipping={} display_wait={}",
+            quirks.shift as u8,
+            quirks.jump as u8,
+            load_store_name(quirks.load_store),
+            quirks.vf_reset as u8,
+            quirks.clipping as u8,
+            quirks.display_wait as u8
+        )?;
+        writeln!(f, "random {} {}", self.random_mode, self.seed)?;
+        writeln!(f, "ips {}", self.instructions_per_second)?;
+        for event in &self.events {
+            let action = if event.pressed { "press" } else { "release" };
+            writeln!(
+                f,
+                "key {} {} {} {:X}",
+                event.frame, event.cycle, action, event.key
+            )?;
+        }
+        if let Some(end) = &self.end {
+            writeln!(f, "end {} {} {}", end.frame, end.cycle, end.state_hash)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Movie {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut version = None;
+        let mut rom_hash = None;
+        let mut load_address = DEFAULT_LOAD_ADDRESS;
+        let mut platform = None;
+        let mut quirks = None;
+        let mut random = None;
+        let mut instructions_per_second = None;
+        let mut events = Vec::new();
+        let mut end = None;
+
+        for (index, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", index + 1, message);
+            let number = |value: &str| {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| error(format!("'{}' is not a number", value)))
+            };
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                ["version", value] => {
+                    let value = number(value)?;
+                    if value != VERSION as u64 {
+                        return Err(error(format!("unsupported movie version {}", value)));
+                    }
+                    version = Some(value);
+                }
+                ["rom", value] => {
+                    rom_hash =
+                        Some(parse_hash(value).ok_or_else(|| {
+                            error("the ROM hash must be 40 hex digits".to_string())
+                        })?)
+                }
+                ["load", value] => {
+                    let digits = value.trim_start_matches("0x").trim_start_matches("0X");
+                    load_address = u16::from_str_radix(digits, 16)
+                        .map_err(|_| error(format!("'{}' is not a hex address", value)))?;
+                }
+                ["platform", value] => {
+                    platform = Some(
+                        value
+                            .parse()
+                            .map_err(|e: UnknownPlatform| error(e.to_string()))?,
+                    )
+                }
+                ["quirks", ref values @ ..] => quirks = Some(parse_quirks(values).map_err(error)?),
+                ["random", mode, seed] => {
+                    random = Some((mode.parse().map_err(error)?, number(seed)?))
+                }
+                ["ips", value] => {
+                    instructions_per_second = Some(
+                        value
+                            .parse()
+                            .map_err(|_| error(format!("'{}' is not a number", value)))?,
+                    )
+                }
+                ["key", frame, cycle, action, key] => {
+                    let pressed = match action {
+                        "press" => true,
+                        "release" => false,
+                        _ => return Err(error("action must be press or release".to_string())),
+                    };
+                    let key = match u8::from_str_radix(key, 16) {
+                        Ok(key) if key <= 0xF => key as usize,
+                        _ => return Err(error("key must be a hex digit 0-F".to_string())),
+                    };
+                    events.push(MovieEvent {
+                        frame: number(frame)?,
+                        cycle: number(cycle)?,
+                        key,
+                        pressed,
+                    });
+                }
+                ["end", frame, cycle, hash] => {
+                    end = Some(MovieEnd {
+                        frame: number(frame)?,
+                        cycle: number(cycle)?,
+                        state_hash: hash.to_string(),
+                    })
+                }
+                _ => return Err(error(format!("unrecognised line '{}'", line))),
+            }
+        }
+
+        // Playback looks events up by frame, so they have to be in order. Stable, so a press and
+        // release in the same frame keep theirs.
+        events.sort_by_key(|event| (event.frame, event.cycle));
+
+        let missing = |field: &str| format!("the movie has no {} line", field);
+        version.ok_or_else(|| missing("version"))?;
+        let (random_mode, seed) = random.ok_or_else(|| missing("random"))?;
+        Ok(Movie {
+            rom_hash: rom_hash.ok_or_else(|| missing("rom"))?,
+            load_address,
+            platform: platform.ok_or_else(|| missing("platform"))?,
+            quirks: quirks.ok_or_else(|| missing("quirks"))?,
+            random_mode,
+            seed,
+            instructions_per_second: instructions_per_second.ok_or_else(|| missing("ips"))?,
+            events,
+            end,
+        })
+    }
+}
+
+fn parse_hash(value: &str) -> Option<[u8; 20]> {
+    if value.len() != 40 {
+        return None;
+    }
+    let mut hash = [0; 20];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(value.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+// `name=value` pairs for every quirk.
+fn parse_quirks(values: &[&str]) -> Result<Quirks, String> {
+    let mut quirks = Quirks::default();
+    let mut seen = Vec::new();
+    for pair in values {
+        let (name, value) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("expected name=value, found '{}'", pair))?;
+        let flag = || match value {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            _ => Err(format!("{} must be 0 or 1", name)),
+        };
+        match name {
+            "shift" => quirks.shift = flag()?,
+            "jump" => quirks.jump = flag()?,
+            "vf_reset" => quirks.vf_reset = flag()?,
+            "clipping" => quirks.clipping = flag()?,
+            "display_wait" => quirks.display_wait = flag()?,
+            "load_store" => {
+                quirks.load_store = match value {
+                    "unchanged" => LoadStoreQuirk::Unchanged,
+                    "x" => LoadStoreQuirk::IncrementByX,
+                    "x+1" => LoadStoreQuirk::IncrementByXPlusOne,
+                    _ => return Err("load_store must be unchanged, x or x+1".to_string()),
+                }
+            }
+            _ => return Err(format!("unknown quirk '{}'", name)),
+        }
+        seen.push(name);
+    }
+    for name in [
+        "shift",
+        "jump",
+        "load_store",
+        "vf_reset",
+        "clipping",
+        "display_wait",
+    ] {
+        if !seen.contains(&name) {
+            return Err(format!("the quirks line has no {}", name));
+        }
+    }
+    Ok(quirks)
+}
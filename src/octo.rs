@@ -0,0 +1,1197 @@
+// A compiler for Octo, the high level CHIP-8 assembly language used by the Octo IDE and most
+// modern homebrew. Reference: https://github.com/JohnEarnest/Octo/blob/gh-pages/docs/Manual.md
+//
+// Supported: labels and `main`, `:const`, `:alias`, `:unpack`, `:next`, `:org`, `:byte`,
+// `:pointer`, `:call`, `:macro`, `:calc`, `:assert`, every CHIP-8, SUPER-CHIP and XO-CHIP
+// statement, `if ... then`, `if ... begin ... else ... end`, `loop ... while ... again` and bare
+// numbers as sprite data. `:stringmode` isn't supported, and `:breakpoint` and `:monitor` are
+// accepted but ignored.
+//
+// Instructions are built as `Opcode`s and checked against the target platform's decoder, so a
+// program that compiles only uses instructions the `CPU` will run on that platform.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use crate::assembler::{AsmError, LOAD_ADDRESS};
+use crate::instruction::Instruction;
+use crate::opcode::Opcode;
+use crate::platform::Platform;
+
+// Stops `:macro a a` from expanding forever.
+const MAX_MACRO_EXPANSIONS: usize = 100_000;
+const VF: usize = 0xF;
+
+// Whether `path` looks like Octo source rather than a ROM.
+pub fn is_source(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "8o")
+}
+
+// Compiles Octo source into a ROM to be loaded at 0x200.
+pub fn compile(source: &str, platform: Platform) -> Result<Vec<u8>, AsmError> {
+    Compiler::new("<input>", source, platform).run()
+}
+
+pub fn compile_file(path: &Path, platform: Platform) -> Result<Vec<u8>, AsmError> {
+    let source = fs::read_to_string(path).map_err(|err| read_error(path, err))?;
+    Compiler::new(&path.display().to_string(), &source, platform).run()
+}
+
+// Reads a ROM for `platform`, compiling it first if it's Octo source.
+pub fn read_program(path: &Path, platform: Platform) -> Result<Vec<u8>, AsmError> {
+    if is_source(path) {
+        compile_file(path, platform)
+    } else {
+        fs::read(path).map_err(|err| read_error(path, err))
+    }
+}
+
+fn read_error(path: &Path, err: std::io::Error) -> AsmError {
+    AsmError {
+        file: path.display().to_string(),
+        line: 0,
+        column: 0,
+        message: err.to_string(),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+// Splits on whitespace, dropping `#` comments and keeping "quoted strings" whole.
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (index, line) in source.lines().enumerate() {
+        let mut chars = line.char_indices().peekable();
+        while let Some(&(start, c)) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            if c == '#' {
+                break;
+            }
+
+            let mut end = line.len();
+            if c == '"' {
+                chars.next();
+                for (i, c) in chars.by_ref() {
+                    if c == '"' {
+                        end = i + 1;
+                        break;
+                    }
+                }
+            } else {
+                while let Some(&(i, c)) = chars.peek() {
+                    if c.is_whitespace() {
+                        end = i;
+                        break;
+                    }
+                    chars.next();
+                }
+            }
+            tokens.push_back(Token {
+                text: line[start..end].to_string(),
+                line: index + 1,
+                column: start + 1,
+            });
+        }
+    }
+    tokens
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+// Where a forward reference to a label gets patched once the label is defined.
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    // The low 12 bits of the instruction word
+    Address,
+    // A whole 16-bit word
+    Word,
+    // `:unpack`: the high byte is the nibble then the top 4 bits of the address
+    UnpackHigh(u8),
+    UnpackLow,
+}
+
+struct Fixup {
+    addr: usize,
+    kind: FixupKind,
+    label: String,
+    token: Token,
+}
+
+// A value that might be a label that hasn't been defined yet.
+enum Value {
+    Number(f64),
+    Forward(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Key,
+    NotKey,
+}
+
+impl Comparison {
+    fn negate(self) -> Comparison {
+        match self {
+            Comparison::Equal => Comparison::NotEqual,
+            Comparison::NotEqual => Comparison::Equal,
+            Comparison::Less => Comparison::GreaterEqual,
+            Comparison::GreaterEqual => Comparison::Less,
+            Comparison::Greater => Comparison::LessEqual,
+            Comparison::LessEqual => Comparison::Greater,
+            Comparison::Key => Comparison::NotKey,
+            Comparison::NotKey => Comparison::Key,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Register(usize),
+    Number(u8),
+}
+
+struct Condition {
+    x: usize,
+    comparison: Comparison,
+    // None for key tests
+    operand: Option<Operand>,
+}
+
+enum Block {
+    // `if ... begin`, with the address of the jump to patch at `else` or `end`
+    If {
+        jump: usize,
+        token: Token,
+    },
+    // `loop`, with the start address and the `while` jumps out of the loop
+    Loop {
+        start: u16,
+        exits: Vec<usize>,
+        token: Token,
+    },
+}
+
+struct Compiler {
+    file: String,
+    platform: Platform,
+    tokens: VecDeque<Token>,
+    // The most recently read token, for errors at the end of the file
+    last: Token,
+    // ROM contents from LOAD_ADDRESS, and which bytes have been written
+    rom: Vec<u8>,
+    written: Vec<bool>,
+    here: usize,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, usize>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    expansions: usize,
+}
+
+impl Compiler {
+    fn new(file: &str, source: &str, platform: Platform) -> Compiler {
+        Compiler {
+            file: file.to_string(),
+            platform,
+            tokens: tokenize(source),
+            last: Token {
+                text: String::new(),
+                line: 1,
+                column: 1,
+            },
+            rom: Vec::new(),
+            written: Vec::new(),
+            here: LOAD_ADDRESS as usize,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            expansions: 0,
+        }
+    }
+
+    fn error(&self, token: &Token, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: token.line,
+            column: token.column,
+            message: message.into(),
+        }
+    }
+
+    fn run(mut self) -> Result<Vec<u8>, AsmError> {
+        // Execution starts at `main`. Unless the program starts with it, the first instruction
+        // is a jump there.
+        let starts_with_main = matches!(
+            (self.tokens.front(), self.tokens.get(1)),
+            (Some(colon), Some(name)) if colon.text == ":" && name.text == "main"
+        );
+        if !starts_with_main {
+            let token = self.tokens.front().cloned().unwrap_or(self.last.clone());
+            self.fixup(FixupKind::Address, "main", 0, &token);
+            self.emit(Opcode::Jp(0), &token)?;
+        }
+
+        while let Some(token) = self.tokens.pop_front() {
+            self.last = token.clone();
+            self.statement(token)?;
+        }
+
+        if let Some(block) = self.blocks.last() {
+            let (Block::If { token, .. } | Block::Loop { token, .. }) = block;
+            let message = match block {
+                Block::If { .. } => "'begin' without a matching 'end'",
+                Block::Loop { .. } => "'loop' without a matching 'again'",
+            };
+            return Err(self.error(token, message));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(&addr) = self.labels.get(&fixup.label) else {
+                let message = if fixup.label == "main" {
+                    "this program is missing a 'main' label".to_string()
+                } else {
+                    format!("'{}' is not defined", fixup.label)
+                };
+                return Err(self.error(&fixup.token, message));
+            };
+            let index = fixup.addr - LOAD_ADDRESS as usize;
+            match fixup.kind {
+                FixupKind::Address => {
+                    if addr > 0xFFF {
+                        return Err(self.error(
+                            &fixup.token,
+                            format!("'{}' is above 0xFFF, use 'i := long'", fixup.label),
+                        ));
+                    }
+                    self.rom[index] |= (addr >> 8) as u8;
+                    self.rom[index + 1] = addr as u8;
+                }
+                FixupKind::Word => self.rom[index..index + 2].copy_from_slice(&addr.to_be_bytes()),
+                FixupKind::UnpackHigh(nibble) => {
+                    self.rom[index] = nibble << 4 | (addr >> 8) as u8 & 0xF;
+                }
+                FixupKind::UnpackLow => self.rom[index] = addr as u8,
+            }
+        }
+
+        let end = self
+            .written
+            .iter()
+            .rposition(|&written| written)
+            .map_or(0, |i| i + 1);
+        self.rom.truncate(end);
+        Ok(self.rom)
+    }
+
+    fn next(&mut self) -> Result<Token, AsmError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.last = token.clone();
+                Ok(token)
+            }
+            None => Err(self.error(&self.last, "unexpected end of file")),
+        }
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.front().is_some_and(|token| token.text == text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, AsmError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(self.error(
+                &token,
+                format!("expected '{}', found '{}'", text, token.text),
+            ));
+        }
+        Ok(token)
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AsmError> {
+        let plain = |opcode: Opcode| Some(opcode);
+        let simple = match token.text.as_str() {
+            "return" | ";" => plain(Opcode::Ret),
+            "clear" => plain(Opcode::Cls),
+            "hires" => plain(Opcode::High),
+            "lores" => plain(Opcode::Low),
+            "exit" => plain(Opcode::Exit),
+            "scroll-left" => plain(Opcode::ScrollLeft),
+            "scroll-right" => plain(Opcode::ScrollRight),
+            "audio" => plain(Opcode::Audio),
+            _ => None,
+        };
+        if let Some(opcode) = simple {
+            return self.emit(opcode, &token);
+        }
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                self.define_label(&name, self.here, &token)
+            }
+            ":next" => {
+                let name = self.name()?;
+                self.define_label(&name, self.here + 1, &token)
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.number()?;
+                self.define_constant(&name, value, &token)
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.braced_expression()?;
+                self.define_constant(&name, value, &token)
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = if self.peek_is("{") {
+                    let value = self.braced_expression()?;
+                    if !(0.0..16.0).contains(&value) {
+                        return Err(self.error(&token, "alias must be a register 0-15"));
+                    }
+                    value as usize
+                } else {
+                    self.register()?
+                };
+                self.aliases.insert(name, register);
+                Ok(())
+            }
+            ":unpack" => {
+                let nibble_token = self.next()?;
+                let nibble = if nibble_token.text == "long" {
+                    0
+                } else {
+                    self.tokens.push_front(nibble_token.clone());
+                    self.nibble()?
+                };
+                let value = self.value()?;
+                let (high, low) = match value {
+                    Value::Number(addr) => {
+                        let addr = addr as i64 as u16;
+                        (nibble << 4 | (addr >> 8) as u8 & 0xF, addr as u8)
+                    }
+                    Value::Forward(label) => {
+                        // The second bytes of the two instructions
+                        self.fixup(FixupKind::UnpackHigh(nibble), &label, 1, &token);
+                        self.fixup(FixupKind::UnpackLow, &label, 3, &token);
+                        (0, 0)
+                    }
+                };
+                // v0 := high, v1 := low
+                self.emit(Opcode::LdByte { x: 0, nn: high }, &token)?;
+                self.emit(Opcode::LdByte { x: 1, nn: low }, &token)
+            }
+            ":org" => {
+                let addr = self.number()?;
+                if addr < LOAD_ADDRESS as f64 || addr >= self.platform.memory_size() as f64 {
+                    return Err(self.error(&token, format!("can't :org to {}", addr)));
+                }
+                self.here = addr as usize;
+                Ok(())
+            }
+            ":byte" => {
+                let value = self.byte()?;
+                self.write(&[value], &token)
+            }
+            ":pointer" => match self.value()? {
+                Value::Number(addr) => {
+                    let addr = self.fit(addr, 0, 0xFFFF, &token)? as u16;
+                    self.write(&addr.to_be_bytes(), &token)
+                }
+                Value::Forward(label) => {
+                    self.fixup(FixupKind::Word, &label, 0, &token);
+                    self.write(&[0, 0], &token)
+                }
+            },
+            ":call" => {
+                let addr = self.address(&token)?;
+                self.emit(Opcode::Call(addr), &token)
+            }
+            ":macro" => self.define_macro(&token),
+            ":assert" => {
+                let message = if self.tokens.front().is_some_and(|t| t.text.starts_with('"')) {
+                    self.next()?.text.trim_matches('"').to_string()
+                } else {
+                    "assertion failed".to_string()
+                };
+                if self.braced_expression()? == 0.0 {
+                    return Err(self.error(&token, message));
+                }
+                Ok(())
+            }
+            ":breakpoint" => self.name().map(|_| ()),
+            ":monitor" => {
+                self.next()?;
+                self.next().map(|_| ())
+            }
+            ":stringmode" => Err(self.error(&token, "':stringmode' isn't supported")),
+
+            "bcd" => self.register_statement(Opcode::LdB, &token),
+            "saveflags" => self.register_statement(Opcode::LdRVx, &token),
+            "loadflags" => self.register_statement(Opcode::LdVxR, &token),
+            "save" | "load" => {
+                let x = self.register()?;
+                let opcode = if self.peek_is("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    if token.text == "save" {
+                        Opcode::SaveRange { x, y }
+                    } else {
+                        Opcode::LoadRange { x, y }
+                    }
+                } else if token.text == "save" {
+                    Opcode::LdIVx(x)
+                } else {
+                    Opcode::LdVxI(x)
+                };
+                self.emit(opcode, &token)
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(Opcode::Drw { x, y, n }, &token)
+            }
+            "jump" => {
+                let addr = self.address(&token)?;
+                self.emit(Opcode::Jp(addr), &token)
+            }
+            "jump0" => {
+                let addr = self.address(&token)?;
+                self.emit(Opcode::JpV0(addr), &token)
+            }
+            "native" => {
+                let addr = self.address(&token)?;
+                self.emit(Opcode::Sys(addr), &token)
+            }
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(Opcode::ScrollDown(n), &token)
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(Opcode::ScrollUp(n), &token)
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(Opcode::Plane(n), &token)
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let opcode = match token.text.as_str() {
+                    "delay" => Opcode::LdDtVx(x),
+                    "buzzer" => Opcode::LdStVx(x),
+                    _ => Opcode::Pitch(x),
+                };
+                self.emit(opcode, &token)
+            }
+            "i" => self.index_statement(&token),
+
+            "if" => {
+                let condition = self.condition()?;
+                let keyword = self.next()?;
+                match keyword.text.as_str() {
+                    "then" => self.emit_condition(&condition, false, &token),
+                    "begin" => {
+                        self.emit_condition(&condition, true, &token)?;
+                        let jump = self.here;
+                        self.emit(Opcode::Jp(0), &token)?;
+                        self.blocks.push(Block::If { jump, token });
+                        Ok(())
+                    }
+                    _ => Err(self.error(&keyword, "expected 'then' or 'begin'")),
+                }
+            }
+            "else" => {
+                let Some(Block::If { jump, .. }) = self.blocks.last() else {
+                    return Err(self.error(&token, "'else' without 'if ... begin'"));
+                };
+                let previous = *jump;
+                let jump = self.here;
+                self.emit(Opcode::Jp(0), &token)?;
+                self.patch_jump(previous, self.here as u16);
+                self.blocks.pop();
+                self.blocks.push(Block::If { jump, token });
+                Ok(())
+            }
+            "end" => {
+                let Some(Block::If { jump, .. }) = self.blocks.last() else {
+                    return Err(self.error(&token, "'end' without 'if ... begin'"));
+                };
+                let jump = *jump;
+                self.patch_jump(jump, self.here as u16);
+                self.blocks.pop();
+                Ok(())
+            }
+            "loop" => {
+                self.blocks.push(Block::Loop {
+                    start: self.here as u16,
+                    exits: Vec::new(),
+                    token,
+                });
+                Ok(())
+            }
+            "while" => {
+                if !self
+                    .blocks
+                    .iter()
+                    .any(|block| matches!(block, Block::Loop { .. }))
+                {
+                    return Err(self.error(&token, "'while' outside a loop"));
+                }
+                let condition = self.condition()?;
+                self.emit_condition(&condition, true, &token)?;
+                let jump = self.here;
+                self.emit(Opcode::Jp(0), &token)?;
+                let Some(Block::Loop { exits, .. }) = self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|block| matches!(block, Block::Loop { .. }))
+                else {
+                    unreachable!();
+                };
+                exits.push(jump);
+                Ok(())
+            }
+            "again" => {
+                let Some(Block::Loop { start, .. }) = self.blocks.last() else {
+                    return Err(self.error(&token, "'again' without 'loop'"));
+                };
+                let start = *start;
+                self.emit(Opcode::Jp(start), &token)?;
+                let Some(Block::Loop { exits, .. }) = self.blocks.pop() else {
+                    unreachable!();
+                };
+                for exit in exits {
+                    self.patch_jump(exit, self.here as u16);
+                }
+                Ok(())
+            }
+
+            _ => {
+                if let Some(x) = self.register_of(&token.text) {
+                    return self.register_assignment(x, &token);
+                }
+                if self.macros.contains_key(&token.text) {
+                    return self.expand_macro(&token);
+                }
+                // A number (or constant) on its own is a byte of data, and a label on its own
+                // calls it.
+                if let Some(&addr) = self.labels.get(&token.text) {
+                    let addr = self.fit(addr as f64, 0, 0xFFF, &token)? as u16;
+                    return self.emit(Opcode::Call(addr), &token);
+                }
+                self.tokens.push_front(token.clone());
+                match self.value()? {
+                    Value::Number(value) => {
+                        let byte = self.fit(value, -128.0, 255.0, &token)? as u8;
+                        self.write(&[byte], &token)
+                    }
+                    Value::Forward(label) if is_name(&label) => {
+                        self.fixup(FixupKind::Address, &label, 0, &token);
+                        self.emit(Opcode::Call(0), &token)
+                    }
+                    Value::Forward(_) => {
+                        Err(self.error(&token, format!("unexpected '{}'", token.text)))
+                    }
+                }
+            }
+        }
+    }
+
+    fn register_statement(
+        &mut self,
+        opcode: fn(usize) -> Opcode,
+        token: &Token,
+    ) -> Result<(), AsmError> {
+        let x = self.register()?;
+        self.emit(opcode(x), token)
+    }
+
+    fn index_statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        let operator = self.next()?;
+        match operator.text.as_str() {
+            "+=" => {
+                let x = self.register()?;
+                self.emit(Opcode::AddI(x), token)
+            }
+            ":=" if self.peek_is("hex") => {
+                self.next()?;
+                let x = self.register()?;
+                self.emit(Opcode::LdF(x), token)
+            }
+            ":=" if self.peek_is("bighex") => {
+                self.next()?;
+                let x = self.register()?;
+                self.emit(Opcode::LdHf(x), token)
+            }
+            ":=" if self.peek_is("long") => {
+                self.next()?;
+                let addr = match self.value()? {
+                    Value::Number(addr) => self.fit(addr, 0, 0xFFFF, token)? as u16,
+                    Value::Forward(label) => {
+                        // The word after F000
+                        self.fixup(FixupKind::Word, &label, 2, token);
+                        0
+                    }
+                };
+                self.emit(Opcode::LdLongI, token)?;
+                self.write(&addr.to_be_bytes(), token)
+            }
+            ":=" => {
+                let addr = self.address(token)?;
+                self.emit(Opcode::LdI(addr), token)
+            }
+            _ => Err(self.error(&operator, "expected ':=' or '+=' after 'i'")),
+        }
+    }
+
+    fn register_assignment(&mut self, x: usize, token: &Token) -> Result<(), AsmError> {
+        let operator = self.next()?;
+        let operand = self.next()?;
+        let y = self.register_of(&operand.text);
+
+        let opcode = match (operator.text.as_str(), y) {
+            (":=", _) if operand.text == "delay" => Opcode::LdVxDt(x),
+            (":=", _) if operand.text == "key" => Opcode::LdVxK(x),
+            (":=", _) if operand.text == "random" => {
+                let nn = self.byte()?;
+                Opcode::Rnd { x, nn }
+            }
+            (":=", Some(y)) => Opcode::LdReg { x, y },
+            ("+=", Some(y)) => Opcode::AddReg { x, y },
+            ("-=", Some(y)) => Opcode::Sub { x, y },
+            ("=-", Some(y)) => Opcode::Subn { x, y },
+            ("|=", Some(y)) => Opcode::Or { x, y },
+            ("&=", Some(y)) => Opcode::And { x, y },
+            ("^=", Some(y)) => Opcode::Xor { x, y },
+            (">>=", Some(y)) => Opcode::Shr { x, y },
+            ("<<=", Some(y)) => Opcode::Shl { x, y },
+            (":=" | "+=" | "-=", None) => {
+                self.tokens.push_front(operand);
+                let nn = self.byte()?;
+                match operator.text.as_str() {
+                    ":=" => Opcode::LdByte { x, nn },
+                    "+=" => Opcode::AddByte { x, nn },
+                    _ => Opcode::AddByte {
+                        x,
+                        nn: nn.wrapping_neg(),
+                    },
+                }
+            }
+            _ => {
+                return Err(self.error(
+                    &operator,
+                    format!("can't use '{}' with '{}'", operator.text, operand.text),
+                ))
+            }
+        };
+        self.emit(opcode, token)
+    }
+
+    fn condition(&mut self) -> Result<Condition, AsmError> {
+        let x = self.register()?;
+        let operator = self.next()?;
+        let comparison = match operator.text.as_str() {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessEqual,
+            ">=" => Comparison::GreaterEqual,
+            "key" => Comparison::Key,
+            "-key" => Comparison::NotKey,
+            _ => {
+                return Err(self.error(&operator, format!("unknown comparison '{}'", operator.text)))
+            }
+        };
+        let operand = match comparison {
+            Comparison::Key | Comparison::NotKey => None,
+            _ => {
+                let token = self.next()?;
+                match self.register_of(&token.text) {
+                    Some(y) => Some(Operand::Register(y)),
+                    None => {
+                        self.tokens.push_front(token);
+                        Some(Operand::Number(self.byte()?))
+                    }
+                }
+            }
+        };
+        Ok(Condition {
+            x,
+            comparison,
+            operand,
+        })
+    }
+
+    // Emits code that skips the next instruction when the condition is false, or when it is true
+    // if `negate` is set.
+    fn emit_condition(
+        &mut self,
+        condition: &Condition,
+        negate: bool,
+        token: &Token,
+    ) -> Result<(), AsmError> {
+        let comparison = if negate {
+            condition.comparison.negate()
+        } else {
+            condition.comparison
+        };
+        let x = condition.x;
+        let opcode = match (comparison, condition.operand) {
+            (Comparison::Equal, Some(Operand::Number(nn))) => Opcode::SneByte { x, nn },
+            (Comparison::Equal, Some(Operand::Register(y))) => Opcode::SneReg { x, y },
+            (Comparison::NotEqual, Some(Operand::Number(nn))) => Opcode::SeByte { x, nn },
+            (Comparison::NotEqual, Some(Operand::Register(y))) => Opcode::SeReg { x, y },
+            (Comparison::Key, _) => Opcode::Sknp(x),
+            (Comparison::NotKey, _) => Opcode::Skp(x),
+            (_, Some(operand)) => {
+                // Work out a >= b into VF with a subtraction, then skip on VF
+                let x = Operand::Register(x);
+                let (a, b, skip_if) = match comparison {
+                    Comparison::GreaterEqual => (x, operand, 0),
+                    Comparison::Less => (x, operand, 1),
+                    Comparison::LessEqual => (operand, x, 0),
+                    _ => (operand, x, 1),
+                };
+                match (a, b) {
+                    (Operand::Register(a), Operand::Register(b)) => {
+                        self.emit(Opcode::LdReg { x: VF, y: a }, token)?;
+                        self.emit(Opcode::Sub { x: VF, y: b }, token)?;
+                    }
+                    (Operand::Register(a), Operand::Number(b)) => {
+                        self.emit(Opcode::LdByte { x: VF, nn: b }, token)?;
+                        self.emit(Opcode::Subn { x: VF, y: a }, token)?;
+                    }
+                    (Operand::Number(a), Operand::Register(b)) => {
+                        self.emit(Opcode::LdByte { x: VF, nn: a }, token)?;
+                        self.emit(Opcode::Sub { x: VF, y: b }, token)?;
+                    }
+                    (Operand::Number(_), Operand::Number(_)) => unreachable!(),
+                }
+                Opcode::SeByte { x: VF, nn: skip_if }
+            }
+            (_, None) => unreachable!(),
+        };
+        self.emit(opcode, token)
+    }
+
+    fn define_macro(&mut self, token: &Token) -> Result<(), AsmError> {
+        let name = self.name()?;
+        let mut params = Vec::new();
+        while !self.peek_is("{") {
+            params.push(self.name()?);
+        }
+        self.expect("{")?;
+
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let body_token = self.next()?;
+            match body_token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => (),
+            }
+            body.push(body_token);
+        }
+
+        if self
+            .macros
+            .insert(name.clone(), Macro { params, body })
+            .is_some()
+        {
+            return Err(self.error(token, format!("macro '{}' is already defined", name)));
+        }
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, token: &Token) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(self.error(token, "too many macro expansions, is a macro recursive?"));
+        }
+
+        let param_count = self.macros[&token.text].params.len();
+        let mut args = Vec::new();
+        for _ in 0..param_count {
+            args.push(self.next()?.text);
+        }
+
+        let definition = &self.macros[&token.text];
+        let expanded: Vec<Token> = definition
+            .body
+            .iter()
+            .map(|body_token| {
+                let text = match definition.params.iter().position(|p| *p == body_token.text) {
+                    Some(i) => args[i].clone(),
+                    None => body_token.text.clone(),
+                };
+                // Errors inside the expansion point at the invocation
+                Token {
+                    text,
+                    line: token.line,
+                    column: token.column,
+                }
+            })
+            .collect();
+        for expanded_token in expanded.into_iter().rev() {
+            self.tokens.push_front(expanded_token);
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<String, AsmError> {
+        let token = self.next()?;
+        if !is_name(&token.text) {
+            return Err(self.error(&token, format!("'{}' isn't a valid name", token.text)));
+        }
+        Ok(token.text)
+    }
+
+    fn define_label(&mut self, name: &str, addr: usize, token: &Token) -> Result<(), AsmError> {
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return Err(self.error(token, format!("'{}' is already defined", name)));
+        }
+        self.labels.insert(name.to_string(), addr as u16);
+        Ok(())
+    }
+
+    fn define_constant(&mut self, name: &str, value: f64, token: &Token) -> Result<(), AsmError> {
+        if self.labels.contains_key(name) {
+            return Err(self.error(token, format!("'{}' is already defined as a label", name)));
+        }
+        // :calc can redefine constants, like Octo
+        self.constants.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    fn register_of(&self, text: &str) -> Option<usize> {
+        if let Some(&register) = self.aliases.get(text) {
+            return Some(register);
+        }
+        let digit = text.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        usize::from_str_radix(digit, 16).ok()
+    }
+
+    fn register(&mut self) -> Result<usize, AsmError> {
+        let token = self.next()?;
+        self.register_of(&token.text).ok_or_else(|| {
+            self.error(
+                &token,
+                format!("expected a register, found '{}'", token.text),
+            )
+        })
+    }
+
+    // A number, constant, label or { expression }.
+    fn value(&mut self) -> Result<Value, AsmError> {
+        if self.peek_is("{") {
+            return self.braced_expression().map(Value::Number);
+        }
+        let token = self.next()?;
+        if let Some(value) = self.lookup(&token.text) {
+            return Ok(Value::Number(value));
+        }
+        match parse_number(&token.text) {
+            Some(value) => Ok(Value::Number(value)),
+            None => Ok(Value::Forward(token.text)),
+        }
+    }
+
+    // A value that has to be known now.
+    fn number(&mut self) -> Result<f64, AsmError> {
+        let token = self.tokens.front().cloned().unwrap_or(self.last.clone());
+        match self.value()? {
+            Value::Number(value) => Ok(value),
+            Value::Forward(name) => Err(self.error(&token, format!("'{}' is not defined", name))),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, AsmError> {
+        let token = self.tokens.front().cloned().unwrap_or(self.last.clone());
+        let value = self.number()?;
+        Ok(self.fit(value, -128.0, 255.0, &token)? as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, AsmError> {
+        let token = self.tokens.front().cloned().unwrap_or(self.last.clone());
+        let value = self.number()?;
+        Ok(self.fit(value, 0.0, 15.0, &token)? as u8)
+    }
+
+    // A 12-bit address. Labels that aren't defined yet are patched in at the end.
+    fn address(&mut self, token: &Token) -> Result<u16, AsmError> {
+        match self.value()? {
+            Value::Number(addr) => Ok(self.fit(addr, 0, 0xFFF, token)? as u16),
+            Value::Forward(label) => {
+                if !is_name(&label) {
+                    return Err(self.error(token, format!("'{}' isn't an address", label)));
+                }
+                self.fixup(FixupKind::Address, &label, 0, token);
+                Ok(0)
+            }
+        }
+    }
+
+    // Checks a value is within `min..=max` and truncates it to an integer. Negative values wrap
+    // like two's complement.
+    fn fit(
+        &self,
+        value: f64,
+        min: impl Into<f64>,
+        max: impl Into<f64>,
+        token: &Token,
+    ) -> Result<i64, AsmError> {
+        let (min, max) = (min.into(), max.into());
+        if value < min || value > max {
+            return Err(self.error(
+                token,
+                format!("{} is out of range {}..={}", value, min, max),
+            ));
+        }
+        let value = value as i64;
+        Ok(if value < 0 { value & max as i64 } else { value })
+    }
+
+    fn lookup(&self, name: &str) -> Option<f64> {
+        if let Some(&value) = self.constants.get(name) {
+            return Some(value);
+        }
+        self.labels.get(name).map(|&addr| addr as f64)
+    }
+
+    // Records a forward reference to `label`, `offset` bytes past the current address.
+    fn fixup(&mut self, kind: FixupKind, label: &str, offset: usize, token: &Token) {
+        self.fixups.push(Fixup {
+            addr: self.here + offset,
+            kind,
+            label: label.to_string(),
+            token: token.clone(),
+        });
+    }
+
+    fn patch_jump(&mut self, addr: usize, target: u16) {
+        let index = addr - LOAD_ADDRESS as usize;
+        let word = Opcode::Jp(target).encode();
+        self.rom[index..index + 2].copy_from_slice(&word.to_be_bytes());
+    }
+
+    // Writes an instruction, checking the target platform has it.
+    fn emit(&mut self, opcode: Opcode, token: &Token) -> Result<(), AsmError> {
+        let word = opcode.encode();
+        if Opcode::decode(&Instruction::from_bytes(word), self.platform) != Some(opcode) {
+            return Err(self.error(
+                token,
+                format!("'{}' isn't available on {}", opcode, self.platform),
+            ));
+        }
+        self.write(&word.to_be_bytes(), token)
+    }
+
+    fn write(&mut self, bytes: &[u8], token: &Token) -> Result<(), AsmError> {
+        for &byte in bytes {
+            if self.here >= self.platform.memory_size() {
+                return Err(self.error(token, "the program doesn't fit in memory"));
+            }
+            let index = self.here - LOAD_ADDRESS as usize;
+            if index >= self.rom.len() {
+                self.rom.resize(index + 1, 0);
+                self.written.resize(index + 1, false);
+            }
+            if self.written[index] {
+                return Err(self.error(token, format!("overwrites the byte at {:#X}", self.here)));
+            }
+            self.rom[index] = byte;
+            self.written[index] = true;
+            self.here += 1;
+        }
+        Ok(())
+    }
+
+    // Reads `{ expression }` and evaluates it.
+    fn braced_expression(&mut self) -> Result<f64, AsmError> {
+        let open = self.expect("{")?;
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => (),
+            }
+            tokens.push(token);
+        }
+        if tokens.is_empty() {
+            return Err(self.error(&open, "empty expression"));
+        }
+
+        let mut position = 0;
+        let value = self.expression(&tokens, &mut position)?;
+        if let Some(extra) = tokens.get(position) {
+            return Err(self.error(extra, format!("unexpected '{}'", extra.text)));
+        }
+        Ok(value)
+    }
+
+    // Octo expressions have no operator precedence: binary operators are evaluated right to
+    // left, so `2 * 3 + 1` is 8. Use parentheses to group.
+    fn expression(&self, tokens: &[Token], position: &mut usize) -> Result<f64, AsmError> {
+        let left = self.term(tokens, position)?;
+        let Some(operator) = tokens.get(*position) else {
+            return Ok(left);
+        };
+        if operator.text == ")" {
+            return Ok(left);
+        }
+        *position += 1;
+        let right = self.expression(tokens, position)?;
+
+        let (a, b) = (left, right);
+        let bits = |f: fn(i64, i64) -> i64| f(a as i64, b as i64) as f64;
+        let truth = |value: bool| if value { 1.0 } else { 0.0 };
+        Ok(match operator.text.as_str() {
+            "+" => a + b,
+            "-" => a - b,
+            "*" => a * b,
+            "/" => a / b,
+            "%" => a % b,
+            "&" => bits(|a, b| a & b),
+            "|" => bits(|a, b| a | b),
+            "^" => bits(|a, b| a ^ b),
+            "<<" => bits(|a, b| a << (b & 63)),
+            ">>" => bits(|a, b| a >> (b & 63)),
+            "pow" => a.powf(b),
+            "min" => a.min(b),
+            "max" => a.max(b),
+            "<" => truth(a < b),
+            ">" => truth(a > b),
+            "<=" => truth(a <= b),
+            ">=" => truth(a >= b),
+            "==" => truth(a == b),
+            "!=" => truth(a != b),
+            _ => return Err(self.error(operator, format!("unknown operator '{}'", operator.text))),
+        })
+    }
+
+    fn term(&self, tokens: &[Token], position: &mut usize) -> Result<f64, AsmError> {
+        let Some(token) = tokens.get(*position) else {
+            let last = tokens.last().unwrap_or(&self.last);
+            return Err(self.error(last, "expression ends too soon"));
+        };
+        *position += 1;
+
+        let unary: Option<fn(f64) -> f64> = match token.text.as_str() {
+            "-" => Some(|a| -a),
+            "~" => Some(|a| !(a as i64) as f64),
+            "!" => Some(|a| if a == 0.0 { 1.0 } else { 0.0 }),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(function) = unary {
+            return Ok(function(self.term(tokens, position)?));
+        }
+
+        match token.text.as_str() {
+            "(" => {
+                let value = self.expression(tokens, position)?;
+                match tokens.get(*position) {
+                    Some(close) if close.text == ")" => {
+                        *position += 1;
+                        Ok(value)
+                    }
+                    _ => Err(self.error(token, "'(' without a matching ')'")),
+                }
+            }
+            "@" => {
+                // The byte already compiled at an address
+                let addr = self.term(tokens, position)? as i64;
+                let index = addr - LOAD_ADDRESS as i64;
+                Ok(usize::try_from(index)
+                    .ok()
+                    .and_then(|index| self.rom.get(index))
+                    .map_or(0.0, |&byte| byte as f64))
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            text => self
+                .lookup(text)
+                .or_else(|| parse_number(text))
+                .ok_or_else(|| self.error(token, format!("'{}' is not defined", text))),
+        }
+    }
+}
+
+fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// Decimal, 0x hex or 0b binary, optionally negative.
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
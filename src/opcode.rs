@@ -0,0 +1,516 @@
+// The meaning of each instruction, decoded from the fields of an `Instruction`.
+//
+// Mnemonics follow Cowgod's technical reference for CHIP-8 and SUPER-CHIP. The XO-CHIP extensions
+// don't have a standard assembly syntax, so they use SAVE/LOAD for the register ranges, PLANE,
+// AUDIO and PITCH, and `LD I, LONG nnnn` for the F000 NNNN long index load.
+//
+// The disassembler formats opcodes with `mnemonic` and `operands`, and the assembler turns the
+// same mnemonic and operands back into an opcode with `from_parts`, so they share one syntax.
+
+use std::fmt;
+
+use crate::instruction::Instruction;
+use crate::platform::Platform;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    // 0NNN: call a machine code routine. Not supported by the interpreter.
+    Sys(u16),
+    // 00E0
+    Cls,
+    // 00EE
+    Ret,
+    // 00CN (SUPER-CHIP)
+    ScrollDown(u8),
+    // 00DN (XO-CHIP)
+    ScrollUp(u8),
+    // 00FB (SUPER-CHIP)
+    ScrollRight,
+    // 00FC (SUPER-CHIP)
+    ScrollLeft,
+    // 00FD (SUPER-CHIP)
+    Exit,
+    // 00FE (SUPER-CHIP)
+    Low,
+    // 00FF (SUPER-CHIP)
+    High,
+    // 1NNN
+    Jp(u16),
+    // 2NNN
+    Call(u16),
+    // 3XNN
+    SeByte { x: usize, nn: u8 },
+    // 4XNN
+    SneByte { x: usize, nn: u8 },
+    // 5XY0
+    SeReg { x: usize, y: usize },
+    // 5XY2 (XO-CHIP)
+    SaveRange { x: usize, y: usize },
+    // 5XY3 (XO-CHIP)
+    LoadRange { x: usize, y: usize },
+    // 6XNN
+    LdByte { x: usize, nn: u8 },
+    // 7XNN
+    AddByte { x: usize, nn: u8 },
+    // 8XY0
+    LdReg { x: usize, y: usize },
+    // 8XY1
+    Or { x: usize, y: usize },
+    // 8XY2
+    And { x: usize, y: usize },
+    // 8XY3
+    Xor { x: usize, y: usize },
+    // 8XY4
+    AddReg { x: usize, y: usize },
+    // 8XY5
+    Sub { x: usize, y: usize },
+    // 8XY6
+    Shr { x: usize, y: usize },
+    // 8XY7
+    Subn { x: usize, y: usize },
+    // 8XYE
+    Shl { x: usize, y: usize },
+    // 9XY0
+    SneReg { x: usize, y: usize },
+    // ANNN
+    LdI(u16),
+    // BNNN (BXNN with the jump quirk)
+    JpV0(u16),
+    // CXNN
+    Rnd { x: usize, nn: u8 },
+    // DXYN
+    Drw { x: usize, y: usize, n: u8 },
+    // EX9E
+    Skp(usize),
+    // EXA1
+    Sknp(usize),
+    // F000 NNNN (XO-CHIP). The address is in the following word.
+    LdLongI,
+    // FN01 (XO-CHIP)
+    Plane(u8),
+    // F002 (XO-CHIP)
+    Audio,
+    // FX07
+    LdVxDt(usize),
+    // FX0A
+    LdVxK(usize),
+    // FX15
+    LdDtVx(usize),
+    // FX18
+    LdStVx(usize),
+    // FX1E
+    AddI(usize),
+    // FX29
+    LdF(usize),
+    // FX30 (SUPER-CHIP)
+    LdHf(usize),
+    // FX33
+    LdB(usize),
+    // FX3A (XO-CHIP)
+    Pitch(usize),
+    // FX55
+    LdIVx(usize),
+    // FX65
+    LdVxI(usize),
+    // FX75 (SUPER-CHIP)
+    LdRVx(usize),
+    // FX85 (SUPER-CHIP)
+    LdVxR(usize),
+}
+
+// An operand as it is written in assembly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    // V0-VF
+    Register(usize),
+    // A number. Whether it has to fit in 4, 8, 12 or 16 bits depends on the instruction.
+    Number(u16),
+    // I
+    Index,
+    // [I]
+    IndexMemory,
+    // DT
+    DelayTimer,
+    // ST
+    SoundTimer,
+    // K
+    Key,
+    // F, the small font
+    Font,
+    // HF, the SUPER-CHIP large font
+    LargeFont,
+    // B, BCD
+    Bcd,
+    // R, the SUPER-CHIP RPL flags
+    Flags,
+    // LONG, the F000 NNNN marker
+    Long,
+}
+
+// Every mnemonic `from_parts` accepts.
+pub const MNEMONICS: [&str; 32] = [
+    "SYS", "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE",
+    "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND",
+    "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Register(x) => write!(f, "V{:X}", x),
+            // Single digits read the same in decimal and hex
+            Operand::Number(value) if *value < 10 => write!(f, "{}", value),
+            Operand::Number(value) => write!(f, "{:#X}", value),
+            Operand::Index => f.write_str("I"),
+            Operand::IndexMemory => f.write_str("[I]"),
+            Operand::DelayTimer => f.write_str("DT"),
+            Operand::SoundTimer => f.write_str("ST"),
+            Operand::Key => f.write_str("K"),
+            Operand::Font => f.write_str("F"),
+            Operand::LargeFont => f.write_str("HF"),
+            Operand::Bcd => f.write_str("B"),
+            Operand::Flags => f.write_str("R"),
+            Operand::Long => f.write_str("LONG"),
+        }
+    }
+}
+
+impl Opcode {
+    // Decodes an instruction using the instruction set of `platform`. Returns None if the
+    // platform has no such instruction.
+    pub fn decode(instruction: &Instruction, platform: Platform) -> Option<Opcode> {
+        let Instruction {
+            op_code,
+            nnn,
+            nn,
+            n,
+            x,
+            y,
+        } = *instruction;
+        let superchip = platform.supports_superchip();
+        let xochip = platform.supports_xochip();
+
+        let opcode = match op_code {
+            0x0 => match nnn {
+                0x0E0 => Opcode::Cls,
+                0x0EE => Opcode::Ret,
+                0x0C0..=0x0CF if superchip => Opcode::ScrollDown(n),
+                0x0D0..=0x0DF if xochip => Opcode::ScrollUp(n),
+                0x0FB if superchip => Opcode::ScrollRight,
+                0x0FC if superchip => Opcode::ScrollLeft,
+                0x0FD if superchip => Opcode::Exit,
+                0x0FE if superchip => Opcode::Low,
+                0x0FF if superchip => Opcode::High,
+                _ => Opcode::Sys(nnn),
+            },
+            0x1 => Opcode::Jp(nnn),
+            0x2 => Opcode::Call(nnn),
+            0x3 => Opcode::SeByte { x, nn },
+            0x4 => Opcode::SneByte { x, nn },
+            0x5 => match n {
+                0x0 => Opcode::SeReg { x, y },
+                0x2 if xochip => Opcode::SaveRange { x, y },
+                0x3 if xochip => Opcode::LoadRange { x, y },
+                _ => return None,
+            },
+            0x6 => Opcode::LdByte { x, nn },
+            0x7 => Opcode::AddByte { x, nn },
+            0x8 => match n {
+                0x0 => Opcode::LdReg { x, y },
+                0x1 => Opcode::Or { x, y },
+                0x2 => Opcode::And { x, y },
+                0x3 => Opcode::Xor { x, y },
+                0x4 => Opcode::AddReg { x, y },
+                0x5 => Opcode::Sub { x, y },
+                0x6 => Opcode::Shr { x, y },
+                0x7 => Opcode::Subn { x, y },
+                0xE => Opcode::Shl { x, y },
+                _ => return None,
+            },
+            0x9 if n == 0 => Opcode::SneReg { x, y },
+            0xA => Opcode::LdI(nnn),
+            0xB => Opcode::JpV0(nnn),
+            0xC => Opcode::Rnd { x, nn },
+            0xD => Opcode::Drw { x, y, n },
+            0xE => match nn {
+                0x9E => Opcode::Skp(x),
+                0xA1 => Opcode::Sknp(x),
+                _ => return None,
+            },
+            0xF => match nn {
+                0x00 if x == 0 && xochip => Opcode::LdLongI,
+                0x01 if xochip => Opcode::Plane(x as u8),
+                0x02 if x == 0 && xochip => Opcode::Audio,
+                0x07 => Opcode::LdVxDt(x),
+                0x0A => Opcode::LdVxK(x),
+                0x15 => Opcode::LdDtVx(x),
+                0x18 => Opcode::LdStVx(x),
+                0x1E => Opcode::AddI(x),
+                0x29 => Opcode::LdF(x),
+                0x30 if superchip => Opcode::LdHf(x),
+                0x33 => Opcode::LdB(x),
+                0x3A if xochip => Opcode::Pitch(x),
+                0x55 => Opcode::LdIVx(x),
+                0x65 => Opcode::LdVxI(x),
+                0x75 if superchip => Opcode::LdRVx(x),
+                0x85 if superchip => Opcode::LdVxR(x),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(opcode)
+    }
+
+    // The instruction word. `decode(Instruction::from_bytes(opcode.encode()))` gives back the same
+    // opcode on any platform that supports it.
+    pub fn encode(&self) -> u16 {
+        let x_only = |prefix: u16, x: usize, nn: u16| prefix << 12 | (x as u16) << 8 | nn;
+        let xy = |prefix: u16, x: usize, y: usize, n: u16| {
+            prefix << 12 | (x as u16) << 8 | (y as u16) << 4 | n
+        };
+
+        match *self {
+            Opcode::Sys(nnn) => nnn,
+            Opcode::Cls => 0x00E0,
+            Opcode::Ret => 0x00EE,
+            Opcode::ScrollDown(n) => 0x00C0 | n as u16,
+            Opcode::ScrollUp(n) => 0x00D0 | n as u16,
+            Opcode::ScrollRight => 0x00FB,
+            Opcode::ScrollLeft => 0x00FC,
+            Opcode::Exit => 0x00FD,
+            Opcode::Low => 0x00FE,
+            Opcode::High => 0x00FF,
+            Opcode::Jp(nnn) => 0x1000 | nnn,
+            Opcode::Call(nnn) => 0x2000 | nnn,
+            Opcode::SeByte { x, nn } => x_only(0x3, x, nn as u16),
+            Opcode::SneByte { x, nn } => x_only(0x4, x, nn as u16),
+            Opcode::SeReg { x, y } => xy(0x5, x, y, 0x0),
+            Opcode::SaveRange { x, y } => xy(0x5, x, y, 0x2),
+            Opcode::LoadRange { x, y } => xy(0x5, x, y, 0x3),
+            Opcode::LdByte { x, nn } => x_only(0x6, x, nn as u16),
+            Opcode::AddByte { x, nn } => x_only(0x7, x, nn as u16),
+            Opcode::LdReg { x, y } => xy(0x8, x, y, 0x0),
+            Opcode::Or { x, y } => xy(0x8, x, y, 0x1),
+            Opcode::And { x, y } => xy(0x8, x, y, 0x2),
+            Opcode::Xor { x, y } => xy(0x8, x, y, 0x3),
+            Opcode::AddReg { x, y } => xy(0x8, x, y, 0x4),
+            Opcode::Sub { x, y } => xy(0x8, x, y, 0x5),
+            Opcode::Shr { x, y } => xy(0x8, x, y, 0x6),
+            Opcode::Subn { x, y } => xy(0x8, x, y, 0x7),
+            Opcode::Shl { x, y } => xy(0x8, x, y, 0xE),
+            Opcode::SneReg { x, y } => xy(0x9, x, y, 0x0),
+            Opcode::LdI(nnn) => 0xA000 | nnn,
+            Opcode::JpV0(nnn) => 0xB000 | nnn,
+            Opcode::Rnd { x, nn } => x_only(0xC, x, nn as u16),
+            Opcode::Drw { x, y, n } => xy(0xD, x, y, n as u16),
+            Opcode::Skp(x) => x_only(0xE, x, 0x9E),
+            Opcode::Sknp(x) => x_only(0xE, x, 0xA1),
+            Opcode::LdLongI => 0xF000,
+            Opcode::Plane(n) => x_only(0xF, n as usize, 0x01),
+            Opcode::Audio => 0xF002,
+            Opcode::LdVxDt(x) => x_only(0xF, x, 0x07),
+            Opcode::LdVxK(x) => x_only(0xF, x, 0x0A),
+            Opcode::LdDtVx(x) => x_only(0xF, x, 0x15),
+            Opcode::LdStVx(x) => x_only(0xF, x, 0x18),
+            Opcode::AddI(x) => x_only(0xF, x, 0x1E),
+            Opcode::LdF(x) => x_only(0xF, x, 0x29),
+            Opcode::LdHf(x) => x_only(0xF, x, 0x30),
+            Opcode::LdB(x) => x_only(0xF, x, 0x33),
+            Opcode::Pitch(x) => x_only(0xF, x, 0x3A),
+            Opcode::LdIVx(x) => x_only(0xF, x, 0x55),
+            Opcode::LdVxI(x) => x_only(0xF, x, 0x65),
+            Opcode::LdRVx(x) => x_only(0xF, x, 0x75),
+            Opcode::LdVxR(x) => x_only(0xF, x, 0x85),
+        }
+    }
+
+    // The length of the instruction in bytes, including the address word after F000.
+    pub fn size(&self) -> usize {
+        match self {
+            Opcode::LdLongI => 4,
+            _ => 2,
+        }
+    }
+
+    // The address a jump or call goes to. BNNN's target depends on a register so it isn't
+    // included.
+    pub fn branch_target(&self) -> Option<u16> {
+        match *self {
+            Opcode::Jp(nnn) | Opcode::Call(nnn) => Some(nnn),
+            _ => None,
+        }
+    }
+
+    // The opcode written as `mnemonic` with `operands`, the inverse of `mnemonic` and `operands`.
+    // The mnemonic is case insensitive. Numbers are checked against the size of the field they
+    // go in.
+    pub fn from_parts(mnemonic: &str, operands: &[Operand]) -> Result<Opcode, String> {
+        use Operand::*;
+        let mnemonic = mnemonic.to_ascii_uppercase();
+
+        let opcode = match (mnemonic.as_str(), operands) {
+            ("SYS", &[Number(nnn)]) => Opcode::Sys(address(nnn)?),
+            ("CLS", []) => Opcode::Cls,
+            ("RET", []) => Opcode::Ret,
+            ("SCD", &[Number(n)]) => Opcode::ScrollDown(nibble(n)?),
+            ("SCU", &[Number(n)]) => Opcode::ScrollUp(nibble(n)?),
+            ("SCR", []) => Opcode::ScrollRight,
+            ("SCL", []) => Opcode::ScrollLeft,
+            ("EXIT", []) => Opcode::Exit,
+            ("LOW", []) => Opcode::Low,
+            ("HIGH", []) => Opcode::High,
+            ("JP", &[Number(nnn)]) => Opcode::Jp(address(nnn)?),
+            ("JP", &[Register(0), Number(nnn)]) => Opcode::JpV0(address(nnn)?),
+            ("CALL", &[Number(nnn)]) => Opcode::Call(address(nnn)?),
+            ("SE", &[Register(x), Number(nn)]) => Opcode::SeByte { x, nn: byte(nn)? },
+            ("SE", &[Register(x), Register(y)]) => Opcode::SeReg { x, y },
+            ("SNE", &[Register(x), Number(nn)]) => Opcode::SneByte { x, nn: byte(nn)? },
+            ("SNE", &[Register(x), Register(y)]) => Opcode::SneReg { x, y },
+            ("SAVE", &[Register(x), Register(y)]) => Opcode::SaveRange { x, y },
+            ("LOAD", &[Register(x), Register(y)]) => Opcode::LoadRange { x, y },
+            ("LD", &[Register(x), Number(nn)]) => Opcode::LdByte { x, nn: byte(nn)? },
+            ("LD", &[Register(x), Register(y)]) => Opcode::LdReg { x, y },
+            ("LD", &[Index, Number(nnn)]) => Opcode::LdI(address(nnn)?),
+            ("LD", &[Index, Long]) => Opcode::LdLongI,
+            ("LD", &[Register(x), DelayTimer]) => Opcode::LdVxDt(x),
+            ("LD", &[Register(x), Key]) => Opcode::LdVxK(x),
+            ("LD", &[DelayTimer, Register(x)]) => Opcode::LdDtVx(x),
+            ("LD", &[SoundTimer, Register(x)]) => Opcode::LdStVx(x),
+            ("LD", &[Font, Register(x)]) => Opcode::LdF(x),
+            ("LD", &[LargeFont, Register(x)]) => Opcode::LdHf(x),
+            ("LD", &[Bcd, Register(x)]) => Opcode::LdB(x),
+            ("LD", &[IndexMemory, Register(x)]) => Opcode::LdIVx(x),
+            ("LD", &[Register(x), IndexMemory]) => Opcode::LdVxI(x),
+            ("LD", &[Flags, Register(x)]) => Opcode::LdRVx(x),
+            ("LD", &[Register(x), Flags]) => Opcode::LdVxR(x),
+            ("ADD", &[Register(x), Number(nn)]) => Opcode::AddByte { x, nn: byte(nn)? },
+            ("ADD", &[Register(x), Register(y)]) => Opcode::AddReg { x, y },
+            ("ADD", &[Index, Register(x)]) => Opcode::AddI(x),
+            ("OR", &[Register(x), Register(y)]) => Opcode::Or { x, y },
+            ("AND", &[Register(x), Register(y)]) => Opcode::And { x, y },
+            ("XOR", &[Register(x), Register(y)]) => Opcode::Xor { x, y },
+            ("SUB", &[Register(x), Register(y)]) => Opcode::Sub { x, y },
+            ("SHR", &[Register(x), Register(y)]) => Opcode::Shr { x, y },
+            ("SHR", &[Register(x)]) => Opcode::Shr { x, y: x },
+            ("SUBN", &[Register(x), Register(y)]) => Opcode::Subn { x, y },
+            ("SHL", &[Register(x), Register(y)]) => Opcode::Shl { x, y },
+            ("SHL", &[Register(x)]) => Opcode::Shl { x, y: x },
+            ("RND", &[Register(x), Number(nn)]) => Opcode::Rnd { x, nn: byte(nn)? },
+            ("DRW", &[Register(x), Register(y), Number(n)]) => Opcode::Drw {
+                x,
+                y,
+                n: nibble(n)?,
+            },
+            ("SKP", &[Register(x)]) => Opcode::Skp(x),
+            ("SKNP", &[Register(x)]) => Opcode::Sknp(x),
+            ("PLANE", &[Number(n)]) => Opcode::Plane(nibble(n)?),
+            ("AUDIO", []) => Opcode::Audio,
+            ("PITCH", &[Register(x)]) => Opcode::Pitch(x),
+            _ if MNEMONICS.contains(&mnemonic.as_str()) => {
+                return Err(format!("invalid operands for {}", mnemonic))
+            }
+            _ => return Err(format!("unknown instruction '{}'", mnemonic)),
+        };
+        Ok(opcode)
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        self.parts().0
+    }
+
+    pub fn operands(&self) -> Vec<Operand> {
+        self.parts().1
+    }
+
+    fn parts(&self) -> (&'static str, Vec<Operand>) {
+        use Operand::*;
+        let v = Register;
+        let num = |value: u8| Number(value as u16);
+
+        match *self {
+            Opcode::Sys(nnn) => ("SYS", vec![Number(nnn)]),
+            Opcode::Cls => ("CLS", vec![]),
+            Opcode::Ret => ("RET", vec![]),
+            Opcode::ScrollDown(n) => ("SCD", vec![num(n)]),
+            Opcode::ScrollUp(n) => ("SCU", vec![num(n)]),
+            Opcode::ScrollRight => ("SCR", vec![]),
+            Opcode::ScrollLeft => ("SCL", vec![]),
+            Opcode::Exit => ("EXIT", vec![]),
+            Opcode::Low => ("LOW", vec![]),
+            Opcode::High => ("HIGH", vec![]),
+            Opcode::Jp(nnn) => ("JP", vec![Number(nnn)]),
+            Opcode::Call(nnn) => ("CALL", vec![Number(nnn)]),
+            Opcode::SeByte { x, nn } => ("SE", vec![v(x), num(nn)]),
+            Opcode::SneByte { x, nn } => ("SNE", vec![v(x), num(nn)]),
+            Opcode::SeReg { x, y } => ("SE", vec![v(x), v(y)]),
+            Opcode::SaveRange { x, y } => ("SAVE", vec![v(x), v(y)]),
+            Opcode::LoadRange { x, y } => ("LOAD", vec![v(x), v(y)]),
+            Opcode::LdByte { x, nn } => ("LD", vec![v(x), num(nn)]),
+            Opcode::AddByte { x, nn } => ("ADD", vec![v(x), num(nn)]),
+            Opcode::LdReg { x, y } => ("LD", vec![v(x), v(y)]),
+            Opcode::Or { x, y } => ("OR", vec![v(x), v(y)]),
+            Opcode::And { x, y } => ("AND", vec![v(x), v(y)]),
+            Opcode::Xor { x, y } => ("XOR", vec![v(x), v(y)]),
+            Opcode::AddReg { x, y } => ("ADD", vec![v(x), v(y)]),
+            Opcode::Sub { x, y } => ("SUB", vec![v(x), v(y)]),
+            Opcode::Shr { x, y } => ("SHR", vec![v(x), v(y)]),
+            Opcode::Subn { x, y } => ("SUBN", vec![v(x), v(y)]),
+            Opcode::Shl { x, y } => ("SHL", vec![v(x), v(y)]),
+            Opcode::SneReg { x, y } => ("SNE", vec![v(x), v(y)]),
+            Opcode::LdI(nnn) => ("LD", vec![Index, Number(nnn)]),
+            Opcode::JpV0(nnn) => ("JP", vec![v(0), Number(nnn)]),
+            Opcode::Rnd { x, nn } => ("RND", vec![v(x), num(nn)]),
+            Opcode::Drw { x, y, n } => ("DRW", vec![v(x), v(y), num(n)]),
+            Opcode::Skp(x) => ("SKP", vec![v(x)]),
+            Opcode::Sknp(x) => ("SKNP", vec![v(x)]),
+            Opcode::LdLongI => ("LD", vec![Index, Long]),
+            Opcode::Plane(n) => ("PLANE", vec![num(n)]),
+            Opcode::Audio => ("AUDIO", vec![]),
+            Opcode::LdVxDt(x) => ("LD", vec![v(x), DelayTimer]),
+            Opcode::LdVxK(x) => ("LD", vec![v(x), Key]),
+            Opcode::LdDtVx(x) => ("LD", vec![DelayTimer, v(x)]),
+            Opcode::LdStVx(x) => ("LD", vec![SoundTimer, v(x)]),
+            Opcode::AddI(x) => ("ADD", vec![Index, v(x)]),
+            Opcode::LdF(x) => ("LD", vec![Font, v(x)]),
+            Opcode::LdHf(x) => ("LD", vec![LargeFont, v(x)]),
+            Opcode::LdB(x) => ("LD", vec![Bcd, v(x)]),
+            Opcode::Pitch(x) => ("PITCH", vec![v(x)]),
+            Opcode::LdIVx(x) => ("LD", vec![IndexMemory, v(x)]),
+            Opcode::LdVxI(x) => ("LD", vec![v(x), IndexMemory]),
+            Opcode::LdRVx(x) => ("LD", vec![Flags, v(x)]),
+            Opcode::LdVxR(x) => ("LD", vec![v(x), Flags]),
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (mnemonic, operands) = self.parts();
+        f.write_str(mnemonic)?;
+        for (i, operand) in operands.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", separator, operand)?;
+        }
+        Ok(())
+    }
+}
+
+fn address(value: u16) -> Result<u16, String> {
+    fit(value, 12)
+}
+
+fn byte(value: u16) -> Result<u8, String> {
+    fit(value, 8).map(|value| value as u8)
+}
+
+fn nibble(value: u16) -> Result<u8, String> {
+    fit(value, 4).map(|value| value as u8)
+}
+
+fn fit(value: u16, bits: u32) -> Result<u16, String> {
+    if value >> bits == 0 {
+        Ok(value)
+    } else {
+        Err(format!("{:#X} doesn't fit in {} bits", value, bits))
+    }
+}
@@ -0,0 +1,88 @@
+// The colours used to draw the framebuffer. Pixels are indexed by the XO-CHIP bitplanes lit at
+// that position: off, plane 1, plane 2, both planes. CHIP-8 and SUPER-CHIP only use the first two.
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [[u8; 3]; 4],
+}
+
+impl Palette {
+    pub const NAMES: [&'static str; 4] = ["default", "amber", "green", "octo"];
+
+    // A built-in palette by name.
+    pub fn named(name: &str) -> Option<Palette> {
+        let colors = match name.to_ascii_lowercase().as_str() {
+            "default" => return Some(Palette::default()),
+            "amber" => [0x1A1000, 0xFFB000, 0xB07800, 0x604000],
+            "green" => [0x0F380F, 0x9BBC0F, 0x8BAC0F, 0x306230],
+            // The Octo IDE's colours
+            "octo" => [0x996600, 0xFFCC00, 0xFF6600, 0x662200],
+            _ => return None,
+        };
+        Some(Palette {
+            colors: colors.map(|rgb: u32| {
+                let [_, r, g, b] = rgb.to_be_bytes();
+                [r, g, b]
+            }),
+        })
+    }
+
+    // The RGB colour of a framebuffer pixel.
+    pub fn color(&self, planes: u8) -> [u8; 3] {
+        self.colors[planes as usize & 0b11]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            colors: [
+                [0x00, 0x00, 0x00],
+                [0xFF, 0xFF, 0xFF],
+                [0xAA, 0xAA, 0xAA],
+                [0x55, 0x55, 0x55],
+            ],
+        }
+    }
+}
+
+// A palette name, or 2 or 4 comma separated RRGGBB colours: off and lit, or one for each
+// combination of XO-CHIP planes. With 2 colours, everything lit uses the second.
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(palette) = Palette::named(s) {
+            return Ok(palette);
+        }
+        let colors = s
+            .split(',')
+            .map(|color| parse_color(color.trim()))
+            .collect::<Option<Vec<[u8; 3]>>>()
+            .ok_or_else(|| {
+                format!(
+                    "unknown palette '{}', expected one of {} or RRGGBB colours",
+                    s,
+                    Palette::NAMES.join(", ")
+                )
+            })?;
+        let colors = match colors[..] {
+            [off, lit] => [off, lit, lit, lit],
+            [off, plane1, plane2, both] => [off, plane1, plane2, both],
+            _ => return Err("a palette needs 2 or 4 colours".to_string()),
+        };
+        Ok(Palette { colors })
+    }
+}
+
+fn parse_color(text: &str) -> Option<[u8; 3]> {
+    let digits = text.strip_prefix('#').unwrap_or(text);
+    if digits.len() != 6 {
+        return None;
+    }
+    let rgb = u32::from_str_radix(digits, 16).ok()?;
+    let [_, r, g, b] = rgb.to_be_bytes();
+    Some([r, g, b])
+}
@@ -0,0 +1,91 @@
+// Reference: https://github.com/Timendus/chip8-test-suite#quirks-test
+// The original COSMAC VIP interpreter and the later CHIP-48/SUPER-CHIP/XO-CHIP interpreters
+// disagree on how a handful of instructions behave. ROMs written for one will often break on
+// another, so each divergence can be switched independently.
+
+// How FX55/FX65 leave the index register after storing/loading V0 through VX.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStoreQuirk {
+    // I is left untouched (SUPER-CHIP).
+    Unchanged,
+    // I ends up incremented by X (CHIP-48).
+    IncrementByX,
+    // I ends up incremented by X + 1, pointing just past the last byte (COSMAC VIP, XO-CHIP).
+    IncrementByXPlusOne,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VX in place. When false VX is set to VY before shifting.
+    pub shift: bool,
+    // BNNN jumps to NNN + VX (read as BXNN). When false it jumps to NNN + V0.
+    pub jump: bool,
+    pub load_store: LoadStoreQuirk,
+    // 8XY1/8XY2/8XY3 reset VF to 0.
+    pub vf_reset: bool,
+    // DXYN clips sprites at the screen edges. When false they wrap around to the other side.
+    pub clipping: bool,
+    // DXYN waits for the next 60 Hz tick before drawing, limiting sprites to 60 per second.
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift: false,
+            jump: false,
+            load_store: LoadStoreQuirk::IncrementByXPlusOne,
+            vf_reset: true,
+            clipping: true,
+            display_wait: true,
+        }
+    }
+
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift: true,
+            jump: true,
+            load_store: LoadStoreQuirk::IncrementByX,
+            vf_reset: false,
+            clipping: true,
+            display_wait: false,
+        }
+    }
+
+    pub fn superchip() -> Quirks {
+        Quirks {
+            shift: true,
+            jump: true,
+            load_store: LoadStoreQuirk::Unchanged,
+            vf_reset: false,
+            clipping: true,
+            display_wait: false,
+        }
+    }
+
+    pub fn xochip() -> Quirks {
+        Quirks {
+            shift: false,
+            jump: false,
+            load_store: LoadStoreQuirk::IncrementByXPlusOne,
+            vf_reset: false,
+            clipping: false,
+            display_wait: false,
+        }
+    }
+}
+
+// The behaviour this interpreter has always had: modern shifts and no load/store increment,
+// but the original V0 jump.
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            shift: true,
+            jump: false,
+            load_store: LoadStoreQuirk::Unchanged,
+            vf_reset: false,
+            clipping: true,
+            display_wait: false,
+        }
+    }
+}
@@ -0,0 +1,90 @@
+// Random numbers for CXNN. The CPU owns a `RandomSource` so that a run can be reproduced
+// exactly from its seed: the same ROM, seed and inputs always give the same numbers.
+
+use std::fmt;
+use std::str::FromStr;
+
+// The seed used when none is given.
+pub const DEFAULT_SEED: u64 = 0;
+
+pub trait RandomSource {
+    // The next random byte for CXNN.
+    fn next_byte(&mut self) -> u8;
+
+    // Called on every 60 Hz timer tick, for generators that depend on timing.
+    fn tick(&mut self) {}
+
+    // The generator's position in its sequence, stored in save states so that a restored game
+    // carries on with the same numbers.
+    fn state(&self) -> u64;
+
+    fn set_state(&mut self, state: u64);
+}
+
+// The built-in generators, selectable from the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RandomMode {
+    #[default]
+    Seeded,
+}
+
+impl RandomMode {
+    pub fn source(&self, seed: u64) -> Box<dyn RandomSource> {
+        match self {
+            RandomMode::Seeded => Box::new(SeededRandom::new(seed)),
+        }
+    }
+}
+
+impl FromStr for RandomMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "seeded" => Ok(RandomMode::Seeded),
+            _ => Err(format!("unknown random mode '{}', expected seeded", s)),
+        }
+    }
+}
+
+impl fmt::Display for RandomMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RandomMode::Seeded => write!(f, "seeded"),
+        }
+    }
+}
+
+// SplitMix64, which is small, fast and gives a good sequence from any seed including 0.
+#[derive(Debug, Clone)]
+pub struct SeededRandom {
+    state: u64,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> SeededRandom {
+        SeededRandom { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_byte(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+}
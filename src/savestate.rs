@@ -0,0 +1,233 @@
+// Save state binary format, version 2. All multi-byte values are little-endian.
+//
+//   offset  size  field
+//   0       4     magic "C8ST"
+//   4       2     format version (2)
+//   6       20    SHA-1 of the loaded ROM
+//   26      1     platform: 0 COSMAC VIP, 1 CHIP-48, 2 SUPER-CHIP, 3 XO-CHIP
+//   27      6     quirks: shift, jump, load/store (0 unchanged, 1 +X, 2 +X+1), vf reset,
+//                 clipping, display wait
+//   33      16    V0-VF
+//   49      2     I
+//   51      2     PC
+//   53      32    stack, 16 x u16
+//   85      1     SP
+//   86      1     delay timer
+//   87      1     sound timer
+//   88      2     keypad, bit n set if key n is held
+//   90      1     vblank flag
+//   91      1     hires flag
+//   92      1     selected XO-CHIP planes
+//   93      16    RPL user flags
+//   109     1     1 if an XO-CHIP audio pattern is loaded
+//   110     16    audio pattern
+//   126     1     pitch
+//   127     4     memory length (4096 or 65536), followed by memory
+//   ...     4     framebuffer length, followed by one byte per pixel
+//   ...     1     CXNN generator: 0 seeded, 2 supplied by the program embedding the emulator.
+//                 1 was a VIP-style generator that has been removed, and isn't accepted
+//   ...     8     generator seed
+//   ...     8     generator state
+//
+// Booleans are stored as 0 or 1. Version 1 is the same without the generator fields, and loading
+// it leaves the generator as it is.
+
+use std::fmt;
+
+use crate::platform::Platform;
+use crate::quirks::{LoadStoreQuirk, Quirks};
+use crate::random::RandomMode;
+
+pub const MAGIC: &[u8; 4] = b"C8ST";
+pub const VERSION: u16 = 2;
+// The oldest version that can still be loaded
+pub const MIN_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveStateError {
+    // The data doesn't start with the save state magic number.
+    NotASaveState,
+    // The data was written by a newer (or unknown) version of the format.
+    UnsupportedVersion(u16),
+    // The data ends before all of the fields have been read.
+    Truncated,
+    // The save state was made with a different ROM to the one loaded.
+    RomMismatch,
+    // A field holds a value that can't be restored.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::NotASaveState => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+            SaveStateError::RomMismatch => write!(f, "save state is for a different ROM"),
+            SaveStateError::Invalid(field) => write!(f, "save state has an invalid {}", field),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+#[derive(Default)]
+pub(crate) struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub(crate) fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    // A length-prefixed block of bytes.
+    pub(crate) fn block(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes(bytes);
+    }
+
+    pub(crate) fn platform(&mut self, platform: Platform) {
+        self.u8(match platform {
+            Platform::CosmacVip => 0,
+            Platform::Chip48 => 1,
+            Platform::SuperChip => 2,
+            Platform::XoChip => 3,
+        });
+    }
+
+    pub(crate) fn quirks(&mut self, quirks: &Quirks) {
+        self.bool(quirks.shift);
+        self.bool(quirks.jump);
+        self.u8(match quirks.load_store {
+            LoadStoreQuirk::Unchanged => 0,
+            LoadStoreQuirk::IncrementByX => 1,
+            LoadStoreQuirk::IncrementByXPlusOne => 2,
+        });
+        self.bool(quirks.vf_reset);
+        self.bool(quirks.clipping);
+        self.bool(quirks.display_wait);
+    }
+
+    // None is a generator supplied through `CPU::set_random_source`.
+    pub(crate) fn random_mode(&mut self, mode: Option<RandomMode>) {
+        self.u8(match mode {
+            Some(RandomMode::Seeded) => 0,
+            None => 2,
+        });
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> StateReader<'a> {
+        StateReader { bytes }
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.bytes.len() < len {
+            return Err(SaveStateError::Truncated);
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self, field: &'static str) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::Invalid(field)),
+        }
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn block(&mut self) -> Result<&'a [u8], SaveStateError> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    pub(crate) fn platform(&mut self) -> Result<Platform, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(Platform::CosmacVip),
+            1 => Ok(Platform::Chip48),
+            2 => Ok(Platform::SuperChip),
+            3 => Ok(Platform::XoChip),
+            _ => Err(SaveStateError::Invalid("platform")),
+        }
+    }
+
+    pub(crate) fn quirks(&mut self) -> Result<Quirks, SaveStateError> {
+        Ok(Quirks {
+            shift: self.bool("shift quirk")?,
+            jump: self.bool("jump quirk")?,
+            load_store: match self.u8()? {
+                0 => LoadStoreQuirk::Unchanged,
+                1 => LoadStoreQuirk::IncrementByX,
+                2 => LoadStoreQuirk::IncrementByXPlusOne,
+                _ => return Err(SaveStateError::Invalid("load/store quirk")),
+            },
+            vf_reset: self.bool("vf reset quirk")?,
+            clipping: self.bool("clipping quirk")?,
+            display_wait: self.bool("display wait quirk")?,
+        })
+    }
+
+    pub(crate) fn random_mode(&mut self) -> Result<Option<RandomMode>, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(Some(RandomMode::Seeded)),
+            2 => Ok(None),
+            _ => Err(SaveStateError::Invalid("random number generator")),
+        }
+    }
+}
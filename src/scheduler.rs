@@ -0,0 +1,99 @@
+// Keeps the CPU and the 60 Hz timers running at their real speed regardless of how often the
+// frontend calls in.
+//
+// Time is measured against a monotonic clock and split into 60 Hz ticks. Each tick runs
+// instructions_per_second / 60 instructions followed by one timer decrement, so the CPU and the
+// timers can never drift apart. Rendering is left to the frontend, which only needs to draw once
+// after however many ticks were due; when it has fallen behind the intermediate frames are
+// skipped. If it falls too far behind (the window was dragged, the machine went to sleep) the
+// backlog is dropped instead of running the game at high speed to catch up.
+
+use std::time::{Duration, Instant};
+
+pub const TIMER_HZ: u32 = 60;
+pub const DEFAULT_INSTRUCTIONS_PER_SECOND: u32 = 840;
+// The most ticks that will be run in one update. Anything beyond this is dropped.
+pub const MAX_CATCH_UP_TICKS: u32 = 6;
+
+const TICK: Duration = Duration::from_nanos(1_000_000_000 / TIMER_HZ as u64);
+
+// The ticks that are due after an update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Catchup {
+    // Ticks to run now. Only the last one needs to be rendered.
+    pub ticks: u32,
+    // Ticks that were dropped because the host fell too far behind.
+    pub dropped_ticks: u32,
+}
+
+pub struct Scheduler {
+    instructions_per_second: u32,
+    last_update: Instant,
+    // Time that has passed but not yet been turned into ticks.
+    pending: Duration,
+    // Instructions left over from dividing instructions_per_second by 60, carried to the next tick.
+    cycle_remainder: u32,
+}
+
+impl Scheduler {
+    pub fn new(instructions_per_second: u32, now: Instant) -> Scheduler {
+        Scheduler {
+            instructions_per_second,
+            last_update: now,
+            pending: Duration::ZERO,
+            cycle_remainder: 0,
+        }
+    }
+
+    pub fn instructions_per_second(&self) -> u32 {
+        self.instructions_per_second
+    }
+
+    pub fn set_instructions_per_second(&mut self, instructions_per_second: u32) {
+        self.instructions_per_second = instructions_per_second;
+        self.cycle_remainder = 0;
+    }
+
+    // Works out how many ticks are due at `now`.
+    pub fn update(&mut self, now: Instant) -> Catchup {
+        let elapsed = now.saturating_duration_since(self.last_update);
+        self.last_update = now;
+        self.advance(elapsed)
+    }
+
+    // Works out how many ticks are due after `elapsed` more time has passed.
+    pub fn advance(&mut self, elapsed: Duration) -> Catchup {
+        self.pending += elapsed;
+
+        let mut ticks: u32 = 0;
+        while self.pending >= TICK {
+            self.pending -= TICK;
+            ticks += 1;
+        }
+
+        let dropped_ticks = ticks.saturating_sub(MAX_CATCH_UP_TICKS);
+        Catchup {
+            ticks: ticks - dropped_ticks,
+            dropped_ticks,
+        }
+    }
+
+    // The number of instructions to run in the next tick. Rates that aren't a multiple of 60 are
+    // spread across ticks so the average is exact.
+    pub fn cycles_for_next_tick(&mut self) -> u32 {
+        let total = self.instructions_per_second + self.cycle_remainder;
+        self.cycle_remainder = total % TIMER_HZ;
+        total / TIMER_HZ
+    }
+
+    // When the next tick will be due, for frontends that sleep between ticks.
+    pub fn next_tick_at(&self) -> Instant {
+        self.last_update + (TICK - self.pending)
+    }
+
+    // Forgets any pending time, e.g. after the emulator has been paused.
+    pub fn reset(&mut self, now: Instant) {
+        self.last_update = now;
+        self.pending = Duration::ZERO;
+    }
+}
@@ -0,0 +1,92 @@
+// Plays the beeper through the default sound card using cpal.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use chip8::audio::AudioSink;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SizedSample};
+
+pub struct CpalSink {
+    // The stream stops playing when it is dropped.
+    _stream: cpal::Stream,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    sample_rate: u32,
+}
+
+impl CpalSink {
+    pub fn new() -> Result<CpalSink, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("no audio output device available")?;
+        let supported_config = device
+            .default_output_config()
+            .map_err(|err| err.to_string())?;
+        let sample_format = supported_config.sample_format();
+        let config: cpal::StreamConfig = supported_config.into();
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+
+        let stream = match sample_format {
+            cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config, queue.clone()),
+            cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config, queue.clone()),
+            cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config, queue.clone()),
+            format => return Err(format!("unsupported sample format {}", format)),
+        }?;
+        stream.play().map_err(|err| err.to_string())?;
+
+        Ok(CpalSink {
+            _stream: stream,
+            queue,
+            sample_rate: config.sample_rate.0,
+        })
+    }
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+) -> Result<cpal::Stream, String>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let mut queue = queue.lock().unwrap();
+                // The same mono sample goes to every channel. If the emulator has fallen behind,
+                // play silence rather than blocking the audio thread.
+                for frame in data.chunks_mut(channels) {
+                    let sample = queue.pop_front().unwrap_or(0.0);
+                    for output in frame {
+                        *output = T::from_sample(sample);
+                    }
+                }
+            },
+            |err| eprintln!("Audio stream error: {}", err),
+            None,
+        )
+        .map_err(|err| err.to_string())
+}
+
+impl AudioSink for CpalSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let mut queue = self.queue.lock().unwrap();
+        queue.extend(samples);
+        // Keep at most 100ms buffered so the beep doesn't lag behind the game if the emulator
+        // runs faster than the sound card.
+        let max_len = self.sample_rate as usize / 10;
+        if queue.len() > max_len {
+            let excess = queue.len() - max_len;
+            queue.drain(..excess);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,270 @@
+// Execution traces: one record per instruction `CPU::execute_instruction` completes, with the
+// machine state after it ran. Instructions that fault, or that are left waiting for a key press,
+// a vblank or to exit, aren't recorded.
+//
+// Tracing is off unless a `Tracer` is attached with `CPU::set_tracer`, and then costs one branch
+// per instruction.
+//
+// Text format, one line per instruction, all numbers hex:
+//
+//   PC   OP   MNEMONIC          V0 V1 .. VF                                      I    SP DT ST
+//   0200 6012 LD V0, 0x12       12 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  0000 0  00 00
+//
+// Export format, for comparing against other emulators with chip8-tracediff: whitespace
+// separated hex columns PC, opcode, V0-VF, I, SP, DT, ST and the framebuffer. The framebuffer is
+// one bit per pixel (lit in any plane), rows top to bottom and the leftmost pixel in the high bit,
+// so 512 digits in lores and 2048 in hires.
+//
+// Binary format, all multi-byte values little-endian:
+//
+//   offset  size  field
+//   0       4     magic "C8TR"
+//   4       2     format version (1)
+//   6       -     records of RECORD_SIZE bytes:
+//                   0   2   PC
+//                   2   2   opcode
+//                   4   16  V0-VF
+//                   20  2   I
+//                   22  1   SP
+//                   23  1   delay timer
+//                   24  1   sound timer
+
+use std::fmt::{self, Write as _};
+use std::io::{self, Read};
+use std::str::FromStr;
+
+use crate::cpu::CPU;
+use crate::instruction::Instruction;
+use crate::opcode::Opcode;
+use crate::platform::Platform;
+
+pub const MAGIC: &[u8; 4] = b"C8TR";
+pub const VERSION: u16 = 1;
+pub const RECORD_SIZE: usize = 25;
+
+// One executed instruction and the state it left behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pc: u16,
+    pub opcode: u16,
+    pub registers: [u8; 16],
+    pub index: u16,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl TraceRecord {
+    // The disassembled instruction, or `DW` if it doesn't decode on `platform`.
+    pub fn mnemonic(&self, platform: Platform) -> String {
+        match Opcode::decode(&Instruction::from_bytes(self.opcode), platform) {
+            Some(opcode) => opcode.to_string(),
+            None => format!("DW {:#06X}", self.opcode),
+        }
+    }
+
+    pub fn text(&self, platform: Platform) -> String {
+        let registers: Vec<String> = self
+            .registers
+            .iter()
+            .map(|value| format!("{:02X}", value))
+            .collect();
+        format!(
+            "{:04X} {:04X} {:<17} {}  {:04X} {:X}  {:02X} {:02X}",
+            self.pc,
+            self.opcode,
+            self.mnemonic(platform),
+            registers.join(" "),
+            self.index,
+            self.sp,
+            self.delay_timer,
+            self.sound_timer
+        )
+    }
+
+    // The export format line, with `framebuffer` from `pack_framebuffer`.
+    pub fn export(&self, framebuffer: &[u8]) -> String {
+        let mut text = format!("{:04X} {:04X}", self.pc, self.opcode);
+        for value in self.registers {
+            let _ = write!(text, " {:02X}", value);
+        }
+        let _ = write!(
+            text,
+            " {:04X} {:02X} {:02X} {:02X} ",
+            self.index, self.sp, self.delay_timer, self.sound_timer
+        );
+        for byte in framebuffer {
+            let _ = write!(text, "{:02X}", byte);
+        }
+        text
+    }
+
+    pub fn to_bytes(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[0..2].copy_from_slice(&self.pc.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.opcode.to_le_bytes());
+        bytes[4..20].copy_from_slice(&self.registers);
+        bytes[20..22].copy_from_slice(&self.index.to_le_bytes());
+        bytes[22] = self.sp;
+        bytes[23] = self.delay_timer;
+        bytes[24] = self.sound_timer;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> TraceRecord {
+        let mut registers = [0; 16];
+        registers.copy_from_slice(&bytes[4..20]);
+        TraceRecord {
+            pc: u16::from_le_bytes([bytes[0], bytes[1]]),
+            opcode: u16::from_le_bytes([bytes[2], bytes[3]]),
+            registers,
+            index: u16::from_le_bytes([bytes[20], bytes[21]]),
+            sp: bytes[22],
+            delay_timer: bytes[23],
+            sound_timer: bytes[24],
+        }
+    }
+}
+
+// The framebuffer with one bit per pixel, set if the pixel is lit in any plane. Rows are padded
+// to whole bytes, with the leftmost pixel in the high bit.
+pub fn pack_framebuffer(cpu: &CPU) -> Vec<u8> {
+    let mut packed = Vec::new();
+    for row in cpu.framebuffer().chunks(cpu.display_width()) {
+        for pixels in row.chunks(8) {
+            let byte = pixels
+                .iter()
+                .enumerate()
+                .filter(|(_, &planes)| planes != 0)
+                .fold(0u8, |byte, (i, _)| byte | 0x80 >> i);
+            packed.push(byte);
+        }
+    }
+    packed
+}
+
+// Receives a record for every instruction the CPU completes, along with the CPU itself for
+// anything else it wants to capture.
+pub trait Tracer {
+    fn record(&mut self, record: &TraceRecord, cpu: &CPU);
+
+    // Flushes anything buffered and reports the first write error, if there was one.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceFormat {
+    #[default]
+    Text,
+    Export,
+    Binary,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(TraceFormat::Text),
+            "export" => Ok(TraceFormat::Export),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => Err(format!(
+                "unknown trace format '{}', expected text, export or binary",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TraceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceFormat::Text => f.write_str("text"),
+            TraceFormat::Export => f.write_str("export"),
+            TraceFormat::Binary => f.write_str("binary"),
+        }
+    }
+}
+
+// Writes records in `format`. Write errors are held until `finish` so they don't interrupt
+// emulation; nothing more is written after the first one.
+pub struct TraceWriter<W: io::Write> {
+    writer: W,
+    format: TraceFormat,
+    platform: Platform,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> TraceWriter<W> {
+    // `platform` is used to disassemble instructions in the text format.
+    pub fn new(mut writer: W, format: TraceFormat, platform: Platform) -> TraceWriter<W> {
+        let error = match format {
+            TraceFormat::Text | TraceFormat::Export => None,
+            TraceFormat::Binary => writer
+                .write_all(MAGIC)
+                .and_then(|()| writer.write_all(&VERSION.to_le_bytes()))
+                .err(),
+        };
+        TraceWriter {
+            writer,
+            format,
+            platform,
+            error,
+        }
+    }
+}
+
+impl<W: io::Write> Tracer for TraceWriter<W> {
+    fn record(&mut self, record: &TraceRecord, cpu: &CPU) {
+        if self.error.is_some() {
+            return;
+        }
+        let result = match self.format {
+            TraceFormat::Text => writeln!(self.writer, "{}", record.text(self.platform)),
+            TraceFormat::Export => {
+                writeln!(self.writer, "{}", record.export(&pack_framebuffer(cpu)))
+            }
+            TraceFormat::Binary => self.writer.write_all(&record.to_bytes()),
+        };
+        self.error = result.err();
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.writer.flush()
+    }
+}
+
+// Reads a whole binary trace.
+pub fn read_binary(mut reader: impl Read) -> io::Result<Vec<TraceRecord>> {
+    let mut header = [0; 6];
+    reader.read_exact(&mut header)?;
+    if &header[0..4] != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a binary trace",
+        ));
+    }
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported trace version {}", version),
+        ));
+    }
+
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    if data.len() % RECORD_SIZE != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "trace ends part way through a record",
+        ));
+    }
+    Ok(data
+        .chunks_exact(RECORD_SIZE)
+        .map(|chunk| TraceRecord::from_bytes(chunk.try_into().unwrap()))
+        .collect())
+}
@@ -0,0 +1,369 @@
+// Lines up two execution traces and finds the first instruction where they disagree.
+//
+// Our side is a binary or export format trace (see `trace`). The reference side is any text
+// trace with one instruction per line and whitespace separated columns, described by a
+// `ColumnFormat` such as `pc,opcode,v0-vf,i,sp,dt,st,fb`. Values are hex, with or without 0x, and
+// anything up to a `:` or `=` is ignored so `PC:0200` and `V0=12` work too. Lines that are blank
+// or start with `#` are skipped.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::trace::{self, TraceRecord};
+
+// What one column of a reference trace holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Pc,
+    Opcode,
+    Register(usize),
+    Index,
+    Sp,
+    DelayTimer,
+    SoundTimer,
+    // Packed one bit per pixel, as in the export format
+    Framebuffer,
+    Skip,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnFormat(Vec<Field>);
+
+impl ColumnFormat {
+    // The layout of our export format.
+    pub fn export() -> ColumnFormat {
+        let mut fields = vec![Field::Pc, Field::Opcode];
+        fields.extend((0..16).map(Field::Register));
+        fields.extend([
+            Field::Index,
+            Field::Sp,
+            Field::DelayTimer,
+            Field::SoundTimer,
+            Field::Framebuffer,
+        ]);
+        ColumnFormat(fields)
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.0
+    }
+}
+
+impl Default for ColumnFormat {
+    fn default() -> Self {
+        ColumnFormat::export()
+    }
+}
+
+// A comma separated list of pc, opcode (or op), v0-vf (each register, or the range for sixteen
+// columns), i, sp, dt, st, fb and skip (or _) for columns to ignore.
+impl FromStr for ColumnFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = Vec::new();
+        for name in s.split(',').map(|name| name.trim().to_ascii_lowercase()) {
+            let field = match name.as_str() {
+                "pc" => Field::Pc,
+                "opcode" | "op" => Field::Opcode,
+                "i" => Field::Index,
+                "sp" => Field::Sp,
+                "dt" => Field::DelayTimer,
+                "st" => Field::SoundTimer,
+                "fb" => Field::Framebuffer,
+                "skip" | "_" => Field::Skip,
+                "v0-vf" => {
+                    fields.extend((0..16).map(Field::Register));
+                    continue;
+                }
+                _ => match register_column(&name) {
+                    Some(x) => Field::Register(x),
+                    None => return Err(format!("unknown column '{}'", name)),
+                },
+            };
+            fields.push(field);
+        }
+        if !fields.contains(&Field::Pc) {
+            return Err("the columns must include pc".to_string());
+        }
+        Ok(ColumnFormat(fields))
+    }
+}
+
+fn register_column(name: &str) -> Option<usize> {
+    let digit = name.strip_prefix('v')?;
+    if digit.len() != 1 {
+        return None;
+    }
+    usize::from_str_radix(digit, 16).ok()
+}
+
+// One instruction from either trace. Fields the trace doesn't have are None and aren't compared.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Step {
+    // The line in the trace file, or the record number in a binary trace, from 1
+    pub line: usize,
+    pub pc: u16,
+    pub opcode: Option<u16>,
+    pub registers: [Option<u8>; 16],
+    pub index: Option<u16>,
+    pub sp: Option<u8>,
+    pub delay_timer: Option<u8>,
+    pub sound_timer: Option<u8>,
+    pub framebuffer: Option<Vec<u8>>,
+}
+
+impl Step {
+    fn from_record(line: usize, record: &TraceRecord) -> Step {
+        Step {
+            line,
+            pc: record.pc,
+            opcode: Some(record.opcode),
+            registers: record.registers.map(Some),
+            index: Some(record.index),
+            sp: Some(record.sp),
+            delay_timer: Some(record.delay_timer),
+            sound_timer: Some(record.sound_timer),
+            framebuffer: None,
+        }
+    }
+
+    // Replaces the machine state with `next`'s, keeping this step's PC and opcode. Used for
+    // traces that log the state before each instruction instead of after it.
+    fn with_state_of(&self, next: &Step) -> Step {
+        Step {
+            line: self.line,
+            pc: self.pc,
+            opcode: self.opcode,
+            ..next.clone()
+        }
+    }
+}
+
+// A one line summary: PC, opcode and whichever registers are known.
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>6}: {:04X}", self.line, self.pc)?;
+        match self.opcode {
+            Some(opcode) => write!(f, " {:04X}", opcode)?,
+            None => write!(f, " ----")?,
+        }
+        for value in self.registers {
+            match value {
+                Some(value) => write!(f, " {:02X}", value)?,
+                None => write!(f, " --")?,
+            }
+        }
+        match self.index {
+            Some(index) => write!(f, "  I {:04X}", index),
+            None => write!(f, "  I ----"),
+        }
+    }
+}
+
+// Reads a trace: binary if it starts with the binary trace magic, otherwise text in `format`.
+pub fn parse(data: &[u8], format: &ColumnFormat) -> Result<Vec<Step>, String> {
+    if data.starts_with(trace::MAGIC) {
+        let records = trace::read_binary(data).map_err(|err| err.to_string())?;
+        return Ok(records
+            .iter()
+            .enumerate()
+            .map(|(i, record)| Step::from_record(i + 1, record))
+            .collect());
+    }
+    let text = std::str::from_utf8(data).map_err(|_| "not a binary or text trace".to_string())?;
+    parse_text(text, format)
+}
+
+pub fn parse_text(text: &str, format: &ColumnFormat) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        if columns.len() < format.fields().len() {
+            return Err(format!(
+                "line {}: expected {} columns, found {}",
+                line_number,
+                format.fields().len(),
+                columns.len()
+            ));
+        }
+
+        let mut step = Step {
+            line: line_number,
+            ..Step::default()
+        };
+        for (&field, column) in format.fields().iter().zip(columns) {
+            let value = column.rsplit([':', '=']).next().unwrap_or(column);
+            let digits = value
+                .strip_prefix("0x")
+                .or_else(|| value.strip_prefix("0X"))
+                .unwrap_or(value);
+            let invalid = || format!("line {}: '{}' is not a hex number", line_number, column);
+            let number = || u16::from_str_radix(digits, 16).map_err(|_| invalid());
+            let byte = || u8::from_str_radix(digits, 16).map_err(|_| invalid());
+            match field {
+                Field::Pc => step.pc = number()?,
+                Field::Opcode => step.opcode = Some(number()?),
+                Field::Register(x) => step.registers[x] = Some(byte()?),
+                Field::Index => step.index = Some(number()?),
+                Field::Sp => step.sp = Some(byte()?),
+                Field::DelayTimer => step.delay_timer = Some(byte()?),
+                Field::SoundTimer => step.sound_timer = Some(byte()?),
+                Field::Framebuffer => {
+                    step.framebuffer = Some(parse_hex_bytes(digits).ok_or_else(invalid)?)
+                }
+                Field::Skip => (),
+            }
+        }
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+fn parse_hex_bytes(digits: &str) -> Option<Vec<u8>> {
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// Converts a trace that logs the state before each instruction to one that logs it after, like
+// ours. The last instruction is dropped as its result isn't known.
+pub fn state_before_to_after(steps: &[Step]) -> Vec<Step> {
+    steps
+        .windows(2)
+        .map(|pair| pair[0].with_state_of(&pair[1]))
+        .collect()
+}
+
+// How far into the reference trace our first instruction is: the first step with the same PC.
+pub fn align(ours: &[Step], reference: &[Step]) -> Option<usize> {
+    let first = ours.first()?;
+    reference.iter().position(|step| step.pc == first.pc)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub field: String,
+    pub ours: String,
+    pub reference: String,
+}
+
+// The first position (in both traces, after alignment) where the steps disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub position: usize,
+    pub differences: Vec<Difference>,
+}
+
+// Compares the steps both traces have and returns the first disagreement, if any. Traces of
+// different lengths aren't a divergence on their own since emulators rarely stop at the same
+// instruction.
+pub fn first_divergence(ours: &[Step], reference: &[Step]) -> Option<Divergence> {
+    ours.iter()
+        .zip(reference)
+        .map(|(a, b)| compare(a, b))
+        .enumerate()
+        .find(|(_, differences)| !differences.is_empty())
+        .map(|(position, differences)| Divergence {
+            position,
+            differences,
+        })
+}
+
+pub fn compare(ours: &Step, reference: &Step) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    let mut check = |field: String, a: Option<u16>, b: Option<u16>, width: usize| {
+        if let (Some(a), Some(b)) = (a, b) {
+            if a != b {
+                differences.push(Difference {
+                    field,
+                    ours: format!("{:0width$X}", a, width = width),
+                    reference: format!("{:0width$X}", b, width = width),
+                });
+            }
+        }
+    };
+    check("PC".to_string(), Some(ours.pc), Some(reference.pc), 4);
+    check("opcode".to_string(), ours.opcode, reference.opcode, 4);
+    for x in 0..16 {
+        let a = ours.registers[x].map(u16::from);
+        let b = reference.registers[x].map(u16::from);
+        check(format!("V{:X}", x), a, b, 2);
+    }
+    check("I".to_string(), ours.index, reference.index, 4);
+    check(
+        "SP".to_string(),
+        ours.sp.map(u16::from),
+        reference.sp.map(u16::from),
+        2,
+    );
+    check(
+        "DT".to_string(),
+        ours.delay_timer.map(u16::from),
+        reference.delay_timer.map(u16::from),
+        2,
+    );
+    check(
+        "ST".to_string(),
+        ours.sound_timer.map(u16::from),
+        reference.sound_timer.map(u16::from),
+        2,
+    );
+
+    if let (Some(a), Some(b)) = (&ours.framebuffer, &reference.framebuffer) {
+        if a != b {
+            let pixels = a
+                .iter()
+                .zip(b)
+                .map(|(a, b)| (a ^ b).count_ones() as usize)
+                .sum::<usize>();
+            differences.push(Difference {
+                field: "framebuffer".to_string(),
+                ours: format!("{} bytes", a.len()),
+                reference: format!("{} bytes, {} pixels differ", b.len(), pixels),
+            });
+        }
+    }
+    differences
+}
+
+// Two packed framebuffers as ASCII art side by side, with differing pixels marked `X` on the
+// reference side. Resolution is worked out from the size: 256 bytes is 64x32, 1024 is 128x64.
+pub fn framebuffers_side_by_side(ours: &[u8], reference: &[u8]) -> String {
+    let width = if ours.len() >= 1024 { 128 } else { 64 };
+    let row_bytes = width / 8;
+    let pixel = |buffer: &[u8], row: usize, x: usize| {
+        buffer
+            .get(row * row_bytes + x / 8)
+            .is_some_and(|byte| byte & (0x80 >> (x % 8)) != 0)
+    };
+
+    let rows = ours.len().max(reference.len()) / row_bytes;
+    let mut text = String::new();
+    for row in 0..rows {
+        for x in 0..width {
+            text.push(if pixel(ours, row, x) { '#' } else { '.' });
+        }
+        text.push_str("  ");
+        for x in 0..width {
+            let (a, b) = (pixel(ours, row, x), pixel(reference, row, x));
+            text.push(match (a == b, b) {
+                (false, _) => 'X',
+                (true, true) => '#',
+                (true, false) => '.',
+            });
+        }
+        text.push('\n');
+    }
+    text
+}
@@ -0,0 +1,147 @@
+// The assembler: everything the disassembler prints assembles back to the same bytes, plus
+// labels, directives and the errors.
+
+use std::fs;
+use std::process;
+
+use chip8::assembler::{self, AsmError};
+use chip8::disassembler;
+use chip8::instruction::Instruction;
+use chip8::opcode::Opcode;
+use chip8::Platform;
+
+fn assemble(source: &str) -> Vec<u8> {
+    assembler::assemble(source).unwrap_or_else(|err| panic!("{}", err))
+}
+
+fn error(source: &str) -> AsmError {
+    assembler::assemble(source).unwrap_err()
+}
+
+#[test]
+fn every_instruction_round_trips() {
+    for platform in Platform::ALL {
+        for word in 0..=u16::MAX {
+            let Some(opcode) = Opcode::decode(&Instruction::from_bytes(word), platform) else {
+                continue;
+            };
+            let source = match opcode {
+                Opcode::LdLongI => "LD I, LONG 0x1234".to_string(),
+                _ => opcode.to_string(),
+            };
+            let mut expected = word.to_be_bytes().to_vec();
+            if opcode == Opcode::LdLongI {
+                expected.extend([0x12, 0x34]);
+            }
+            assert_eq!(
+                assembler::assemble(&source),
+                Ok(expected),
+                "{:04X} on {}",
+                word,
+                platform
+            );
+        }
+    }
+}
+
+#[test]
+fn listings_reassemble() {
+    for name in [
+        "breakout",
+        "ibm_logo",
+        "maze",
+        "pong",
+        "space_invaders",
+        "tetris",
+    ] {
+        let rom = fs::read(format!("roms/{}.ch8", name)).unwrap();
+        let listing = disassembler::listing(&rom, 0x200, Platform::XoChip);
+        assert_eq!(assemble(&listing), rom, "{}", name);
+    }
+
+    // Words that don't decode, a jump into the middle of F000 NNNN, and F000 or a lone byte at
+    // the end
+    for rom in [
+        vec![0x12, 0x03, 0xF0, 0x00, 0x12, 0x34, 0x80, 0x0F],
+        vec![0x00, 0xE0, 0xF0, 0x00],
+        vec![0x00, 0xE0, 0xAB],
+    ] {
+        let listing = disassembler::listing(&rom, 0x200, Platform::XoChip);
+        assert_eq!(assemble(&listing), rom, "{}", listing);
+    }
+}
+
+#[test]
+fn labels_and_constants() {
+    let source = "
+SPEED   EQU 4
+start:  LD V0, SPEED
+        LD I, sprite + 1      ; forward reference
+        ADD V1, -1
+        JP start
+sprite: DB 0xF0, 0x90
+        DW sprite - 2, end
+end:
+";
+    assert_eq!(
+        assemble(source),
+        [0x60, 0x04, 0xA2, 0x09, 0x71, 0xFF, 0x12, 0x00, 0xF0, 0x90, 0x02, 0x06, 0x02, 0x0E]
+    );
+
+    // Mnemonics and registers are case insensitive, names aren't
+    assert_eq!(
+        assemble("Loop: ld va, 1\n jp Loop"),
+        [0x6A, 0x01, 0x12, 0x00]
+    );
+    assert_eq!(error("loop: JP Loop").message, "'Loop' is not defined");
+}
+
+#[test]
+fn directives() {
+    // ORG pads with zeros, DB takes strings, characters and binary
+    assert_eq!(
+        assemble("CLS\nORG 0x206\nDB \"Hi\", 'c', 0b101, -1"),
+        [0x00, 0xE0, 0, 0, 0, 0, b'H', b'i', b'c', 0b101, 0xFF]
+    );
+
+    let dir = std::env::temp_dir().join(format!("chip8-assembler-{}", process::id()));
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(dir.join("main.asm"), "INCLUDE \"lib/font.asm\"\nLD I, font").unwrap();
+    // Paths are relative to the including file
+    fs::write(
+        dir.join("lib/font.asm"),
+        "INCLUDE \"digits.asm\"\nCOUNT EQU 1",
+    )
+    .unwrap();
+    fs::write(dir.join("lib/digits.asm"), "font: DB 0xF0").unwrap();
+    fs::write(dir.join("self.asm"), "INCLUDE \"self.asm\"").unwrap();
+    let rom = assembler::assemble_file(&dir.join("main.asm"));
+    let recursive = assembler::assemble_file(&dir.join("self.asm"));
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(rom.unwrap(), [0xF0, 0xA2, 0x00]);
+    assert!(recursive
+        .unwrap_err()
+        .message
+        .ends_with("self.asm includes itself"));
+}
+
+#[test]
+fn errors_point_at_the_problem() {
+    let err = error("CLS\n  JP nowhere");
+    assert_eq!(err.to_string(), "<input>:2:6: 'nowhere' is not defined");
+
+    let message = |source: &str| error(source).message;
+    assert_eq!(message("a: CLS\na: RET"), "'a' is already defined");
+    assert_eq!(message("V0: CLS"), "'V0' can't be used as a name");
+    assert_eq!(message("I EQU 1"), "'I' can't be used as a name");
+    assert_eq!(message("DB 256"), "256 is out of range");
+    assert_eq!(
+        message("CLS\nORG 0x200"),
+        "ORG 0x200 is before the current address 0x202"
+    );
+    assert_eq!(message("INCLUDE font.asm"), "expected a quoted file name");
+    assert_eq!(message("LD V0, 0x100"), "0x100 doesn't fit in 8 bits");
+    assert_eq!(message("FROB V0"), "unknown instruction 'FROB'");
+    assert_eq!(message("LD V0"), "invalid operands for LD");
+}
@@ -0,0 +1,86 @@
+// Audio in headless runs: the sound timer through the `Beeper` into a WAV file.
+
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::process;
+
+use chip8::assembler;
+use chip8::audio::{AudioConfig, Beeper, WavSink, DEFAULT_SAMPLE_RATE};
+use chip8::headless::{self, KeyScript, RunLimit};
+use chip8::{Platform, CPU};
+
+const WAV_HEADER_LEN: usize = 44;
+const FRAMES: u64 = 60;
+
+// Sets the sound timer, then spins.
+fn sound_timer_program(ticks: u8) -> String {
+    format!("LD V0, {}\nLD ST, V0\nspin: JP spin", ticks)
+}
+
+// Runs `source` for a second with a beeper writing to a WAV file and returns the samples.
+fn record(source: &str, sample_rate: u32) -> Vec<i16> {
+    let platform = Platform::CosmacVip;
+    let mut cpu = CPU::with_platform(platform, platform.quirks());
+    cpu.load_rom(assembler::assemble(source).unwrap()).unwrap();
+
+    let path =
+        std::env::temp_dir().join(format!("chip8-audio-{}-{}.wav", process::id(), sample_rate));
+    let sink = WavSink::new(BufWriter::new(File::create(&path).unwrap()), sample_rate).unwrap();
+    let mut beeper = Beeper::new(AudioConfig::default(), Box::new(sink));
+    headless::run(
+        &mut cpu,
+        600,
+        RunLimit::Frames(FRAMES),
+        &KeyScript::default(),
+        Some(&mut beeper),
+    )
+    .unwrap();
+    beeper.finish().unwrap();
+    drop(beeper);
+    let wav = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(&wav[..4], b"RIFF");
+    assert_eq!(&wav[8..16], b"WAVEfmt ");
+    assert_eq!(wav[24..28], sample_rate.to_le_bytes());
+    assert_eq!(&wav[36..40], b"data");
+    let data_len = u32::from_le_bytes(wav[40..44].try_into().unwrap()) as usize;
+    assert_eq!(data_len, wav.len() - WAV_HEADER_LEN);
+    wav[WAV_HEADER_LEN..]
+        .chunks(2)
+        .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+        .collect()
+}
+
+#[test]
+fn the_buzzer_sounds_while_the_sound_timer_runs() {
+    let samples = record(&sound_timer_program(30), DEFAULT_SAMPLE_RATE);
+    assert_eq!(samples.len(), DEFAULT_SAMPLE_RATE as usize);
+
+    // The timer is set during the first frame and counted down at the end of each, so it is
+    // non-zero for 29 ticks
+    let per_tick = (DEFAULT_SAMPLE_RATE / 60) as usize;
+    let (beep, silence) = samples.split_at(29 * per_tick);
+    let level = (AudioConfig::default().volume * i16::MAX as f32) as i16;
+    assert!(beep
+        .iter()
+        .all(|&sample| sample == level || sample == -level));
+    // A 440 Hz square wave changes sign 880 times a second
+    let changes = beep.windows(2).filter(|pair| pair[0] != pair[1]).count();
+    assert_eq!(changes, 880 * 29 / 60);
+    assert!(silence.iter().all(|&sample| sample == 0));
+}
+
+#[test]
+fn the_output_is_silent_when_the_sound_timer_is_zero() {
+    let samples = record(&sound_timer_program(0), DEFAULT_SAMPLE_RATE);
+    assert_eq!(samples.len(), DEFAULT_SAMPLE_RATE as usize);
+    assert!(samples.iter().all(|&sample| sample == 0));
+}
+
+#[test]
+fn sample_rates_that_dont_divide_by_60_dont_drift() {
+    // 367.5 samples a tick
+    let samples = record(&sound_timer_program(0), 22_050);
+    assert_eq!(samples.len(), 22_050);
+}
@@ -0,0 +1,155 @@
+// The config file: parsing, the per-ROM sections and how they combine with the rest of the file.
+
+#![cfg(feature = "window")]
+
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use chip8::config::{Config, Settings};
+use chip8::keyboard::{HostKey, Layout};
+use chip8::palette::Palette;
+use chip8::Platform;
+use winit::event::VirtualKeyCode;
+
+const SPACE_INVADERS_SHA1: &str = "5c28a5f85289c9d859f95fd5eadbdcb1c30bb08b";
+
+const CONFIG: &str = r#"
+ips = 700
+quirks = "schip"
+palette = "amber"
+layout = "positional"
+
+[keys]
+5 = ["Up", "W"]
+
+[hotkeys]
+pause = "Space"
+
+[rom."Tetris.ch8"]
+ips = 1200
+scale = 12
+
+[rom."Tetris.ch8".keys]
+8 = "scancode:0x1F"
+
+[rom.5C28A5F85289C9D859F95FD5EADBDCB1C30BB08B]
+quirks = "vip"
+"#;
+
+#[test]
+fn settings_are_parsed() {
+    let config = Config::parse(CONFIG).unwrap();
+    let settings = &config.settings;
+    assert_eq!(settings.ips, Some(700));
+    assert_eq!(settings.quirks, Some(Platform::SuperChip));
+    assert_eq!(settings.palette, Some("amber".parse::<Palette>().unwrap()));
+    assert_eq!(settings.layout, Some(Layout::Positional));
+    assert_eq!(
+        settings.keys,
+        [(
+            0x5,
+            vec![
+                HostKey::Named(VirtualKeyCode::Up),
+                HostKey::Named(VirtualKeyCode::W)
+            ]
+        )]
+    );
+    assert_eq!(settings.hotkeys.pause, Some(VirtualKeyCode::Space));
+    assert_eq!(settings.hotkeys.quit, None);
+    assert_eq!(settings.scale, None);
+}
+
+#[test]
+fn rom_sections_are_found_by_file_name_or_sha1() {
+    let config = Config::parse(CONFIG).unwrap();
+    let tetris = config
+        .rom_section(Path::new("roms/tetris.ch8"), None)
+        .unwrap();
+    assert_eq!(tetris.ips, Some(1200));
+
+    let invaders = config
+        .rom_section(Path::new("invaders.ch8"), Some(SPACE_INVADERS_SHA1))
+        .unwrap();
+    assert_eq!(invaders.quirks, Some(Platform::CosmacVip));
+
+    // The file name is tried before the hash
+    let section = config
+        .rom_section(Path::new("TETRIS.CH8"), Some(SPACE_INVADERS_SHA1))
+        .unwrap();
+    assert_eq!(section.ips, Some(1200));
+
+    assert!(config
+        .rom_section(Path::new("pong.ch8"), Some("0123"))
+        .is_none());
+}
+
+#[test]
+fn rom_sections_replace_settings_and_add_keys() {
+    let config = Config::parse(CONFIG).unwrap();
+    let tetris = config.for_rom(Path::new("tetris.ch8"), None);
+    assert_eq!(tetris.ips, Some(1200));
+    assert_eq!(tetris.scale, Some(12));
+    // Not in the section, so from the rest of the file
+    assert_eq!(tetris.quirks, Some(Platform::SuperChip));
+    assert_eq!(tetris.hotkeys.pause, Some(VirtualKeyCode::Space));
+    assert_eq!(
+        tetris.keys,
+        [
+            (
+                0x5,
+                vec![
+                    HostKey::Named(VirtualKeyCode::Up),
+                    HostKey::Named(VirtualKeyCode::W)
+                ]
+            ),
+            (0x8, vec![HostKey::Scancode(31)]),
+        ]
+    );
+
+    assert_eq!(config.for_rom(Path::new("pong.ch8"), None), config.settings);
+    assert_eq!(
+        Settings::default().merged(&config.settings),
+        config.settings
+    );
+}
+
+#[test]
+fn bad_settings_are_errors() {
+    let error = |text: &str| Config::parse(text).unwrap_err();
+    assert_eq!(error("scale = 0"), "scale must be between 1 and 40");
+    assert_eq!(
+        error("[rom.\"pong.ch8\"]\nscale = 41"),
+        "scale must be between 1 and 40"
+    );
+    assert_eq!(error("ips = 0"), "ips must be at least 1");
+    assert!(error("speed = 10").contains("unknown field `speed`"));
+    assert!(error("quirks = \"nes\"").contains("nes"));
+    assert!(error("[keys]\n10 = \"W\"").contains("hex digit"));
+    assert!(error("[hotkeys]\nquit = \"Wibble\"").contains("unknown host key 'Wibble'"));
+    assert!(error("ips = ").starts_with("TOML parse error"));
+}
+
+#[test]
+fn keymap_paths_are_relative_to_the_config_file() {
+    let dir = std::env::temp_dir().join(format!("chip8-config-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(
+        &path,
+        "keymap = \"keymaps/default\"\n[rom.\"pong.ch8\"]\nkeymap = \"/keymaps/pong\"\n",
+    )
+    .unwrap();
+    let config = Config::load(Some(&path));
+    fs::remove_dir_all(&dir).unwrap();
+
+    let config = config.unwrap();
+    assert_eq!(config.settings.keymap, Some(dir.join("keymaps/default")));
+    let pong = config.for_rom(Path::new("pong.ch8"), None);
+    assert_eq!(pong.keymap.as_deref(), Some(Path::new("/keymaps/pong")));
+
+    // A file given by name has to exist
+    assert!(Config::load(Some(&path))
+        .unwrap_err()
+        .starts_with("Unable to read"));
+}
@@ -0,0 +1,291 @@
+// Runs test ROMs headlessly and compares the final screen with a golden image in tests/golden.
+// The community ROMs aren't redistributed, so their tests are ignored by default: put them in
+// tests/roms (see the README there) and run
+//
+//     cargo test --test conformance -- --include-ignored
+//
+// To record goldens for newly added ROMs, check the output by hand and then run
+//
+//     UPDATE_GOLDEN=1 cargo test --test conformance -- --include-ignored
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chip8::assembler;
+use chip8::headless::{self, KeyEvent, KeyScript, RunEnd, RunLimit};
+use chip8::{Platform, CPU};
+
+const INSTRUCTIONS_PER_SECOND: u32 = 1000;
+
+struct Case {
+    // Also the golden's file name
+    name: &'static str,
+    rom: &'static str,
+    platform: Platform,
+    frames: u64,
+    // Written to 0x1FF, which the Timendus ROMs read to skip their menus
+    mode: Option<u8>,
+    // (frame, key, pressed)
+    keys: &'static [(u64, usize, bool)],
+}
+
+impl Case {
+    const fn new(name: &'static str, rom: &'static str, platform: Platform) -> Case {
+        Case {
+            name,
+            rom,
+            platform,
+            frames: 300,
+            mode: None,
+            keys: &[],
+        }
+    }
+
+    const fn mode(mut self, mode: u8) -> Case {
+        self.mode = Some(mode);
+        self
+    }
+
+    const fn frames(mut self, frames: u64) -> Case {
+        self.frames = frames;
+        self
+    }
+
+    const fn keys(mut self, keys: &'static [(u64, usize, bool)]) -> Case {
+        self.keys = keys;
+        self
+    }
+}
+
+fn manifest_path(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+fn run(case: &Case, rom: Vec<u8>) -> CPU {
+    let mut cpu = CPU::with_platform(case.platform, case.platform.quirks());
+    cpu.load_rom(rom).unwrap();
+    if let Some(mode) = case.mode {
+        cpu.write_memory(0x1FF, &[mode]).unwrap();
+    }
+
+    let script = KeyScript::new(
+        case.keys
+            .iter()
+            .map(|&(frame, key, pressed)| KeyEvent {
+                frame,
+                key,
+                pressed,
+            })
+            .collect(),
+    );
+    let summary = headless::run(
+        &mut cpu,
+        INSTRUCTIONS_PER_SECOND,
+        RunLimit::Frames(case.frames),
+        &script,
+        None,
+    )
+    .unwrap();
+    if let RunEnd::Fault(err) = summary.end {
+        panic!("{}: CPU faulted: {}", case.name, err);
+    }
+    cpu
+}
+
+// The expected and actual screens next to each other, with `<` marking rows that differ.
+fn side_by_side(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let width = expected
+        .iter()
+        .chain(&actual)
+        .map(|row| row.len())
+        .max()
+        .unwrap_or(0);
+
+    let mut text = format!("{:<width$}  {}\n", "expected", "actual", width = width);
+    for row in 0..expected.len().max(actual.len()) {
+        let a = expected.get(row).copied().unwrap_or("");
+        let b = actual.get(row).copied().unwrap_or("");
+        let marker = if a == b { "" } else { "  <" };
+        text.push_str(&format!(
+            "{:<width$}  {:<width$}{}\n",
+            a,
+            b,
+            marker,
+            width = width
+        ));
+    }
+    text
+}
+
+fn read_rom(case: &Case) -> Vec<u8> {
+    fs::read(manifest_path(case.rom)).unwrap_or_else(|err| {
+        panic!(
+            "{}: can't read {} ({}), see tests/roms/README.md",
+            case.name, case.rom, err
+        )
+    })
+}
+
+fn check(case: &Case) {
+    check_rom(case, read_rom(case));
+}
+
+fn check_rom(case: &Case, rom: Vec<u8>) {
+    let actual = headless::framebuffer_ascii(&run(case, rom));
+    let golden_path = manifest_path(&format!("tests/golden/{}.txt", case.name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden_path, &actual).unwrap();
+        return;
+    }
+    let Ok(expected) = fs::read_to_string(&golden_path) else {
+        panic!(
+            "{}: no golden image at {}, check the output and record it with UPDATE_GOLDEN=1\n{}",
+            case.name,
+            golden_path.display(),
+            actual
+        );
+    };
+    if expected != actual {
+        panic!(
+            "{}: the screen doesn't match {}\n{}",
+            case.name,
+            golden_path.display(),
+            side_by_side(&expected, &actual)
+        );
+    }
+}
+
+#[test]
+fn bundled_ibm_logo() {
+    check(&Case::new("ibm-logo", "roms/ibm_logo.ch8", Platform::CosmacVip).frames(60));
+}
+
+// 8XYE used to leave VF at 0 whatever was shifted out. This shifts 0x81 and 0x41 left and draws
+// the two flags, so the screen should read 1 0.
+const SHL_CARRY: &str = "
+        LD V0, 0x81
+        SHL V0
+        LD F, VF
+        LD V1, 0
+        LD V2, 0
+        DRW V1, V2, 5
+        LD V0, 0x41
+        SHL V0
+        LD F, VF
+        LD V1, 5
+        DRW V1, V2, 5
+done:   JP done
+";
+
+#[test]
+fn bundled_shl_carry() {
+    let rom = assembler::assemble(SHL_CARRY).unwrap();
+    check_rom(
+        &Case::new("shl-carry", "<SHL_CARRY>", Platform::CosmacVip).frames(10),
+        rom,
+    );
+}
+
+#[test]
+#[ignore = "needs the community test ROMs in tests/roms"]
+fn timendus_chip8_logo() {
+    check(&Case::new(
+        "chip8-logo",
+        "tests/roms/1-chip8-logo.ch8",
+        Platform::CosmacVip,
+    ));
+}
+
+#[test]
+#[ignore = "needs the community test ROMs in tests/roms"]
+fn timendus_ibm_logo() {
+    check(&Case::new(
+        "timendus-ibm-logo",
+        "tests/roms/2-ibm-logo.ch8",
+        Platform::CosmacVip,
+    ));
+}
+
+#[test]
+#[ignore = "needs the community test ROMs in tests/roms"]
+fn timendus_corax_plus() {
+    check(&Case::new(
+        "corax+",
+        "tests/roms/3-corax+.ch8",
+        Platform::CosmacVip,
+    ));
+}
+
+#[test]
+#[ignore = "needs the community test ROMs in tests/roms"]
+fn timendus_flags() {
+    check(&Case::new(
+        "flags",
+        "tests/roms/4-flags.ch8",
+        Platform::CosmacVip,
+    ));
+}
+
+#[test]
+#[ignore = "needs the community test ROMs in tests/roms"]
+fn timendus_quirks() {
+    // Modes 1-3 pick CHIP-8, SUPER-CHIP and XO-CHIP. The VIP display wait makes the CHIP-8 run
+    // slow, so give it longer.
+    let rom = "tests/roms/5-quirks.ch8";
+    check(
+        &Case::new("quirks-chip8", rom, Platform::CosmacVip)
+            .mode(1)
+            .frames(1200),
+    );
+    check(&Case::new("quirks-schip", rom, Platform::SuperChip).mode(2));
+    check(&Case::new("quirks-xochip", rom, Platform::XoChip).mode(3));
+}
+
+#[test]
+#[ignore = "needs the community test ROMs in tests/roms"]
+fn timendus_keypad() {
+    // Mode 1 is the EX9E test, which highlights the keys that are held
+    let keys = &[(60, 0x1, true), (60, 0x5, true), (60, 0xF, true)];
+    check(
+        &Case::new("keypad", "tests/roms/6-keypad.ch8", Platform::CosmacVip)
+            .mode(1)
+            .keys(keys),
+    );
+}
+
+#[test]
+#[ignore = "needs the community test ROMs in tests/roms"]
+fn timendus_beep() {
+    // B sounds the buzzer while held
+    let case = Case::new("beep", "tests/roms/7-beep.ch8", Platform::CosmacVip)
+        .frames(120)
+        .keys(&[(60, 0xB, true)]);
+    check(&case);
+    assert!(
+        run(&case, read_rom(&case)).is_sound_playing(),
+        "beep: B didn't start the buzzer"
+    );
+}
+
+#[test]
+#[ignore = "needs the community test ROMs in tests/roms"]
+fn timendus_scrolling() {
+    // Modes 1 and 2 are SUPER-CHIP lores and hires, 3 and 4 the same on XO-CHIP
+    let rom = "tests/roms/8-scrolling.ch8";
+    check(&Case::new("scrolling-schip-lores", rom, Platform::SuperChip).mode(1));
+    check(&Case::new("scrolling-schip-hires", rom, Platform::SuperChip).mode(2));
+    check(&Case::new("scrolling-xochip-lores", rom, Platform::XoChip).mode(3));
+    check(&Case::new("scrolling-xochip-hires", rom, Platform::XoChip).mode(4));
+}
+
+#[test]
+#[ignore = "needs the community test ROMs in tests/roms"]
+fn bestcoder_opcodes() {
+    check(&Case::new(
+        "bc-test",
+        "tests/roms/BC_test.ch8",
+        Platform::CosmacVip,
+    ));
+}
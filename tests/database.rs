@@ -0,0 +1,100 @@
+// The ROM database: the bundled entries, and reading entries in the CHIP-8 database format.
+
+use std::fs;
+
+use chip8::database::Database;
+use chip8::palette::Palette;
+use chip8::{LoadStoreQuirk, Platform, Quirks};
+
+#[test]
+fn every_bundled_rom_is_in_the_database() {
+    let database = Database::bundled();
+    for entry in fs::read_dir("roms").unwrap() {
+        let path = entry.unwrap().path();
+        let rom = fs::read(&path).unwrap();
+        let settings = database.lookup(&rom);
+        assert!(
+            settings.is_some(),
+            "{} isn't in the database",
+            path.display()
+        );
+        assert_eq!(settings.unwrap().platform, Some(Platform::CosmacVip));
+    }
+    assert_eq!(database.len(), 7);
+}
+
+#[test]
+fn space_invaders_needs_the_shift_quirk() {
+    let rom = fs::read("roms/space_invaders.ch8").unwrap();
+    let settings = Database::bundled().lookup(&rom).unwrap();
+    assert_eq!(settings.title, "Space Invaders");
+    assert_eq!(settings.authors, ["David Winter"]);
+    assert_eq!(
+        settings.quirks,
+        Some(Quirks {
+            shift: true,
+            ..Quirks::cosmac_vip()
+        })
+    );
+    assert_eq!(settings.instructions_per_second, Some(900));
+    assert_eq!(settings.keys["a"], 5);
+}
+
+const ENTRY: &str = r##"[
+  {
+    "title": "Test",
+    "release": "2024",
+    "roms": {
+      "0123456789ABCDEF0123456789ABCDEF01234567": {
+        "file": "test.ch8",
+        "platforms": ["megachip8", "superchip", "xochip"],
+        "quirkyPlatforms": {
+          "superchip": { "memoryIncrementByX": true, "wrap": true, "logic": true },
+          "xochip": { "shift": true }
+        },
+        "tickrate": 30,
+        "startAddress": 1536,
+        "keys": { "up": 5, "down": 8, "player2Up": 16 },
+        "colors": {
+          "pixels": ["#000000", "#ff0000", "#00ff00", "#0000ff", "#ffffff"],
+          "buzzer": "#990000"
+        },
+        "fontStyle": "octo"
+      }
+    }
+  }
+]"##;
+
+#[test]
+fn entries_use_the_first_supported_platform() {
+    let database = Database::from_json(ENTRY).unwrap();
+    let settings = database
+        .lookup_hash("0123456789abcdef0123456789abcdef01234567")
+        .unwrap();
+    assert_eq!(settings.platform, Some(Platform::SuperChip));
+    assert_eq!(
+        settings.quirks,
+        Some(Quirks {
+            load_store: LoadStoreQuirk::IncrementByX,
+            clipping: false,
+            vf_reset: true,
+            ..Quirks::superchip()
+        })
+    );
+    assert_eq!(settings.instructions_per_second, Some(1800));
+    assert_eq!(settings.load_address, Some(0x600));
+    // Key 0x10 doesn't exist
+    assert_eq!(settings.keys.len(), 2);
+    assert_eq!(
+        settings.palette,
+        Some("000000,ff0000,00ff00,0000ff".parse::<Palette>().unwrap())
+    );
+}
+
+#[test]
+fn unknown_roms_and_bad_databases() {
+    let database = Database::from_json(ENTRY).unwrap();
+    assert_eq!(database.lookup(&[0x00, 0xE0]), None);
+    assert!(Database::from_json("{}").is_err());
+    assert!(Database::from_json(r#"[{ "roms": {} }]"#).is_err());
+}
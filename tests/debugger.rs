@@ -0,0 +1,167 @@
+// The debugger: breakpoints, watchpoints and stepping over calls, on a small assembled program.
+
+use chip8::assembler;
+use chip8::debugger::{Debugger, StopReason, Watchpoint};
+use chip8::{Quirks, CPU};
+
+const PROGRAM: &str = "
+        LD V0, 1        ; 0x200
+        CALL sub        ; 0x202
+        LD I, 0x300     ; 0x204
+        LD [I], V0      ; 0x206
+        LD V2, 7        ; 0x208
+spin:   JP spin         ; 0x20A
+sub:    ADD V1, 2       ; 0x20C
+        CALL inner      ; 0x20E
+        RET             ; 0x210
+inner:  ADD V1, 3       ; 0x212
+        RET             ; 0x214
+";
+const LIMIT: u64 = 1000;
+
+fn start() -> CPU {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_rom(assembler::assemble(PROGRAM).unwrap()).unwrap();
+    cpu
+}
+
+#[test]
+fn breakpoints_stop_before_the_instruction() {
+    let mut cpu = start();
+    let mut debugger = Debugger::new(840);
+    assert!(debugger.add_breakpoint(0x206));
+    assert!(!debugger.add_breakpoint(0x206));
+    assert!(debugger.add_breakpoint(0x212));
+    assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), [0x206, 0x212]);
+
+    // Inside the nested call
+    assert_eq!(
+        debugger.continue_execution(&mut cpu, LIMIT),
+        StopReason::Breakpoint(0x212)
+    );
+    assert_eq!(cpu.pc(), 0x212);
+    assert_eq!(cpu.registers()[1], 2);
+
+    // Continuing from a breakpoint doesn't stop on it again straight away
+    assert_eq!(
+        debugger.continue_execution(&mut cpu, LIMIT),
+        StopReason::Breakpoint(0x206)
+    );
+    assert_eq!(cpu.registers()[1], 5);
+    assert_eq!(cpu.memory()[0x300], 0);
+
+    assert!(debugger.remove_breakpoint(0x212));
+    assert!(!debugger.remove_breakpoint(0x212));
+    assert_eq!(
+        debugger.continue_execution(&mut cpu, LIMIT),
+        StopReason::LimitReached
+    );
+    assert_eq!(cpu.pc(), 0x20A);
+    assert_eq!(cpu.memory()[0x300], 1);
+}
+
+#[test]
+fn stepping_ignores_breakpoints() {
+    let mut cpu = start();
+    let mut debugger = Debugger::new(840);
+    debugger.add_breakpoint(0x200);
+    debugger.add_breakpoint(0x202);
+    assert_eq!(debugger.step(&mut cpu, 3), StopReason::Stepped);
+    assert_eq!(cpu.pc(), 0x20E);
+}
+
+#[test]
+fn step_over_runs_calls_to_their_return() {
+    let mut cpu = start();
+    let mut debugger = Debugger::new(840);
+
+    // Anything but a call is a single step
+    assert_eq!(debugger.step_over(&mut cpu, LIMIT), StopReason::Stepped);
+    assert_eq!(cpu.pc(), 0x202);
+
+    assert_eq!(debugger.step_over(&mut cpu, LIMIT), StopReason::Stepped);
+    assert_eq!(cpu.pc(), 0x204);
+    assert_eq!(cpu.sp(), 0);
+    assert_eq!(cpu.registers()[1], 5);
+
+    // A breakpoint inside the call still stops it
+    let mut cpu = start();
+    debugger.step(&mut cpu, 1);
+    debugger.add_breakpoint(0x212);
+    assert_eq!(
+        debugger.step_over(&mut cpu, LIMIT),
+        StopReason::Breakpoint(0x212)
+    );
+    assert_eq!(cpu.sp(), 2);
+
+    // As does the limit
+    let mut cpu = start();
+    debugger.step(&mut cpu, 1);
+    assert_eq!(debugger.step_over(&mut cpu, 2), StopReason::LimitReached);
+    assert_eq!(cpu.pc(), 0x20E);
+}
+
+#[test]
+fn watchpoints_stop_after_the_change() {
+    let mut cpu = start();
+    let mut debugger = Debugger::new(840);
+    assert!(debugger.add_watchpoint(Watchpoint::Register(1)));
+    assert!(!debugger.add_watchpoint(Watchpoint::Register(1)));
+
+    let reason = debugger.continue_execution(&mut cpu, LIMIT);
+    assert_eq!(
+        reason,
+        StopReason::Watchpoint {
+            watchpoint: Watchpoint::Register(1),
+            pc: 0x20C,
+            old: 0,
+            new: 2,
+        }
+    );
+    assert_eq!(
+        reason.to_string(),
+        "watchpoint V1 changed 0x0 -> 0x2 at 0x20C"
+    );
+    assert_eq!(cpu.pc(), 0x20E);
+    assert_eq!(
+        debugger.continue_execution(&mut cpu, LIMIT),
+        StopReason::Watchpoint {
+            watchpoint: Watchpoint::Register(1),
+            pc: 0x212,
+            old: 2,
+            new: 5,
+        }
+    );
+
+    assert!(debugger.remove_watchpoint(Watchpoint::Register(1)));
+    debugger.add_watchpoint(Watchpoint::Index);
+    assert_eq!(
+        debugger.continue_execution(&mut cpu, LIMIT),
+        StopReason::Watchpoint {
+            watchpoint: Watchpoint::Index,
+            pc: 0x204,
+            old: 0,
+            new: 0x300,
+        }
+    );
+
+    debugger.remove_watchpoint(Watchpoint::Index);
+    debugger.add_watchpoint(Watchpoint::Memory(0x300));
+    assert_eq!(
+        debugger.continue_execution(&mut cpu, LIMIT),
+        StopReason::Watchpoint {
+            watchpoint: Watchpoint::Memory(0x300),
+            pc: 0x206,
+            old: 0,
+            new: 1,
+        }
+    );
+    assert_eq!(
+        debugger.watchpoints().collect::<Vec<_>>(),
+        [Watchpoint::Memory(0x300)]
+    );
+    assert_eq!(
+        debugger.continue_execution(&mut cpu, LIMIT),
+        StopReason::LimitReached
+    );
+}
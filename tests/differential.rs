@@ -0,0 +1,707 @@
+// Differential tests: random programs and machine states are run through both `CPU` and the
+// small reference interpreter below, and the two must end up in the same state after every
+// instruction. The reference is written from the instruction set documentation rather than from
+// cpu.rs and deliberately favours being obviously right over being fast, so a refactor of the
+// big match in cpu.rs that changes behaviour shows up here.
+//
+// Runs are seeded so failures can be reproduced. The seed of a failing case is printed, and
+//
+//     DIFFERENTIAL_SEED=<seed> DIFFERENTIAL_CASES=1 cargo test --test differential
+//
+// reruns just that case. DIFFERENTIAL_CASES also sets how many cases each platform runs.
+//
+// Both sides draw CXNN's numbers from the same kind of generator with the same seed, so the
+// generators themselves aren't under test here, only how CXNN uses them.
+
+use chip8::audio::Tone;
+use chip8::random::RandomMode;
+use chip8::{Chip8Error, Instruction, LoadStoreQuirk, Opcode, Platform, Quirks, StepOutcome, CPU};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const DEFAULT_SEED: u64 = 0xC8;
+const DEFAULT_CASES: u64 = 400;
+const PROGRAM_START: u16 = 0x200;
+const PROGRAM_WORDS: u16 = 32;
+const MAX_STEPS: usize = 100;
+
+// The reference interpreter. Every field mirrors part of the CPU state that can be observed
+// through its public accessors.
+#[derive(Clone)]
+struct Machine {
+    platform: Platform,
+    quirks: Quirks,
+    memory: Vec<u8>,
+    v: [u8; 16],
+    i: u16,
+    pc: u16,
+    stack: Vec<u16>,
+    delay_timer: u8,
+    sound_timer: u8,
+    keys: [bool; 16],
+    hires: bool,
+    display: Vec<u8>,
+    planes: u8,
+    pattern: Option<[u8; 16]>,
+    pitch: u8,
+    rpl: [u8; 16],
+    vblank: bool,
+    random_mode: RandomMode,
+    // Kept as the generator's state rather than the generator so the machine can be cloned
+    random_state: u64,
+}
+
+impl Machine {
+    fn width(&self) -> usize {
+        if self.hires {
+            128
+        } else {
+            64
+        }
+    }
+
+    fn height(&self) -> usize {
+        if self.hires {
+            64
+        } else {
+            32
+        }
+    }
+
+    fn superchip(&self) -> bool {
+        matches!(self.platform, Platform::SuperChip | Platform::XoChip)
+    }
+
+    fn xochip(&self) -> bool {
+        self.platform == Platform::XoChip
+    }
+
+    fn random_byte(&mut self) -> u8 {
+        let mut source = self.random_mode.source(0);
+        source.set_state(self.random_state);
+        let byte = source.next_byte();
+        self.random_state = source.state();
+        byte
+    }
+
+    fn tick(&mut self) {
+        let mut source = self.random_mode.source(0);
+        source.set_state(self.random_state);
+        source.tick();
+        self.random_state = source.state();
+        self.vblank = true;
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
+    // Runs one instruction. A fault leaves the machine exactly as it was.
+    fn step(&mut self) -> Result<StepOutcome, Chip8Error> {
+        let before = self.clone();
+        let result = self.run_one();
+        if result.is_err() {
+            *self = before;
+        }
+        result
+    }
+
+    fn check(&self, addr: usize, len: usize) -> Result<(), Chip8Error> {
+        if addr + len <= self.memory.len() {
+            Ok(())
+        } else {
+            Err(Chip8Error::MemoryOutOfBounds {
+                addr: addr.max(self.memory.len()),
+            })
+        }
+    }
+
+    fn skip(&mut self, condition: bool) {
+        if !condition {
+            return;
+        }
+        let next = self.pc as usize;
+        let long = self.xochip() && self.memory.get(next..next + 2) == Some(&[0xF0, 0x00][..]);
+        self.pc = self.pc.wrapping_add(if long { 4 } else { 2 });
+    }
+
+    fn run_one(&mut self) -> Result<StepOutcome, Chip8Error> {
+        let pc = self.pc;
+        if pc as usize + 2 > self.memory.len() {
+            return Err(Chip8Error::PcOutOfBounds { pc });
+        }
+        let word = u16::from_be_bytes([self.memory[pc as usize], self.memory[pc as usize + 1]]);
+        self.pc = pc.wrapping_add(2);
+
+        let x = (word >> 8 & 0xF) as usize;
+        let y = (word >> 4 & 0xF) as usize;
+        let n = (word & 0xF) as u8;
+        let nn = (word & 0xFF) as u8;
+        let nnn = word & 0xFFF;
+        let unknown = Err(Chip8Error::UnknownOpcode { pc, opcode: word });
+        let (schip, xo) = (self.superchip(), self.xochip());
+
+        match (word >> 12, x, y, n) {
+            (0x0, 0, 0xE, 0x0) => {
+                for pixel in &mut self.display {
+                    *pixel &= !self.planes;
+                }
+            }
+            (0x0, 0, 0xE, 0xE) => match self.stack.pop() {
+                Some(address) => self.pc = address,
+                None => return Err(Chip8Error::StackUnderflow { pc }),
+            },
+            (0x0, 0, 0xC, n) if schip => self.scroll(0, n as isize),
+            (0x0, 0, 0xD, n) if xo => self.scroll(0, -(n as isize)),
+            (0x0, 0, 0xF, 0xB) if schip => self.scroll(4, 0),
+            (0x0, 0, 0xF, 0xC) if schip => self.scroll(-4, 0),
+            (0x0, 0, 0xF, 0xD) if schip => {
+                self.pc = pc;
+                return Ok(StepOutcome::Exited);
+            }
+            (0x0, 0, 0xF, 0xE | 0xF) if schip => {
+                self.hires = n == 0xF;
+                self.display = vec![0; self.width() * self.height()];
+            }
+            (0x1, ..) => self.pc = nnn,
+            (0x2, ..) => {
+                if self.stack.len() == 16 {
+                    return Err(Chip8Error::StackOverflow { pc });
+                }
+                self.stack.push(self.pc);
+                self.pc = nnn;
+            }
+            (0x3, ..) => self.skip(self.v[x] == nn),
+            (0x4, ..) => self.skip(self.v[x] != nn),
+            (0x5, _, _, 0) => self.skip(self.v[x] == self.v[y]),
+            (0x5, _, _, 2 | 3) if xo => {
+                let registers: Vec<usize> = if x <= y {
+                    (x..=y).collect()
+                } else {
+                    (y..=x).rev().collect()
+                };
+                self.check(self.i as usize, registers.len())?;
+                for (offset, r) in registers.into_iter().enumerate() {
+                    let addr = self.i as usize + offset;
+                    if n == 2 {
+                        self.memory[addr] = self.v[r];
+                    } else {
+                        self.v[r] = self.memory[addr];
+                    }
+                }
+            }
+            (0x6, ..) => self.v[x] = nn,
+            (0x7, ..) => self.v[x] = self.v[x].wrapping_add(nn),
+            (0x8, _, _, 0) => self.v[x] = self.v[y],
+            (0x8, _, _, 1..=3) => {
+                self.v[x] = match n {
+                    1 => self.v[x] | self.v[y],
+                    2 => self.v[x] & self.v[y],
+                    _ => self.v[x] ^ self.v[y],
+                };
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
+            }
+            (0x8, _, _, 4) => {
+                let sum = self.v[x] as u16 + self.v[y] as u16;
+                self.v[x] = sum as u8;
+                self.v[0xF] = (sum > 0xFF) as u8;
+            }
+            (0x8, _, _, 5 | 7) => {
+                let (a, b) = if n == 5 {
+                    (self.v[x], self.v[y])
+                } else {
+                    (self.v[y], self.v[x])
+                };
+                self.v[x] = a.wrapping_sub(b);
+                self.v[0xF] = (a >= b) as u8;
+            }
+            (0x8, _, _, 6 | 0xE) => {
+                let source = if self.quirks.shift {
+                    self.v[x]
+                } else {
+                    self.v[y]
+                };
+                if n == 6 {
+                    self.v[x] = source >> 1;
+                    self.v[0xF] = source & 1;
+                } else {
+                    self.v[x] = source << 1;
+                    self.v[0xF] = source >> 7;
+                }
+            }
+            (0x9, _, _, 0) => self.skip(self.v[x] != self.v[y]),
+            (0xA, ..) => self.i = nnn,
+            (0xB, ..) => {
+                let offset = if self.quirks.jump {
+                    self.v[x]
+                } else {
+                    self.v[0]
+                };
+                self.pc = nnn + offset as u16;
+            }
+            (0xC, ..) => self.v[x] = self.random_byte() & nn,
+            (0xD, ..) => return self.draw(pc, x, y, n),
+            (0xE, _, 0x9, 0xE) => self.skip(self.keys[(self.v[x] & 0xF) as usize]),
+            (0xE, _, 0xA, 0x1) => self.skip(!self.keys[(self.v[x] & 0xF) as usize]),
+            (0xF, 0, 0x0, 0x0) if xo => {
+                self.check(self.pc as usize, 2)?;
+                let at = self.pc as usize;
+                self.i = u16::from_be_bytes([self.memory[at], self.memory[at + 1]]);
+                self.pc = self.pc.wrapping_add(2);
+            }
+            (0xF, _, 0x0, 0x1) if xo => self.planes = x as u8 & 0b11,
+            (0xF, 0, 0x0, 0x2) if xo => {
+                self.check(self.i as usize, 16)?;
+                let start = self.i as usize;
+                self.pattern = Some(self.memory[start..start + 16].try_into().unwrap());
+            }
+            (0xF, _, 0x0, 0x7) => self.v[x] = self.delay_timer,
+            (0xF, _, 0x0, 0xA) => match self.keys.iter().position(|&pressed| pressed) {
+                Some(key) => self.v[x] = key as u8,
+                None => {
+                    self.pc = pc;
+                    return Ok(StepOutcome::WaitingForKey);
+                }
+            },
+            (0xF, _, 0x1, 0x5) => self.delay_timer = self.v[x],
+            (0xF, _, 0x1, 0x8) => self.sound_timer = self.v[x],
+            (0xF, _, 0x1, 0xE) => self.i = self.i.wrapping_add(self.v[x] as u16),
+            (0xF, _, 0x2, 0x9) => self.i = 0x50 + 5 * (self.v[x] & 0xF) as u16,
+            (0xF, _, 0x3, 0x0) if schip => self.i = 0xA0 + 10 * (self.v[x] & 0xF) as u16,
+            (0xF, _, 0x3, 0x3) => {
+                self.check(self.i as usize, 3)?;
+                let value = self.v[x];
+                let i = self.i as usize;
+                self.memory[i..i + 3].copy_from_slice(&[value / 100, value / 10 % 10, value % 10]);
+            }
+            (0xF, _, 0x3, 0xA) if xo => self.pitch = self.v[x],
+            (0xF, _, 0x5 | 0x6, 0x5) => {
+                self.check(self.i as usize, x + 1)?;
+                let i = self.i as usize;
+                if y == 5 {
+                    self.memory[i..=i + x].copy_from_slice(&self.v[..=x]);
+                } else {
+                    self.v[..=x].copy_from_slice(&self.memory[i..=i + x]);
+                }
+                self.i = self.i.wrapping_add(match self.quirks.load_store {
+                    LoadStoreQuirk::Unchanged => 0,
+                    LoadStoreQuirk::IncrementByX => x as u16,
+                    LoadStoreQuirk::IncrementByXPlusOne => x as u16 + 1,
+                });
+            }
+            (0xF, _, 0x7, 0x5) if schip => self.rpl[..=x].copy_from_slice(&self.v[..=x]),
+            (0xF, _, 0x8, 0x5) if schip => self.v[..=x].copy_from_slice(&self.rpl[..=x]),
+            _ => return unknown,
+        }
+        Ok(StepOutcome::Executed)
+    }
+
+    fn draw(&mut self, pc: u16, x: usize, y: usize, n: u8) -> Result<StepOutcome, Chip8Error> {
+        let (width, height) = if n == 0 && self.superchip() {
+            (16, 16)
+        } else {
+            (8, n as usize)
+        };
+        let row_bytes = width / 8;
+        let plane_bytes = row_bytes * height;
+        self.check(
+            self.i as usize,
+            plane_bytes * self.planes.count_ones() as usize,
+        )?;
+
+        if self.quirks.display_wait {
+            if !self.vblank {
+                self.pc = pc;
+                return Ok(StepOutcome::WaitingForVBlank);
+            }
+            self.vblank = false;
+        }
+
+        let (screen_width, screen_height) = (self.width(), self.height());
+        let left = self.v[x] as usize % screen_width;
+        let top = self.v[y] as usize % screen_height;
+        let mut collision = false;
+        let mut sprite = self.i as usize;
+        for plane in [1, 2] {
+            if self.planes & plane == 0 {
+                continue;
+            }
+            for row in 0..height {
+                for col in 0..width {
+                    let byte = self.memory[sprite + row * row_bytes + col / 8];
+                    if byte & (0x80 >> (col % 8)) == 0 {
+                        continue;
+                    }
+                    let (px, py) = (left + col, top + row);
+                    if self.quirks.clipping && (px >= screen_width || py >= screen_height) {
+                        continue;
+                    }
+                    let pixel = (py % screen_height) * screen_width + px % screen_width;
+                    collision |= self.display[pixel] & plane != 0;
+                    self.display[pixel] ^= plane;
+                }
+            }
+            sprite += plane_bytes;
+        }
+        self.v[0xF] = collision as u8;
+        Ok(StepOutcome::Executed)
+    }
+
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let old = self.display.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x - dx, y - dy);
+                let inside = sx >= 0 && sx < width && sy >= 0 && sy < height;
+                let moved = if inside {
+                    old[(sy * width + sx) as usize] & self.planes
+                } else {
+                    0
+                };
+                let pixel = (y * width + x) as usize;
+                self.display[pixel] = old[pixel] & !self.planes | moved;
+            }
+        }
+    }
+}
+
+// Registers and other bytes are biased towards the values where arithmetic goes wrong.
+fn interesting_byte(rng: &mut StdRng) -> u8 {
+    match rng.gen_range(0..8) {
+        0 => 0,
+        1 => 1,
+        2 => 0x7F,
+        3 => 0x80,
+        4 => 0xFF,
+        _ => rng.gen(),
+    }
+}
+
+fn random_quirks(rng: &mut StdRng) -> Quirks {
+    Quirks {
+        shift: rng.gen(),
+        jump: rng.gen(),
+        load_store: [
+            LoadStoreQuirk::Unchanged,
+            LoadStoreQuirk::IncrementByX,
+            LoadStoreQuirk::IncrementByXPlusOne,
+        ][rng.gen_range(0..3)],
+        vf_reset: rng.gen(),
+        clipping: rng.gen(),
+        display_wait: rng.gen_bool(0.2),
+    }
+}
+
+// An address somewhere in the program, so jumps and calls mostly land on real instructions.
+fn program_address(rng: &mut StdRng) -> u16 {
+    PROGRAM_START + 2 * rng.gen_range(0..PROGRAM_WORDS)
+}
+
+// A random instruction from any platform, so instructions the platform lacks are covered too.
+fn random_instruction(rng: &mut StdRng) -> u16 {
+    let x = rng.gen_range(0..16u16) << 8;
+    let xy = rng.gen_range(0..0x100u16) << 4;
+    match rng.gen_range(0..24) {
+        0 => [0x00E0, 0x00EE, 0x00FB, 0x00FC, 0x00FD, 0x00FE, 0x00FF][rng.gen_range(0..7)],
+        1 => [0x00C0, 0x00D0][rng.gen_range(0..2)] | rng.gen_range(0..16),
+        2 => 0x1000 | program_address(rng),
+        3 => 0x2000 | program_address(rng),
+        4 => [0x3000, 0x4000][rng.gen_range(0..2)] | x | interesting_byte(rng) as u16,
+        5 => [0x5000, 0x5002, 0x5003, 0x9000][rng.gen_range(0..4)] | xy,
+        6 => [0x6000, 0x7000][rng.gen_range(0..2)] | x | interesting_byte(rng) as u16,
+        7..=9 => 0x8000 | xy | [0, 1, 2, 3, 4, 5, 6, 7, 0xE][rng.gen_range(0..9)],
+        10 => 0xA000 | rng.gen_range(0..0x1000),
+        11 => 0xB000 | program_address(rng),
+        12 => 0xC000 | x | interesting_byte(rng) as u16,
+        13 | 14 => 0xD000 | xy | rng.gen_range(0..16),
+        15 => [0xE09E, 0xE0A1][rng.gen_range(0..2)] | x,
+        16..=19 => {
+            let low = [
+                0x07, 0x0A, 0x15, 0x18, 0x1E, 0x29, 0x30, 0x33, 0x3A, 0x55, 0x65, 0x75, 0x85, 0x01,
+            ];
+            0xF000 | x | low[rng.gen_range(0..low.len())]
+        }
+        20 => [0xF000, 0xF002][rng.gen_range(0..2)],
+        _ => rng.gen(),
+    }
+}
+
+// A CPU and an identical reference machine with a random program and state.
+fn random_case(platform: Platform, rng: &mut StdRng) -> (CPU, Machine, Vec<u16>) {
+    let quirks = random_quirks(rng);
+    let mut cpu = CPU::with_platform(platform, quirks);
+    cpu.set_random(RandomMode::Seeded, rng.gen());
+    let memory_size = platform.memory_size();
+
+    let program: Vec<u16> = (0..PROGRAM_WORDS)
+        .map(|_| random_instruction(rng))
+        .collect();
+    let bytes: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+    cpu.write_memory(PROGRAM_START as usize, &bytes).unwrap();
+
+    // Some data after the program and at the very end of memory, for sprites, loads and stores
+    let data: Vec<u8> = (0..64).map(|_| interesting_byte(rng)).collect();
+    cpu.write_memory(0x300, &data).unwrap();
+    cpu.write_memory(memory_size - 32, &data[..32]).unwrap();
+
+    for x in 0..16 {
+        cpu.set_register(x, interesting_byte(rng));
+    }
+    cpu.set_index(match rng.gen_range(0..4) {
+        0 => 0x300 + rng.gen_range(0..32),
+        1 => (memory_size - rng.gen_range(1..24)) as u16,
+        2 => rng.gen_range(0..memory_size) as u16,
+        _ => rng.gen(),
+    });
+    // Now and then start right at the end of memory, often on an instruction that stays put
+    if rng.gen_bool(0.1) {
+        let last = match rng.gen_range(0..6) {
+            0 => 0xF00A,
+            1 => 0x00FD,
+            2 => 0xD011,
+            _ => random_instruction(rng),
+        };
+        cpu.write_memory(memory_size - 2, &u16::to_be_bytes(last))
+            .unwrap();
+        cpu.set_pc((memory_size - rng.gen_range(1..=2)) as u16);
+    }
+    let stack: Vec<u16> = (0..rng.gen_range(0..=16))
+        .map(|_| program_address(rng))
+        .collect();
+    cpu.set_stack(&stack);
+    cpu.set_delay_timer(interesting_byte(rng));
+    cpu.set_sound_timer(interesting_byte(rng));
+    for key in 0..16 {
+        if rng.gen_bool(0.1) {
+            cpu.key_press(key);
+        }
+    }
+    let lit_planes = if platform == Platform::XoChip { 3 } else { 1 };
+    for _ in 0..rng.gen_range(0..64) {
+        let (x, y) = (rng.gen_range(0..64), rng.gen_range(0..32));
+        cpu.set_display_pixel(x, y, rng.gen_range(1..=lit_planes));
+    }
+
+    let machine = Machine {
+        platform,
+        quirks,
+        memory: cpu.memory().to_vec(),
+        v: *cpu.registers(),
+        i: cpu.index(),
+        pc: cpu.pc(),
+        stack,
+        delay_timer: cpu.delay_timer(),
+        sound_timer: cpu.sound_timer(),
+        keys: std::array::from_fn(|key| cpu.is_key_pressed(key)),
+        hires: false,
+        display: cpu.framebuffer().to_vec(),
+        planes: 1,
+        pattern: None,
+        pitch: 64,
+        rpl: [0; 16],
+        vblank: false,
+        random_mode: RandomMode::Seeded,
+        random_state: RandomMode::Seeded.source(cpu.random_seed()).state(),
+    };
+    (cpu, machine, program)
+}
+
+// Everything that differs between the CPU and the reference, empty if they agree.
+fn differences(cpu: &CPU, machine: &Machine) -> Vec<String> {
+    let mut differences = Vec::new();
+    let mut check = |name: &str, ours: String, reference: String| {
+        if ours != reference {
+            differences.push(format!("{}: cpu {}, reference {}", name, ours, reference));
+        }
+    };
+    check(
+        "PC",
+        format!("{:04X}", cpu.pc()),
+        format!("{:04X}", machine.pc),
+    );
+    check(
+        "V",
+        format!("{:02X?}", cpu.registers()),
+        format!("{:02X?}", machine.v),
+    );
+    check(
+        "I",
+        format!("{:04X}", cpu.index()),
+        format!("{:04X}", machine.i),
+    );
+    check(
+        "stack",
+        format!("{:03X?}", cpu.stack()),
+        format!("{:03X?}", machine.stack),
+    );
+    check(
+        "timers",
+        format!("{} {}", cpu.delay_timer(), cpu.sound_timer()),
+        format!("{} {}", machine.delay_timer, machine.sound_timer),
+    );
+    check(
+        "hires",
+        cpu.is_hires().to_string(),
+        machine.hires.to_string(),
+    );
+    check(
+        "planes",
+        cpu.selected_planes().to_string(),
+        machine.planes.to_string(),
+    );
+    check(
+        "RPL flags",
+        format!("{:02X?}", cpu.rpl_flags()),
+        format!("{:02X?}", machine.rpl),
+    );
+    let tone = match cpu.tone() {
+        Tone::Silent => "silent".to_string(),
+        Tone::Beep => "beep".to_string(),
+        Tone::Pattern { pattern, pitch } => format!("{:02X?} at {}", pattern, pitch),
+    };
+    let expected_tone = match (machine.sound_timer > 0, machine.pattern) {
+        (false, _) => "silent".to_string(),
+        (true, None) => "beep".to_string(),
+        (true, Some(pattern)) => format!("{:02X?} at {}", pattern, machine.pitch),
+    };
+    check("tone", tone, expected_tone);
+
+    if cpu.framebuffer() != machine.display.as_slice() {
+        let pixels = cpu
+            .framebuffer()
+            .iter()
+            .zip(&machine.display)
+            .filter(|(a, b)| a != b)
+            .count();
+        differences.push(format!("framebuffer: {} pixels differ", pixels));
+    }
+    let memory = cpu.memory();
+    if memory != machine.memory.as_slice() {
+        let addr = (0..memory.len())
+            .find(|&a| memory[a] != machine.memory[a])
+            .unwrap();
+        differences.push(format!(
+            "memory: first difference at {:04X}, cpu {:02X}, reference {:02X}",
+            addr, memory[addr], machine.memory[addr]
+        ));
+    }
+    differences
+}
+
+fn listing(program: &[u16], platform: Platform) -> String {
+    program
+        .iter()
+        .enumerate()
+        .map(|(i, &word)| {
+            let mnemonic = Opcode::decode(&Instruction::from_bytes(word), platform)
+                .map_or("???".to_string(), |opcode| opcode.to_string());
+            format!(
+                "  {:04X}  {:04X}  {}\n",
+                PROGRAM_START as usize + 2 * i,
+                word,
+                mnemonic
+            )
+        })
+        .collect()
+}
+
+// Runs one random case and returns a description of the first disagreement.
+fn run_case(platform: Platform, seed: u64) -> Result<(), String> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (mut cpu, mut machine, program) = random_case(platform, &mut rng);
+    let Some((step, what)) = find_disagreement(&mut cpu, &mut machine, &mut rng) else {
+        return Ok(());
+    };
+    Err(format!(
+        "{} seed {} with {:?}, step {}: {}\nprogram:\n{}",
+        platform,
+        seed,
+        cpu.quirks(),
+        step,
+        what,
+        listing(&program, platform)
+    ))
+}
+
+fn find_disagreement(
+    cpu: &mut CPU,
+    machine: &mut Machine,
+    rng: &mut StdRng,
+) -> Option<(usize, String)> {
+    for step in 0..MAX_STEPS {
+        let pc = cpu.pc();
+        let ours = cpu.execute_instruction();
+        let reference = machine.step();
+        if ours != reference {
+            let what = format!(
+                "at {:04X} the cpu returned {:?}, the reference {:?}",
+                pc, ours, reference
+            );
+            return Some((step, what));
+        }
+        let differences = differences(cpu, machine);
+        if !differences.is_empty() {
+            let what = format!("after {:04X}:\n  {}", pc, differences.join("\n  "));
+            return Some((step, what));
+        }
+        if matches!(ours, Err(_) | Ok(StepOutcome::Exited)) {
+            break;
+        }
+
+        // The outside world: timer ticks (which end the display wait) and key presses
+        if rng.gen_bool(0.2) {
+            cpu.decrement_timers();
+            machine.tick();
+        }
+        if rng.gen_bool(0.1) {
+            let key = rng.gen_range(0..16);
+            if machine.keys[key] {
+                cpu.key_release(key);
+            } else {
+                cpu.key_press(key);
+            }
+            machine.keys[key] = !machine.keys[key];
+        }
+    }
+    None
+}
+
+fn env_number(name: &str, default: u64) -> u64 {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be a number", name)),
+        Err(_) => default,
+    }
+}
+
+fn check_platform(platform: Platform) {
+    let seed = env_number("DIFFERENTIAL_SEED", DEFAULT_SEED);
+    let cases = env_number("DIFFERENTIAL_CASES", DEFAULT_CASES);
+    for case in 0..cases {
+        if let Err(failure) = run_case(platform, seed.wrapping_add(case)) {
+            panic!("{}", failure);
+        }
+    }
+}
+
+#[test]
+fn cosmac_vip_matches_the_reference() {
+    check_platform(Platform::CosmacVip);
+}
+
+#[test]
+fn chip48_matches_the_reference() {
+    check_platform(Platform::Chip48);
+}
+
+#[test]
+fn superchip_matches_the_reference() {
+    check_platform(Platform::SuperChip);
+}
+
+#[test]
+fn xochip_matches_the_reference() {
+    check_platform(Platform::XoChip);
+}
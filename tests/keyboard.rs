@@ -0,0 +1,174 @@
+// Keymaps: the layouts, keymap files, looking key presses up and the rebinding screen.
+
+#![cfg(feature = "window")]
+
+use chip8::keyboard::{HostKey, Keymap, Layout, RebindStep, Rebinding, KEYPAD};
+use winit::event::{ElementState, KeyboardInput, ModifiersState, VirtualKeyCode};
+
+#[allow(deprecated)]
+fn press(scancode: u32, virtual_keycode: Option<VirtualKeyCode>) -> KeyboardInput {
+    KeyboardInput {
+        scancode,
+        state: ElementState::Pressed,
+        virtual_keycode,
+        modifiers: ModifiersState::empty(),
+    }
+}
+
+fn named(virtual_keycode: VirtualKeyCode) -> KeyboardInput {
+    press(0, Some(virtual_keycode))
+}
+
+const KEYMAP: &str = "
+layout positional
+# Arrow keys as well
+5 scancode:17 Up
+8 scancode:0x1F Down   # hex scancodes too
+f
+";
+
+#[test]
+fn keymap_files_replace_the_layouts_bindings() {
+    let keymap: Keymap = KEYMAP.parse().unwrap();
+    assert_eq!(
+        keymap.host_keys(0x5),
+        [HostKey::Named(VirtualKeyCode::Up), HostKey::Scancode(17)]
+    );
+    assert_eq!(
+        keymap.host_keys(0x8),
+        [HostKey::Named(VirtualKeyCode::Down), HostKey::Scancode(31)]
+    );
+    // On its own, so unbound
+    assert!(keymap.host_keys(0xF).is_empty());
+    // The rest come from the layout
+    assert_eq!(keymap.host_keys(0x1), [HostKey::Scancode(2)]);
+
+    // Written out in full and read back the same
+    let written = keymap.to_string();
+    assert!(written.starts_with("# CHIP-8 keymap\nlayout positional\n1 scancode:2\n"));
+    assert_eq!(written.parse::<Keymap>().unwrap(), keymap);
+}
+
+#[test]
+fn bad_keymap_files() {
+    let error = |text: &str| text.parse::<Keymap>().unwrap_err();
+    assert_eq!(
+        error("5 W\n10 Up"),
+        "line 2: the CHIP-8 key must be a hex digit 0-F"
+    );
+    assert_eq!(error("5 Wibble"), "line 1: unknown host key 'Wibble'");
+    assert_eq!(error("5 scancode:x"), "line 1: invalid scancode 'x'");
+    assert_eq!(
+        error("layout dvorak"),
+        "line 1: unknown layout 'dvorak', expected qwerty, positional or none"
+    );
+}
+
+#[test]
+fn keys_are_looked_up_by_name_or_scancode() {
+    let qwerty = Keymap::default();
+    assert_eq!(qwerty.key(&named(VirtualKeyCode::W)), Some(0x5));
+    assert_eq!(qwerty.key(&named(VirtualKeyCode::V)), Some(0xF));
+    assert_eq!(qwerty.key(&named(VirtualKeyCode::P)), None);
+    assert_eq!(qwerty.key(&press(17, None)), None);
+
+    // The key W is on a QWERTY keyboard, whatever it's called
+    let positional = Keymap::new(Layout::Positional);
+    assert_eq!(
+        positional.key(&press(17, Some(VirtualKeyCode::Z))),
+        Some(0x5)
+    );
+    assert_eq!(positional.key(&named(VirtualKeyCode::W)), None);
+
+    assert_eq!(
+        Keymap::new(Layout::None).key(&named(VirtualKeyCode::W)),
+        None
+    );
+}
+
+#[test]
+fn scancodes_win_over_names() {
+    let mut keymap = Keymap::new(Layout::None);
+    keymap.bind(0x1, &[HostKey::Named(VirtualKeyCode::A)]);
+    keymap.bind(0x2, &[HostKey::Scancode(30)]);
+    assert_eq!(keymap.key(&press(30, Some(VirtualKeyCode::A))), Some(0x2));
+    assert_eq!(keymap.key(&press(16, Some(VirtualKeyCode::A))), Some(0x1));
+}
+
+#[test]
+fn binding_a_host_key_moves_it() {
+    let mut keymap = Keymap::default();
+    keymap.bind(
+        0x5,
+        &[
+            HostKey::Named(VirtualKeyCode::Q),
+            HostKey::Named(VirtualKeyCode::Up),
+        ],
+    );
+    assert_eq!(keymap.key(&named(VirtualKeyCode::Q)), Some(0x5));
+    assert_eq!(keymap.key(&named(VirtualKeyCode::Up)), Some(0x5));
+    assert_eq!(keymap.key(&named(VirtualKeyCode::W)), None);
+    assert!(keymap.host_keys(0x4).is_empty());
+}
+
+#[test]
+fn rebinding_goes_through_every_key() {
+    let mut rebinding = Rebinding::new(&Keymap::new(Layout::None));
+    for (position, &key) in KEYPAD.iter().enumerate() {
+        assert_eq!(rebinding.key(), key);
+        // Keys without a name are bound by scancode
+        let scancode = 100 + position as u32;
+        assert!(matches!(
+            rebinding.press(&press(scancode, None)),
+            RebindStep::Continue
+        ));
+        if key == 0x5 {
+            // A second host key, then start again with a different one
+            rebinding.press(&named(VirtualKeyCode::Up));
+            assert!(rebinding
+                .prompt()
+                .contains(&format!("(scancode:{} Up)", scancode)));
+            rebinding.press(&named(VirtualKeyCode::Back));
+            rebinding.press(&named(VirtualKeyCode::W));
+            rebinding.press(&named(VirtualKeyCode::Up));
+        }
+
+        let step = rebinding.press(&named(VirtualKeyCode::Return));
+        if position < KEYPAD.len() - 1 {
+            assert!(matches!(step, RebindStep::Continue));
+            continue;
+        }
+        let RebindStep::Done(keymap) = step else {
+            panic!("rebinding didn't finish after the last key");
+        };
+        for (position, &key) in KEYPAD.iter().enumerate() {
+            let expected = if key == 0x5 {
+                vec![
+                    HostKey::Named(VirtualKeyCode::W),
+                    HostKey::Named(VirtualKeyCode::Up),
+                ]
+            } else {
+                vec![HostKey::Scancode(100 + position as u32)]
+            };
+            assert_eq!(keymap.host_keys(key), expected, "key {:X}", key);
+        }
+    }
+}
+
+#[test]
+fn rebinding_can_keep_keys_or_give_up() {
+    let mut rebinding = Rebinding::new(&Keymap::default());
+    // Return with nothing pressed keeps 1's keys
+    rebinding.press(&named(VirtualKeyCode::Return));
+    assert_eq!(rebinding.key(), 0x2);
+    assert_eq!(
+        rebinding.keymap().host_keys(0x1),
+        [HostKey::Named(VirtualKeyCode::Key1)]
+    );
+
+    rebinding.press(&named(VirtualKeyCode::Up));
+    assert!(matches!(
+        rebinding.press(&named(VirtualKeyCode::Escape)),
+        RebindStep::Cancelled
+    ));
+}
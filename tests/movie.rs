@@ -0,0 +1,215 @@
+// Records headless runs as movies and checks they play back to the same state, and that
+// playback notices when something doesn't line up.
+
+use std::fs;
+
+use chip8::headless::{self, KeyEvent, KeyScript, RunEnd, RunLimit};
+use chip8::movie::{Desync, Movie};
+use chip8::random::{RandomMode, RandomSource};
+use chip8::{Chip8Error, Platform, CPU};
+
+const INSTRUCTIONS_PER_SECOND: u32 = 840;
+
+fn keys(events: &[(u64, usize, bool)]) -> KeyScript {
+    KeyScript::new(
+        events
+            .iter()
+            .map(|&(frame, key, pressed)| KeyEvent {
+                frame,
+                key,
+                pressed,
+            })
+            .collect(),
+    )
+}
+
+fn breakout() -> CPU {
+    let platform = Platform::CosmacVip;
+    let mut cpu = CPU::with_platform(platform, platform.quirks());
+    cpu.set_random(RandomMode::Seeded, 1234);
+    cpu.load_rom(fs::read("roms/breakout.ch8").unwrap())
+        .unwrap();
+    cpu
+}
+
+// Breakout with the paddle moved left and right, round-tripped through the text format.
+fn recorded_breakout() -> Movie {
+    let script = keys(&[
+        (30, 4, true),
+        (45, 4, false),
+        (60, 6, true),
+        (100, 6, false),
+    ]);
+    let (summary, movie) = headless::record(
+        &mut breakout(),
+        INSTRUCTIONS_PER_SECOND,
+        RunLimit::Frames(300),
+        &script,
+        None,
+    )
+    .unwrap();
+    assert_eq!(summary.end, RunEnd::LimitReached);
+    let text = movie.to_string();
+    let parsed: Movie = text.parse().unwrap();
+    assert_eq!(parsed, movie);
+    parsed
+}
+
+fn play(movie: &Movie) -> headless::Playback {
+    let mut cpu = movie.cpu();
+    cpu.load_rom(fs::read("roms/breakout.ch8").unwrap())
+        .unwrap();
+    headless::play(&mut cpu, movie, None, None).unwrap()
+}
+
+#[test]
+fn playback_matches_the_recording() {
+    let movie = recorded_breakout();
+    assert_eq!(movie.events.len(), 4);
+    assert_eq!(movie.events[0].cycle, 30 * 14);
+
+    let playback = play(&movie);
+    assert_eq!(playback.desync, None);
+    assert_eq!(playback.matches_end, Some(true));
+    assert_eq!(playback.summary.frames, 300);
+}
+
+#[test]
+fn playback_with_different_input_doesnt_match() {
+    let mut movie = recorded_breakout();
+    movie.events.truncate(2);
+    let playback = play(&movie);
+    assert_eq!(playback.desync, None);
+    assert_eq!(playback.matches_end, Some(false));
+}
+
+#[test]
+fn playback_reports_the_first_desync() {
+    let mut movie = recorded_breakout();
+    movie.events[2].cycle += 1;
+    movie.events[3].cycle += 1;
+    let playback = play(&movie);
+    assert_eq!(
+        playback.desync,
+        Some(Desync {
+            frame: 60,
+            recorded_cycle: 60 * 14 + 1,
+            cycle: 60 * 14,
+        })
+    );
+}
+
+#[test]
+fn playback_stops_where_the_program_exited() {
+    let platform = Platform::SuperChip;
+    let mut cpu = CPU::with_platform(platform, platform.quirks());
+    // Wait for a key, then exit part way through the frame
+    cpu.load_rom(vec![0xF0, 0x0A, 0x00, 0xFD]).unwrap();
+    let script = keys(&[(10, 0xA, true), (12, 0xA, false)]);
+    let (summary, movie) = headless::record(
+        &mut cpu,
+        INSTRUCTIONS_PER_SECOND,
+        RunLimit::Frames(100),
+        &script,
+        None,
+    )
+    .unwrap();
+    assert_eq!(summary.end, RunEnd::Exited);
+
+    let mut cpu = movie.cpu();
+    cpu.load_rom(vec![0xF0, 0x0A, 0x00, 0xFD]).unwrap();
+    let playback = headless::play(&mut cpu, &movie, None, None).unwrap();
+    assert_eq!(playback.summary, summary);
+    assert_eq!(playback.matches_end, Some(true));
+}
+
+struct Constant;
+
+impl RandomSource for Constant {
+    fn next_byte(&mut self) -> u8 {
+        4
+    }
+
+    fn state(&self) -> u64 {
+        0
+    }
+
+    fn set_state(&mut self, _state: u64) {}
+}
+
+#[test]
+fn custom_random_sources_cant_be_recorded() {
+    let mut cpu = breakout();
+    cpu.set_random_source(Box::new(Constant));
+    assert!(Movie::new(&cpu, INSTRUCTIONS_PER_SECOND).is_err());
+}
+
+#[test]
+fn invalid_movies_are_rejected() {
+    let movie = recorded_breakout().to_string();
+
+    let without_seed: String = movie
+        .lines()
+        .filter(|line| !line.starts_with("random"))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    assert_eq!(
+        without_seed.parse::<Movie>().unwrap_err(),
+        "the movie has no random line"
+    );
+
+    let bad_key = movie.replace("press 4", "press 10");
+    assert!(bad_key
+        .parse::<Movie>()
+        .unwrap_err()
+        .ends_with("key must be a hex digit 0-F"));
+
+    let future = movie.replace("version 1", "version 2");
+    assert_eq!(
+        future.parse::<Movie>().unwrap_err(),
+        "line 2: unsupported movie version 2"
+    );
+}
+
+#[test]
+fn zero_instructions_per_second_is_rejected() {
+    // Otherwise a cycle limit would never be reached
+    let limit = RunLimit::Cycles(100);
+    assert_eq!(
+        headless::run(&mut breakout(), 0, limit, &KeyScript::default(), None),
+        Err(Chip8Error::ZeroInstructionsPerSecond)
+    );
+    assert_eq!(
+        headless::record(&mut breakout(), 0, limit, &KeyScript::default(), None).unwrap_err(),
+        "instructions per second must be at least 1"
+    );
+
+    let mut movie = recorded_breakout();
+    movie.instructions_per_second = 0;
+    let mut cpu = movie.cpu();
+    cpu.load_rom(fs::read("roms/breakout.ch8").unwrap())
+        .unwrap();
+    assert_eq!(
+        headless::play(&mut cpu, &movie, Some(limit), None),
+        Err(Chip8Error::ZeroInstructionsPerSecond)
+    );
+    assert_eq!(cpu.pc(), 0x200);
+}
+
+#[test]
+fn events_are_sorted_when_parsed() {
+    let movie = recorded_breakout();
+    let text = movie.to_string();
+    let (keys, others): (Vec<&str>, Vec<&str>) =
+        text.lines().partition(|line| line.starts_with("key"));
+    // Key lines in reverse, after the end line
+    let shuffled: String = others
+        .iter()
+        .chain(keys.iter().rev())
+        .map(|line| format!("{}\n", line))
+        .collect();
+    assert_ne!(shuffled, text);
+    let parsed: Movie = shuffled.parse().unwrap();
+    assert_eq!(parsed, movie);
+    assert_eq!(play(&parsed).matches_end, Some(true));
+}
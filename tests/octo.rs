@@ -0,0 +1,164 @@
+// The Octo compiler: small programs compiled to the exact bytes Octo would produce, and the
+// errors for programs it can't compile.
+
+use chip8::assembler::AsmError;
+use chip8::octo;
+use chip8::Platform;
+
+fn compile(source: &str) -> Vec<u8> {
+    octo::compile(source, Platform::XoChip).unwrap_or_else(|err| panic!("{}", err))
+}
+
+fn error(source: &str) -> AsmError {
+    octo::compile(source, Platform::XoChip).unwrap_err()
+}
+
+#[test]
+fn labels_and_forward_references() {
+    let source = "
+: main
+  i := ball
+  sprite v0 v1 2
+  jump main
+: ball
+  0x81 0x42
+";
+    assert_eq!(
+        compile(source),
+        [0xA2, 0x06, 0xD0, 0x12, 0x12, 0x00, 0x81, 0x42]
+    );
+
+    // Without `main` first, the program starts with a jump to it. A label on its own calls it,
+    // whether or not it's defined yet.
+    let source = "
+: data 1 2
+: main
+  jump data
+: helper
+  :pointer data
+  helper
+  later
+: later
+";
+    assert_eq!(
+        compile(source),
+        [0x12, 0x04, 0x01, 0x02, 0x12, 0x02, 0x02, 0x02, 0x22, 0x06, 0x22, 0x0C]
+    );
+}
+
+#[test]
+fn constants_and_aliases() {
+    let source = "
+:const SPEED 3
+:alias x v4
+: main
+  x := SPEED
+  x += { SPEED * 2 }
+  x -= 1
+  va := x
+";
+    // Anything before `main` means the program starts with a jump to it
+    assert_eq!(
+        compile(source),
+        [0x12, 0x02, 0x64, 0x03, 0x74, 0x06, 0x74, 0xFF, 0x8A, 0x40]
+    );
+}
+
+#[test]
+fn loops() {
+    let source = "
+: main
+  loop
+    v0 += 1
+    while v0 != 10
+  again
+";
+    assert_eq!(
+        compile(source),
+        [0x70, 0x01, 0x40, 0x0A, 0x12, 0x08, 0x12, 0x00]
+    );
+}
+
+#[test]
+fn conditionals() {
+    let source = "
+: main
+  if v0 == 5 then v1 := 2
+  if v0 key then v1 := 3
+";
+    assert_eq!(
+        compile(source),
+        [0x40, 0x05, 0x61, 0x02, 0xE0, 0xA1, 0x61, 0x03]
+    );
+
+    // Comparisons other than == and != go through VF
+    let source = "
+: main
+  if v0 > v1 begin
+    v2 := 1
+  else
+    v2 := 2
+  end
+";
+    assert_eq!(
+        compile(source),
+        [0x8F, 0x10, 0x8F, 0x05, 0x3F, 0x00, 0x12, 0x0C, 0x62, 0x01, 0x12, 0x0E, 0x62, 0x02]
+    );
+}
+
+#[test]
+fn macros_and_calc() {
+    let source = "
+:macro add-twice reg n { reg += n reg += n }
+:calc HALF { 10 / 2 }
+# No precedence, right to left
+:calc EIGHT { 2 * 3 + 1 }
+: main
+  add-twice v3 HALF
+  :byte { EIGHT }
+";
+    assert_eq!(compile(source), [0x12, 0x02, 0x73, 0x05, 0x73, 0x05, 0x08]);
+}
+
+#[test]
+fn errors_point_at_the_statement() {
+    let err = error(": main\n  jump nowhere");
+    assert_eq!(err.to_string(), "<input>:2:3: 'nowhere' is not defined");
+
+    let message = |source: &str| error(source).message;
+    assert_eq!(message("v0 := 1"), "this program is missing a 'main' label");
+    assert_eq!(message(": main : main"), "'main' is already defined");
+    assert_eq!(message(": main again"), "'again' without 'loop'");
+    assert_eq!(message(": main loop"), "'loop' without a matching 'again'");
+    assert_eq!(message(": main else"), "'else' without 'if ... begin'");
+    assert_eq!(
+        message(": main if v0 == 1 begin"),
+        "'begin' without a matching 'end'"
+    );
+    assert_eq!(
+        message(": main if v0 == 1 v1 := 2"),
+        "expected 'then' or 'begin'"
+    );
+    assert_eq!(
+        message(": main v0 := 256"),
+        "256 is out of range -128..=255"
+    );
+    assert_eq!(message(": main v0 :="), "unexpected end of file");
+    assert_eq!(message(": main v0 += v16"), "'v16' is not defined");
+    assert_eq!(
+        message(": main sprite v0 vx 1"),
+        "expected a register, found 'vx'"
+    );
+    assert_eq!(message(":calc X { } : main"), "empty expression");
+    assert_eq!(
+        message(":macro m { m } : main m"),
+        "too many macro expansions, is a macro recursive?"
+    );
+}
+
+#[test]
+fn instructions_are_checked_against_the_platform() {
+    assert_eq!(compile(": main plane 1"), [0xF1, 0x01]);
+    let err = octo::compile(": main plane 1", Platform::CosmacVip).unwrap_err();
+    assert!(err.message.ends_with("isn't available on vip"), "{}", err);
+}
@@ -0,0 +1,881 @@
+// One or more tests per instruction: put the CPU in a known state, run a single instruction and
+// check everything it should (and shouldn't) have changed.
+
+use chip8::audio::Tone;
+use chip8::opcode::Opcode;
+use chip8::random::RandomMode;
+use chip8::{Chip8Error, Instruction, LoadStoreQuirk, Platform, Quirks, StepOutcome, CPU};
+
+// Sprites are drawn from here in the tests
+const SPRITE: u16 = 0x300;
+
+fn cpu() -> CPU {
+    CPU::new(Quirks::default())
+}
+
+fn cpu_for(platform: Platform) -> CPU {
+    CPU::with_platform(platform, platform.quirks())
+}
+
+// Writes `word` at the PC and executes it.
+fn execute(cpu: &mut CPU, word: u16) -> Result<StepOutcome, Chip8Error> {
+    let pc = cpu.pc() as usize;
+    cpu.write_memory(pc, &word.to_be_bytes()).unwrap();
+    cpu.execute_instruction()
+}
+
+fn step(cpu: &mut CPU, word: u16) {
+    assert_eq!(
+        execute(cpu, word),
+        Ok(StepOutcome::Executed),
+        "{:04X}",
+        word
+    );
+}
+
+fn lit(cpu: &CPU, x: usize, y: usize) -> bool {
+    cpu.get_display_pixel(cpu.get_display_pixel_index(x, y))
+}
+
+fn lit_count(cpu: &CPU) -> usize {
+    cpu.framebuffer()
+        .iter()
+        .filter(|&&pixel| pixel != 0)
+        .count()
+}
+
+// Draws an 8 pixel wide `rows` sprite at (x, y) using V0 and V1.
+fn draw(cpu: &mut CPU, x: u8, y: u8, rows: &[u8]) -> Result<StepOutcome, Chip8Error> {
+    cpu.write_memory(SPRITE as usize, rows).unwrap();
+    cpu.set_index(SPRITE);
+    cpu.set_register(0, x);
+    cpu.set_register(1, y);
+    execute(cpu, 0xD010 | rows.len() as u16)
+}
+
+#[test]
+fn cls_clears_the_display() {
+    let mut cpu = cpu();
+    cpu.set_display_pixel(0, 0, 1);
+    cpu.set_display_pixel(63, 31, 1);
+    step(&mut cpu, 0x00E0);
+    assert_eq!(lit_count(&cpu), 0);
+    assert_eq!(cpu.pc(), 0x202);
+}
+
+#[test]
+fn ret_pops_the_stack() {
+    let mut cpu = cpu();
+    cpu.set_stack(&[0x400, 0x500]);
+    step(&mut cpu, 0x00EE);
+    assert_eq!(cpu.pc(), 0x500);
+    assert_eq!(cpu.stack(), &[0x400]);
+}
+
+#[test]
+fn ret_with_an_empty_stack_faults() {
+    let mut cpu = cpu();
+    assert_eq!(
+        execute(&mut cpu, 0x00EE),
+        Err(Chip8Error::StackUnderflow { pc: 0x200 })
+    );
+    assert_eq!(cpu.pc(), 0x200);
+}
+
+#[test]
+fn jp_sets_the_pc() {
+    let mut cpu = cpu();
+    step(&mut cpu, 0x1ABC);
+    assert_eq!(cpu.pc(), 0xABC);
+}
+
+#[test]
+fn call_pushes_the_return_address() {
+    let mut cpu = cpu();
+    step(&mut cpu, 0x2400);
+    assert_eq!(cpu.pc(), 0x400);
+    assert_eq!(cpu.stack(), &[0x202]);
+    assert_eq!(cpu.sp(), 1);
+}
+
+#[test]
+fn call_with_a_full_stack_faults() {
+    let mut cpu = cpu();
+    cpu.set_stack(&[0x300; 16]);
+    assert_eq!(
+        execute(&mut cpu, 0x2400),
+        Err(Chip8Error::StackOverflow { pc: 0x200 })
+    );
+    assert_eq!(cpu.pc(), 0x200);
+    assert_eq!(cpu.sp(), 16);
+}
+
+#[test]
+fn se_byte_skips_when_equal() {
+    let mut cpu = cpu();
+    cpu.set_register(3, 0x42);
+    step(&mut cpu, 0x3342);
+    assert_eq!(cpu.pc(), 0x204);
+    step(&mut cpu, 0x3343);
+    assert_eq!(cpu.pc(), 0x206);
+}
+
+#[test]
+fn sne_byte_skips_when_not_equal() {
+    let mut cpu = cpu();
+    cpu.set_register(3, 0x42);
+    step(&mut cpu, 0x4343);
+    assert_eq!(cpu.pc(), 0x204);
+    step(&mut cpu, 0x4342);
+    assert_eq!(cpu.pc(), 0x206);
+}
+
+#[test]
+fn se_reg_and_sne_reg_compare_registers() {
+    let mut cpu = cpu();
+    cpu.set_register(1, 7);
+    cpu.set_register(2, 7);
+    step(&mut cpu, 0x5120);
+    assert_eq!(cpu.pc(), 0x204);
+    step(&mut cpu, 0x9120);
+    assert_eq!(cpu.pc(), 0x206);
+
+    cpu.set_register(2, 8);
+    step(&mut cpu, 0x5120);
+    assert_eq!(cpu.pc(), 0x208);
+    step(&mut cpu, 0x9120);
+    assert_eq!(cpu.pc(), 0x20C);
+}
+
+#[test]
+fn ld_byte_sets_a_register() {
+    let mut cpu = cpu();
+    step(&mut cpu, 0x6A5C);
+    assert_eq!(cpu.registers()[0xA], 0x5C);
+}
+
+#[test]
+fn add_byte_wraps_without_touching_vf() {
+    let mut cpu = cpu();
+    cpu.set_register(0, 0xFF);
+    cpu.set_register(0xF, 0x55);
+    step(&mut cpu, 0x7002);
+    assert_eq!(cpu.registers()[0], 0x01);
+    assert_eq!(cpu.registers()[0xF], 0x55);
+}
+
+#[test]
+fn ld_reg_copies_a_register() {
+    let mut cpu = cpu();
+    cpu.set_register(4, 0x99);
+    step(&mut cpu, 0x8340);
+    assert_eq!(cpu.registers()[3], 0x99);
+    assert_eq!(cpu.registers()[4], 0x99);
+}
+
+#[test]
+fn logic_ops() {
+    for (word, expected) in [(0x8011, 0b1110), (0x8012, 0b1000), (0x8013, 0b0110)] {
+        let mut cpu = cpu();
+        cpu.set_register(0, 0b1100);
+        cpu.set_register(1, 0b1010);
+        cpu.set_register(0xF, 0x55);
+        step(&mut cpu, word);
+        assert_eq!(cpu.registers()[0], expected, "{:04X}", word);
+        assert_eq!(cpu.registers()[0xF], 0x55, "{:04X} changed VF", word);
+    }
+}
+
+#[test]
+fn logic_ops_reset_vf_with_the_quirk() {
+    for word in [0x8011, 0x8012, 0x8013] {
+        let mut cpu = cpu_for(Platform::CosmacVip);
+        cpu.set_register(0xF, 0x55);
+        step(&mut cpu, word);
+        assert_eq!(cpu.registers()[0xF], 0, "{:04X}", word);
+    }
+}
+
+#[test]
+fn add_reg_sets_the_carry() {
+    let mut cpu = cpu();
+    cpu.set_register(0, 0xF0);
+    cpu.set_register(1, 0x20);
+    step(&mut cpu, 0x8014);
+    assert_eq!(cpu.registers()[0], 0x10);
+    assert_eq!(cpu.registers()[0xF], 1);
+
+    step(&mut cpu, 0x8014);
+    assert_eq!(cpu.registers()[0], 0x30);
+    assert_eq!(cpu.registers()[0xF], 0);
+}
+
+#[test]
+fn add_reg_into_vf_keeps_the_flag() {
+    // The flag is written after the result, so it wins
+    let mut cpu = cpu();
+    cpu.set_register(0xF, 0x80);
+    cpu.set_register(1, 0x80);
+    step(&mut cpu, 0x8F14);
+    assert_eq!(cpu.registers()[0xF], 1);
+
+    cpu.set_register(0xF, 0x01);
+    cpu.set_register(1, 0x02);
+    step(&mut cpu, 0x8F14);
+    assert_eq!(cpu.registers()[0xF], 0);
+}
+
+#[test]
+fn add_reg_from_vf_uses_the_old_value() {
+    let mut cpu = cpu();
+    cpu.set_register(0, 0x01);
+    cpu.set_register(0xF, 0xFF);
+    step(&mut cpu, 0x80F4);
+    assert_eq!(cpu.registers()[0], 0x00);
+    assert_eq!(cpu.registers()[0xF], 1);
+}
+
+#[test]
+fn sub_sets_vf_when_there_is_no_borrow() {
+    for (a, b, result, flag) in [(5, 3, 2, 1), (3, 5, 0xFE, 0), (4, 4, 0, 1)] {
+        let mut cpu = cpu();
+        cpu.set_register(0, a);
+        cpu.set_register(1, b);
+        step(&mut cpu, 0x8015);
+        assert_eq!(cpu.registers()[0], result, "{} - {}", a, b);
+        assert_eq!(cpu.registers()[0xF], flag, "{} - {}", a, b);
+    }
+}
+
+#[test]
+fn subn_subtracts_the_other_way() {
+    for (a, b, result, flag) in [(3, 5, 2, 1), (5, 3, 0xFE, 0), (4, 4, 0, 1)] {
+        let mut cpu = cpu();
+        cpu.set_register(0, a);
+        cpu.set_register(1, b);
+        step(&mut cpu, 0x8017);
+        assert_eq!(cpu.registers()[0], result, "{} =- {}", a, b);
+        assert_eq!(cpu.registers()[0xF], flag, "{} =- {}", a, b);
+    }
+}
+
+#[test]
+fn sub_into_vf_keeps_the_flag() {
+    let mut cpu = cpu();
+    cpu.set_register(0xF, 3);
+    cpu.set_register(1, 5);
+    step(&mut cpu, 0x8F15);
+    assert_eq!(cpu.registers()[0xF], 0);
+}
+
+#[test]
+fn shr_shifts_vx_in_place() {
+    let mut cpu = cpu();
+    cpu.set_register(0, 0b101);
+    cpu.set_register(1, 0xFF);
+    step(&mut cpu, 0x8016);
+    assert_eq!(cpu.registers()[0], 0b10);
+    assert_eq!(cpu.registers()[0xF], 1);
+
+    step(&mut cpu, 0x8016);
+    assert_eq!(cpu.registers()[0], 0b1);
+    assert_eq!(cpu.registers()[0xF], 0);
+}
+
+#[test]
+fn shl_shifts_vx_in_place() {
+    let mut cpu = cpu();
+    cpu.set_register(0, 0x81);
+    step(&mut cpu, 0x801E);
+    assert_eq!(cpu.registers()[0], 0x02);
+    assert_eq!(cpu.registers()[0xF], 1);
+
+    cpu.set_register(0, 0x41);
+    step(&mut cpu, 0x801E);
+    assert_eq!(cpu.registers()[0], 0x82);
+    assert_eq!(cpu.registers()[0xF], 0);
+}
+
+#[test]
+fn shifts_read_vy_without_the_quirk() {
+    let mut cpu = cpu_for(Platform::CosmacVip);
+    cpu.set_register(0, 0xFF);
+    cpu.set_register(1, 0x81);
+    step(&mut cpu, 0x8016);
+    assert_eq!(cpu.registers()[0], 0x40);
+    assert_eq!(cpu.registers()[0xF], 1);
+
+    step(&mut cpu, 0x801E);
+    assert_eq!(cpu.registers()[0], 0x02);
+    assert_eq!(cpu.registers()[0xF], 1);
+    assert_eq!(cpu.registers()[1], 0x81);
+}
+
+#[test]
+fn shifts_into_vf_keep_the_flag() {
+    let mut cpu = cpu();
+    cpu.set_register(0xF, 0x02);
+    step(&mut cpu, 0x8F06);
+    assert_eq!(cpu.registers()[0xF], 0);
+
+    cpu.set_register(0xF, 0x80);
+    step(&mut cpu, 0x8F0E);
+    assert_eq!(cpu.registers()[0xF], 1);
+}
+
+#[test]
+fn ld_i_sets_the_index() {
+    let mut cpu = cpu();
+    step(&mut cpu, 0xA123);
+    assert_eq!(cpu.index(), 0x123);
+}
+
+#[test]
+fn jp_v0_adds_v0() {
+    let mut cpu = cpu();
+    cpu.set_register(0, 4);
+    cpu.set_register(3, 8);
+    step(&mut cpu, 0xB300);
+    assert_eq!(cpu.pc(), 0x304);
+}
+
+#[test]
+fn jp_v0_adds_vx_with_the_quirk() {
+    let mut cpu = cpu_for(Platform::SuperChip);
+    cpu.set_register(0, 4);
+    cpu.set_register(3, 8);
+    step(&mut cpu, 0xB300);
+    assert_eq!(cpu.pc(), 0x308);
+}
+
+#[test]
+fn rnd_masks_the_random_number() {
+    let mut cpu = cpu();
+    cpu.set_register(0, 0xFF);
+    step(&mut cpu, 0xC000);
+    assert_eq!(cpu.registers()[0], 0);
+
+    for _ in 0..100 {
+        step(&mut cpu, 0xC00F);
+        assert!(cpu.registers()[0] <= 0x0F);
+    }
+}
+
+// The first `count` numbers CXFF gives
+fn random_numbers(cpu: &mut CPU, count: usize) -> Vec<u8> {
+    (0..count)
+        .map(|_| {
+            step(cpu, 0xC0FF);
+            cpu.registers()[0]
+        })
+        .collect()
+}
+
+#[test]
+fn rnd_is_reproducible_from_the_seed() {
+    let numbers = |seed| {
+        let mut cpu = cpu();
+        cpu.set_random(RandomMode::Seeded, seed);
+        random_numbers(&mut cpu, 16)
+    };
+    assert_eq!(numbers(1), numbers(1));
+    assert_ne!(numbers(1), numbers(2));
+}
+
+#[test]
+fn save_states_carry_on_the_random_sequence() {
+    let mut cpu = cpu();
+    cpu.set_random(RandomMode::Seeded, 7);
+    random_numbers(&mut cpu, 5);
+    let state = cpu.save_state();
+    let expected = random_numbers(&mut cpu, 8);
+
+    let mut restored = self::cpu();
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.random_mode(), Some(RandomMode::Seeded));
+    assert_eq!(restored.random_seed(), 7);
+    assert_eq!(random_numbers(&mut restored, 8), expected);
+}
+
+#[test]
+fn drw_xors_the_sprite_and_reports_collisions() {
+    let mut cpu = cpu();
+    assert_eq!(
+        draw(&mut cpu, 2, 3, &[0xF0, 0x90]),
+        Ok(StepOutcome::Executed)
+    );
+    assert_eq!(lit_count(&cpu), 6);
+    assert!(lit(&cpu, 2, 3) && lit(&cpu, 5, 3) && lit(&cpu, 2, 4) && lit(&cpu, 5, 4));
+    assert!(!lit(&cpu, 3, 4));
+    assert_eq!(cpu.registers()[0xF], 0);
+
+    draw(&mut cpu, 2, 3, &[0xF0, 0x90]).unwrap();
+    assert_eq!(lit_count(&cpu), 0);
+    assert_eq!(cpu.registers()[0xF], 1);
+}
+
+#[test]
+fn drw_without_overlap_clears_vf() {
+    let mut cpu = cpu();
+    draw(&mut cpu, 0, 0, &[0x80]).unwrap();
+    cpu.set_register(0xF, 0x55);
+    draw(&mut cpu, 1, 0, &[0x80]).unwrap();
+    assert_eq!(cpu.registers()[0xF], 0);
+    assert_eq!(lit_count(&cpu), 2);
+}
+
+#[test]
+fn drw_clips_at_the_edges() {
+    let mut cpu = cpu();
+    draw(&mut cpu, 60, 30, &[0xFF, 0xFF, 0xFF]).unwrap();
+    assert_eq!(lit_count(&cpu), 4 * 2);
+    assert!(lit(&cpu, 63, 31));
+    assert!(!lit(&cpu, 0, 30) && !lit(&cpu, 60, 0));
+}
+
+#[test]
+fn drw_wraps_at_the_edges_without_clipping() {
+    let mut cpu = cpu_for(Platform::XoChip);
+    draw(&mut cpu, 60, 30, &[0xFF, 0xFF, 0xFF]).unwrap();
+    assert_eq!(lit_count(&cpu), 8 * 3);
+    assert!(lit(&cpu, 63, 31) && lit(&cpu, 3, 31) && lit(&cpu, 0, 0) && lit(&cpu, 60, 0));
+    assert!(!lit(&cpu, 4, 0));
+}
+
+#[test]
+fn drw_wraps_the_starting_position() {
+    let mut cpu = cpu();
+    draw(&mut cpu, 64 + 2, 32 + 1, &[0x80]).unwrap();
+    assert!(lit(&cpu, 2, 1));
+    assert_eq!(lit_count(&cpu), 1);
+}
+
+#[test]
+fn drw_past_the_end_of_memory_faults() {
+    let mut cpu = cpu();
+    cpu.set_index(0xFFE);
+    assert_eq!(
+        execute(&mut cpu, 0xD013),
+        Err(Chip8Error::MemoryOutOfBounds { addr: 0x1000 })
+    );
+    assert_eq!(cpu.pc(), 0x200);
+    assert_eq!(lit_count(&cpu), 0);
+}
+
+#[test]
+fn drw_waits_for_vblank_with_the_quirk() {
+    let mut cpu = cpu_for(Platform::CosmacVip);
+    assert_eq!(
+        draw(&mut cpu, 0, 0, &[0x80]),
+        Ok(StepOutcome::WaitingForVBlank)
+    );
+    assert_eq!(cpu.pc(), 0x200);
+    assert_eq!(lit_count(&cpu), 0);
+
+    cpu.decrement_timers();
+    assert_eq!(cpu.execute_instruction(), Ok(StepOutcome::Executed));
+    assert_eq!(lit_count(&cpu), 1);
+}
+
+#[test]
+fn skp_and_sknp_check_the_key_in_vx() {
+    let mut cpu = cpu();
+    cpu.set_register(2, 0xA);
+    step(&mut cpu, 0xE29E);
+    assert_eq!(cpu.pc(), 0x202);
+    step(&mut cpu, 0xE2A1);
+    assert_eq!(cpu.pc(), 0x206);
+
+    cpu.key_press(0xA);
+    step(&mut cpu, 0xE29E);
+    assert_eq!(cpu.pc(), 0x20A);
+    step(&mut cpu, 0xE2A1);
+    assert_eq!(cpu.pc(), 0x20C);
+}
+
+#[test]
+fn skp_uses_the_low_nibble_of_vx() {
+    let mut cpu = cpu();
+    cpu.set_register(2, 0x1A);
+    cpu.key_press(0xA);
+    step(&mut cpu, 0xE29E);
+    assert_eq!(cpu.pc(), 0x204);
+}
+
+#[test]
+fn timers_load_and_store() {
+    let mut cpu = cpu();
+    cpu.set_register(0, 30);
+    step(&mut cpu, 0xF015);
+    step(&mut cpu, 0xF018);
+    assert_eq!(cpu.delay_timer(), 30);
+    assert_eq!(cpu.sound_timer(), 30);
+    assert!(cpu.is_sound_playing());
+
+    cpu.decrement_timers();
+    step(&mut cpu, 0xF107);
+    assert_eq!(cpu.registers()[1], 29);
+}
+
+#[test]
+fn ld_vx_k_blocks_until_a_key_is_pressed() {
+    let mut cpu = cpu();
+    cpu.set_register(0, 0x55);
+    assert_eq!(execute(&mut cpu, 0xF00A), Ok(StepOutcome::WaitingForKey));
+    assert_eq!(execute(&mut cpu, 0xF00A), Ok(StepOutcome::WaitingForKey));
+    assert_eq!(cpu.pc(), 0x200);
+    assert_eq!(cpu.registers()[0], 0x55);
+
+    cpu.key_press(7);
+    step(&mut cpu, 0xF00A);
+    assert_eq!(cpu.registers()[0], 7);
+    assert_eq!(cpu.pc(), 0x202);
+}
+
+// The PC has wrapped round to 0 by the time the last word of XO-CHIP memory runs
+#[test]
+fn waiting_at_the_end_of_memory_stays_put() {
+    let mut cpu = cpu_for(Platform::XoChip);
+    cpu.write_memory(0xFFFE, &[0xF0, 0x0A]).unwrap();
+    cpu.set_pc(0xFFFE);
+    assert_eq!(cpu.execute_instruction(), Ok(StepOutcome::WaitingForKey));
+    assert_eq!(cpu.pc(), 0xFFFE);
+}
+
+#[test]
+fn add_i_adds_vx_without_touching_vf() {
+    let mut cpu = cpu();
+    cpu.set_index(0x100);
+    cpu.set_register(3, 0x20);
+    step(&mut cpu, 0xF31E);
+    assert_eq!(cpu.index(), 0x120);
+
+    // I is 16 bits, so it goes past 0xFFF rather than wrapping or setting VF
+    cpu.set_index(0xFFF);
+    cpu.set_register(3, 1);
+    cpu.set_register(0xF, 0x55);
+    step(&mut cpu, 0xF31E);
+    assert_eq!(cpu.index(), 0x1000);
+    assert_eq!(cpu.registers()[0xF], 0x55);
+
+    cpu.set_index(0xFFFF);
+    step(&mut cpu, 0xF31E);
+    assert_eq!(cpu.index(), 0);
+}
+
+#[test]
+fn ld_f_points_at_the_font() {
+    let mut cpu = cpu();
+    cpu.set_register(0, 0xA);
+    step(&mut cpu, 0xF029);
+    assert_eq!(cpu.index(), 0x50 + 5 * 0xA);
+    let memory = cpu.memory();
+    assert_eq!(
+        &memory[cpu.index() as usize..][..5],
+        &[0xF0, 0x90, 0xF0, 0x90, 0x90]
+    );
+
+    cpu.set_register(0, 0x1A);
+    step(&mut cpu, 0xF029);
+    assert_eq!(cpu.index(), 0x50 + 5 * 0xA);
+}
+
+#[test]
+fn ld_b_stores_decimal_digits() {
+    let mut cpu = cpu();
+    cpu.set_index(0x400);
+    cpu.set_register(5, 254);
+    step(&mut cpu, 0xF533);
+    assert_eq!(&cpu.memory()[0x400..0x403], &[2, 5, 4]);
+    assert_eq!(cpu.index(), 0x400);
+}
+
+#[test]
+fn ld_b_at_the_end_of_memory() {
+    let mut cpu = cpu();
+    cpu.set_register(5, 123);
+    cpu.set_index(0xFFD);
+    step(&mut cpu, 0xF533);
+    assert_eq!(&cpu.memory()[0xFFD..], &[1, 2, 3]);
+
+    cpu.write_memory(0xFFD, &[0, 0, 0]).unwrap();
+    cpu.set_pc(0x200);
+    cpu.set_index(0xFFE);
+    assert_eq!(
+        execute(&mut cpu, 0xF533),
+        Err(Chip8Error::MemoryOutOfBounds { addr: 0x1000 })
+    );
+    assert_eq!(&cpu.memory()[0xFFD..], &[0, 0, 0]);
+    assert_eq!(cpu.pc(), 0x200);
+}
+
+#[test]
+fn store_and_load_registers() {
+    let mut cpu = cpu();
+    for x in 0..16 {
+        cpu.set_register(x, x as u8 * 3);
+    }
+    cpu.set_index(0x400);
+    step(&mut cpu, 0xF355);
+    assert_eq!(&cpu.memory()[0x400..0x405], &[0, 3, 6, 9, 0]);
+    assert_eq!(cpu.index(), 0x400);
+
+    cpu.write_memory(0x400, &[0xA0, 0xA1, 0xA2, 0xA3]).unwrap();
+    step(&mut cpu, 0xF265);
+    assert_eq!(&cpu.registers()[..4], &[0xA0, 0xA1, 0xA2, 9]);
+}
+
+#[test]
+fn store_and_load_increment_i_with_the_quirks() {
+    for (quirk, increment) in [
+        (LoadStoreQuirk::Unchanged, 0),
+        (LoadStoreQuirk::IncrementByX, 3),
+        (LoadStoreQuirk::IncrementByXPlusOne, 4),
+    ] {
+        for word in [0xF355, 0xF365] {
+            let mut cpu = CPU::new(Quirks {
+                load_store: quirk,
+                ..Quirks::default()
+            });
+            cpu.set_index(0x400);
+            step(&mut cpu, word);
+            assert_eq!(cpu.index(), 0x400 + increment, "{:04X} {:?}", word, quirk);
+        }
+    }
+}
+
+#[test]
+fn store_past_the_end_of_memory_faults() {
+    let mut cpu = cpu();
+    cpu.set_index(0xFFE);
+    assert_eq!(
+        execute(&mut cpu, 0xF255),
+        Err(Chip8Error::MemoryOutOfBounds { addr: 0x1000 })
+    );
+    assert_eq!(&cpu.memory()[0xFFE..], &[0, 0]);
+}
+
+#[test]
+fn unknown_opcodes_fault() {
+    for word in [0x0123, 0x5121, 0x8008, 0xE000, 0xF0FF] {
+        let mut cpu = cpu();
+        assert_eq!(
+            execute(&mut cpu, word),
+            Err(Chip8Error::UnknownOpcode {
+                pc: 0x200,
+                opcode: word
+            })
+        );
+        assert_eq!(cpu.pc(), 0x200);
+    }
+}
+
+#[test]
+fn superchip_opcodes_need_superchip() {
+    for word in [
+        0x00FF, 0x00FE, 0x00FD, 0x00C1, 0x00FB, 0x00FC, 0xF030, 0xF075, 0xF085,
+    ] {
+        let mut cpu = cpu();
+        assert!(
+            matches!(
+                execute(&mut cpu, word),
+                Err(Chip8Error::UnknownOpcode { .. })
+            ),
+            "{:04X}",
+            word
+        );
+    }
+}
+
+#[test]
+fn pc_past_the_end_of_memory_faults() {
+    let mut cpu = cpu();
+    cpu.set_pc(0xFFF);
+    assert_eq!(
+        cpu.execute_instruction(),
+        Err(Chip8Error::PcOutOfBounds { pc: 0xFFF })
+    );
+}
+
+#[test]
+fn high_and_low_switch_resolution_and_clear() {
+    let mut cpu = cpu_for(Platform::SuperChip);
+    cpu.set_display_pixel(0, 0, 1);
+    step(&mut cpu, 0x00FF);
+    assert!(cpu.is_hires());
+    assert_eq!((cpu.display_width(), cpu.display_height()), (128, 64));
+    assert_eq!(lit_count(&cpu), 0);
+
+    cpu.set_display_pixel(127, 63, 1);
+    step(&mut cpu, 0x00FE);
+    assert!(!cpu.is_hires());
+    assert_eq!(cpu.framebuffer().len(), 64 * 32);
+    assert_eq!(lit_count(&cpu), 0);
+}
+
+#[test]
+fn exit_leaves_the_pc_on_the_instruction() {
+    let mut cpu = cpu_for(Platform::SuperChip);
+    assert_eq!(execute(&mut cpu, 0x00FD), Ok(StepOutcome::Exited));
+    assert_eq!(cpu.pc(), 0x200);
+}
+
+#[test]
+fn scrolling() {
+    let mut cpu = cpu_for(Platform::XoChip);
+    cpu.set_display_pixel(10, 10, 1);
+    step(&mut cpu, 0x00C3);
+    assert!(lit(&cpu, 10, 13));
+    step(&mut cpu, 0x00D1);
+    assert!(lit(&cpu, 10, 12));
+    step(&mut cpu, 0x00FB);
+    assert!(lit(&cpu, 14, 12));
+    step(&mut cpu, 0x00FC);
+    step(&mut cpu, 0x00FC);
+    assert!(lit(&cpu, 6, 12));
+    assert_eq!(lit_count(&cpu), 1);
+
+    // Pixels scrolled off the edge are lost
+    step(&mut cpu, 0x00CF);
+    step(&mut cpu, 0x00CF);
+    step(&mut cpu, 0x00CF);
+    assert_eq!(lit_count(&cpu), 0);
+}
+
+#[test]
+fn drw_16x16_on_superchip() {
+    let mut cpu = cpu_for(Platform::SuperChip);
+    step(&mut cpu, 0x00FF);
+    let sprite = [0xFF; 32];
+    cpu.write_memory(SPRITE as usize, &sprite).unwrap();
+    cpu.set_index(SPRITE);
+    cpu.set_register(0, 100);
+    cpu.set_register(1, 40);
+    step(&mut cpu, 0xD010);
+    assert_eq!(lit_count(&cpu), 256);
+    assert!(lit(&cpu, 100, 40) && lit(&cpu, 115, 55));
+    assert_eq!(cpu.registers()[0xF], 0);
+}
+
+#[test]
+fn ld_hf_points_at_the_large_font() {
+    let mut cpu = cpu_for(Platform::SuperChip);
+    cpu.set_register(0, 3);
+    step(&mut cpu, 0xF030);
+    assert_eq!(cpu.index(), 0xA0 + 10 * 3);
+}
+
+#[test]
+fn rpl_flags_save_and_load() {
+    let mut cpu = cpu_for(Platform::SuperChip);
+    for x in 0..4 {
+        cpu.set_register(x, 0x10 + x as u8);
+    }
+    step(&mut cpu, 0xF275);
+    assert_eq!(&cpu.rpl_flags()[..4], &[0x10, 0x11, 0x12, 0]);
+
+    for x in 0..4 {
+        cpu.set_register(x, 0);
+    }
+    step(&mut cpu, 0xF185);
+    assert_eq!(&cpu.registers()[..3], &[0x10, 0x11, 0]);
+}
+
+#[test]
+fn register_ranges_save_and_load() {
+    let mut cpu = cpu_for(Platform::XoChip);
+    for x in 0..16 {
+        cpu.set_register(x, x as u8);
+    }
+    cpu.set_index(0x400);
+    step(&mut cpu, 0x5242);
+    assert_eq!(&cpu.memory()[0x400..0x404], &[2, 3, 4, 0]);
+    // Backwards when x > y
+    step(&mut cpu, 0x5422);
+    assert_eq!(&cpu.memory()[0x400..0x403], &[4, 3, 2]);
+    assert_eq!(cpu.index(), 0x400);
+
+    cpu.write_memory(0x400, &[0xA, 0xB]).unwrap();
+    step(&mut cpu, 0x5783);
+    assert_eq!(&cpu.registers()[7..9], &[0xA, 0xB]);
+}
+
+#[test]
+fn ld_long_i_reads_the_next_word() {
+    let mut cpu = cpu_for(Platform::XoChip);
+    cpu.write_memory(0x202, &[0xAB, 0xCD]).unwrap();
+    step(&mut cpu, 0xF000);
+    assert_eq!(cpu.index(), 0xABCD);
+    assert_eq!(cpu.pc(), 0x204);
+}
+
+#[test]
+fn skips_step_over_ld_long_i() {
+    let mut cpu = cpu_for(Platform::XoChip);
+    cpu.write_memory(0x202, &[0xF0, 0x00, 0x12, 0x34]).unwrap();
+    step(&mut cpu, 0x3000);
+    assert_eq!(cpu.pc(), 0x206);
+}
+
+#[test]
+fn plane_selects_what_is_drawn() {
+    let mut cpu = cpu_for(Platform::XoChip);
+    step(&mut cpu, 0xF201);
+    assert_eq!(cpu.selected_planes(), 2);
+    draw(&mut cpu, 0, 0, &[0x80]).unwrap();
+    assert_eq!(cpu.framebuffer()[0], 2);
+
+    // Both planes: the data for plane 2 follows plane 1's
+    step(&mut cpu, 0xF301);
+    cpu.write_memory(SPRITE as usize, &[0x80, 0x40]).unwrap();
+    step(&mut cpu, 0xD011);
+    assert_eq!(&cpu.framebuffer()[..2], &[3, 2]);
+
+    // Clearing only affects the selected planes
+    step(&mut cpu, 0xF101);
+    step(&mut cpu, 0x00E0);
+    assert_eq!(&cpu.framebuffer()[..2], &[2, 2]);
+}
+
+#[test]
+fn audio_pattern_and_pitch() {
+    let mut cpu = cpu_for(Platform::XoChip);
+    let pattern: Vec<u8> = (0..16).collect();
+    cpu.write_memory(0x400, &pattern).unwrap();
+    cpu.set_index(0x400);
+    step(&mut cpu, 0xF002);
+    cpu.set_register(0, 100);
+    step(&mut cpu, 0xF03A);
+    cpu.set_sound_timer(2);
+    match cpu.tone() {
+        Tone::Pattern {
+            pattern: played,
+            pitch,
+        } => {
+            assert_eq!(&played[..], &pattern[..]);
+            assert_eq!(pitch, 100);
+        }
+        other => panic!("expected a pattern, got {:?}", other),
+    }
+}
+
+// Every word that decodes on a platform encodes back to itself and can be rebuilt from its
+// mnemonic and operands.
+#[test]
+fn opcodes_round_trip() {
+    for platform in Platform::ALL {
+        for word in 0..=0xFFFF {
+            let Some(opcode) = Opcode::decode(&Instruction::from_bytes(word), platform) else {
+                continue;
+            };
+            assert_eq!(opcode.encode(), word, "{} on {}", opcode, platform);
+            assert_eq!(
+                Opcode::from_parts(opcode.mnemonic(), &opcode.operands()),
+                Ok(opcode),
+                "{:04X} on {}",
+                word,
+                platform
+            );
+        }
+    }
+}
@@ -0,0 +1,38 @@
+// Palettes given on the command line, by name or as colours.
+
+use chip8::palette::Palette;
+
+#[test]
+fn palettes_can_be_named() {
+    assert_eq!("default".parse::<Palette>(), Ok(Palette::default()));
+    assert_eq!(
+        "Amber".parse::<Palette>(),
+        Ok(Palette::named("amber").unwrap())
+    );
+}
+
+#[test]
+fn two_colours_light_every_plane_the_same() {
+    let palette: Palette = "#102030,ffeedd".parse().unwrap();
+    assert_eq!(palette.color(0), [0x10, 0x20, 0x30]);
+    for planes in 1..4 {
+        assert_eq!(palette.color(planes), [0xFF, 0xEE, 0xDD]);
+    }
+}
+
+#[test]
+fn four_colours_set_each_plane() {
+    let palette: Palette = "000000,ff0000,00ff00,0000ff".parse().unwrap();
+    assert_eq!(palette.color(2), [0x00, 0xFF, 0x00]);
+    assert_eq!(palette.color(3), [0x00, 0x00, 0xFF]);
+}
+
+#[test]
+fn bad_palettes_are_rejected() {
+    assert!("neon".parse::<Palette>().is_err());
+    assert!("12345,ffffff".parse::<Palette>().is_err());
+    assert_eq!(
+        "000000,111111,222222".parse::<Palette>(),
+        Err("a palette needs 2 or 4 colours".to_string())
+    );
+}
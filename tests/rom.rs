@@ -0,0 +1,105 @@
+// ROM loading: where programs go, the ROMs that don't fit and patching memory afterwards.
+
+use chip8::cpu::{DEFAULT_LOAD_ADDRESS, ETI_660_LOAD_ADDRESS};
+use chip8::movie::Movie;
+use chip8::{Chip8Error, Platform, RomError, CPU};
+
+fn cpu_for(platform: Platform) -> CPU {
+    CPU::with_platform(platform, platform.quirks())
+}
+
+fn memory(cpu: &CPU, address: u16, length: usize) -> &[u8] {
+    &cpu.memory()[address as usize..address as usize + length]
+}
+
+#[test]
+fn roms_load_at_0x200() {
+    let mut cpu = cpu_for(Platform::CosmacVip);
+    cpu.load_rom(vec![0x12, 0x34, 0x56]).unwrap();
+    assert_eq!(cpu.pc(), DEFAULT_LOAD_ADDRESS);
+    assert_eq!(memory(&cpu, 0x200, 3), [0x12, 0x34, 0x56]);
+}
+
+#[test]
+fn eti_660_roms_load_at_0x600() {
+    let mut cpu = cpu_for(Platform::CosmacVip);
+    cpu.load_rom_at(vec![0x12, 0x34], ETI_660_LOAD_ADDRESS)
+        .unwrap();
+    assert_eq!(cpu.pc(), 0x600);
+    assert_eq!(memory(&cpu, 0x600, 2), [0x12, 0x34]);
+    assert_eq!(memory(&cpu, 0x200, 2), [0, 0]);
+}
+
+#[test]
+fn roms_must_fit_in_the_platforms_memory() {
+    let mut cpu = cpu_for(Platform::CosmacVip);
+    cpu.load_rom(vec![0xAA; 0x1000 - 0x200]).unwrap();
+    assert_eq!(cpu.memory()[0xFFF], 0xAA);
+
+    let mut cpu = cpu_for(Platform::CosmacVip);
+    assert_eq!(
+        cpu.load_rom(vec![0xAA; 0x1000 - 0x200 + 1]),
+        Err(RomError::TooLarge {
+            size: 0xE01,
+            max: 0xE00,
+            address: 0x200,
+        })
+    );
+    assert_eq!(
+        cpu.load_rom_at(vec![0xAA; 0x1000 - 0x600 + 1], ETI_660_LOAD_ADDRESS),
+        Err(RomError::TooLarge {
+            size: 0xA01,
+            max: 0xA00,
+            address: 0x600,
+        })
+    );
+    // Nothing was written and the program still starts at 0x200
+    assert_eq!(cpu.memory()[0x200], 0);
+    assert_eq!(cpu.rom_hash(), [0; 20]);
+    assert_eq!(cpu.pc(), 0x200);
+
+    // XO-CHIP has 64 KiB
+    let mut cpu = cpu_for(Platform::XoChip);
+    cpu.load_rom(vec![0xAA; 0x1000]).unwrap();
+}
+
+#[test]
+fn empty_roms_and_bad_addresses_are_rejected() {
+    let mut cpu = cpu_for(Platform::CosmacVip);
+    assert_eq!(cpu.load_rom(Vec::new()), Err(RomError::Empty));
+    assert_eq!(
+        cpu.load_rom_at(vec![0x00, 0xE0], 0x100),
+        Err(RomError::InvalidLoadAddress { address: 0x100 })
+    );
+    assert_eq!(
+        cpu.load_rom_at(vec![0x00, 0xE0], 0x1000),
+        Err(RomError::InvalidLoadAddress { address: 0x1000 })
+    );
+}
+
+#[test]
+fn memory_writes_must_fit() {
+    let mut cpu = cpu_for(Platform::CosmacVip);
+    cpu.write_memory(0xFFE, &[0x12, 0x34]).unwrap();
+    assert_eq!(memory(&cpu, 0xFFE, 2), [0x12, 0x34]);
+    assert_eq!(
+        cpu.write_memory(0xFFF, &[0x56, 0x78]),
+        Err(Chip8Error::MemoryOutOfBounds { addr: 0x1000 })
+    );
+    assert_eq!(
+        cpu.write_memory(usize::MAX, &[0x56]),
+        Err(Chip8Error::MemoryOutOfBounds { addr: usize::MAX })
+    );
+    assert_eq!(memory(&cpu, 0xFFE, 2), [0x12, 0x34]);
+}
+
+#[test]
+fn movies_remember_the_load_address() {
+    let mut cpu = cpu_for(Platform::CosmacVip);
+    cpu.load_rom_at(vec![0x16, 0x00], ETI_660_LOAD_ADDRESS)
+        .unwrap();
+    let movie = Movie::new(&cpu, 840).unwrap();
+    let text = movie.to_string();
+    assert!(text.contains("load 0x600\n"));
+    assert_eq!(text.parse::<Movie>().unwrap().load_address, 0x600);
+}
@@ -0,0 +1,113 @@
+// Save states: saving and loading picks up exactly where the machine left off, and data that
+// isn't a save state for the loaded ROM is rejected without touching the machine.
+
+use std::fs;
+
+use chip8::random::RandomMode;
+use chip8::savestate::{SaveStateError, MAGIC};
+use chip8::{Platform, CPU};
+
+fn breakout() -> CPU {
+    let platform = Platform::CosmacVip;
+    let mut cpu = CPU::with_platform(platform, platform.quirks());
+    cpu.set_random(RandomMode::Seeded, 1234);
+    cpu.load_rom(fs::read("roms/breakout.ch8").unwrap())
+        .unwrap();
+    cpu
+}
+
+fn run_frames(cpu: &mut CPU, frames: usize) {
+    for _ in 0..frames {
+        for _ in 0..14 {
+            cpu.execute_instruction().unwrap();
+        }
+        cpu.decrement_timers();
+    }
+}
+
+#[test]
+fn loading_a_save_state_carries_on_from_it() {
+    let mut cpu = breakout();
+    run_frames(&mut cpu, 60);
+    cpu.key_press(0x6);
+    cpu.set_sound_timer(30);
+    let state = cpu.save_state();
+
+    let mut restored = breakout();
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.save_state(), state);
+    assert!(restored.is_key_pressed(0x6));
+    assert_eq!(restored.sound_timer(), 30);
+
+    // Including the random number generator
+    run_frames(&mut cpu, 120);
+    run_frames(&mut restored, 120);
+    assert_eq!(restored.save_state(), cpu.save_state());
+
+    // Loading into the machine that saved it rewinds it
+    cpu.load_state(&state).unwrap();
+    assert_eq!(cpu.save_state(), state);
+}
+
+#[test]
+fn version_1_states_keep_the_generator() {
+    let mut cpu = breakout();
+    run_frames(&mut cpu, 60);
+    let mut state = cpu.save_state();
+    // Version 1 has no generator fields on the end
+    state.truncate(state.len() - 17);
+    state[4..6].copy_from_slice(&1u16.to_le_bytes());
+
+    let mut restored = breakout();
+    restored.set_random(RandomMode::Seeded, 99);
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.pc(), cpu.pc());
+    assert_eq!(restored.registers(), cpu.registers());
+    assert_eq!(restored.random_seed(), 99);
+}
+
+#[test]
+fn bad_save_states_are_rejected() {
+    let mut cpu = breakout();
+    run_frames(&mut cpu, 60);
+    let state = cpu.save_state();
+    assert_eq!(&state[..4], MAGIC);
+
+    let mut target = breakout();
+    let before = target.save_state();
+    let mut load = |state: &[u8]| target.load_state(state).unwrap_err();
+
+    let mut wrong_magic = state.clone();
+    wrong_magic[0] = b'X';
+    assert_eq!(load(&wrong_magic), SaveStateError::NotASaveState);
+    assert_eq!(load(&[]), SaveStateError::NotASaveState);
+
+    let mut wrong_version = state.clone();
+    wrong_version[4..6].copy_from_slice(&3u16.to_le_bytes());
+    assert_eq!(load(&wrong_version), SaveStateError::UnsupportedVersion(3));
+    wrong_version[4..6].copy_from_slice(&0u16.to_le_bytes());
+    assert_eq!(load(&wrong_version), SaveStateError::UnsupportedVersion(0));
+
+    for length in MAGIC.len()..state.len() {
+        assert_eq!(
+            load(&state[..length]),
+            SaveStateError::Truncated,
+            "cut to {} bytes",
+            length
+        );
+    }
+
+    let mut bad_stack_pointer = state.clone();
+    bad_stack_pointer[85] = 17;
+    assert_eq!(
+        load(&bad_stack_pointer),
+        SaveStateError::Invalid("stack pointer")
+    );
+
+    // None of that changed the machine
+    assert_eq!(target.save_state(), before);
+
+    let mut pong = CPU::with_platform(Platform::CosmacVip, Platform::CosmacVip.quirks());
+    pong.load_rom(fs::read("roms/pong.ch8").unwrap()).unwrap();
+    assert_eq!(pong.load_state(&state), Err(SaveStateError::RomMismatch));
+}
@@ -0,0 +1,88 @@
+// The scheduler: 60 Hz ticks from elapsed time, dropping a backlog, spreading instructions
+// across ticks and forgetting time spent paused.
+
+use std::time::{Duration, Instant};
+
+use chip8::scheduler::{Catchup, Scheduler, MAX_CATCH_UP_TICKS, TIMER_HZ};
+
+const TICK: Duration = Duration::from_nanos(1_000_000_000 / TIMER_HZ as u64);
+
+fn catchup(ticks: u32, dropped_ticks: u32) -> Catchup {
+    Catchup {
+        ticks,
+        dropped_ticks,
+    }
+}
+
+#[test]
+fn ticks_are_due_every_sixtieth_of_a_second() {
+    let mut scheduler = Scheduler::new(840, Instant::now());
+    assert_eq!(scheduler.advance(TICK / 2), catchup(0, 0));
+    // Time that isn't a whole tick yet is kept for the next update
+    assert_eq!(scheduler.advance(TICK / 2), catchup(1, 0));
+    assert_eq!(scheduler.advance(TICK * 3), catchup(3, 0));
+    assert_eq!(scheduler.advance(Duration::ZERO), catchup(0, 0));
+}
+
+#[test]
+fn ticks_beyond_the_catch_up_limit_are_dropped() {
+    let mut scheduler = Scheduler::new(840, Instant::now());
+    assert_eq!(
+        scheduler.advance(TICK * MAX_CATCH_UP_TICKS),
+        catchup(MAX_CATCH_UP_TICKS, 0)
+    );
+    assert_eq!(
+        scheduler.advance(Duration::from_secs(1)),
+        catchup(MAX_CATCH_UP_TICKS, 60 - MAX_CATCH_UP_TICKS)
+    );
+    // The dropped ticks are gone, not run later
+    assert_eq!(scheduler.advance(TICK), catchup(1, 0));
+}
+
+#[test]
+fn instructions_are_spread_evenly_across_ticks() {
+    let cycles = |scheduler: &mut Scheduler, ticks: usize| -> Vec<u32> {
+        (0..ticks)
+            .map(|_| scheduler.cycles_for_next_tick())
+            .collect()
+    };
+
+    let mut scheduler = Scheduler::new(840, Instant::now());
+    assert_eq!(cycles(&mut scheduler, 3), [14, 14, 14]);
+
+    // 100 a second is 1⅔ a tick
+    let mut scheduler = Scheduler::new(100, Instant::now());
+    assert_eq!(cycles(&mut scheduler, 6), [1, 2, 2, 1, 2, 2]);
+    let second = cycles(&mut scheduler, 60);
+    assert_eq!(second.iter().sum::<u32>(), 100);
+
+    // Fewer than one a tick still adds up over a second
+    let mut scheduler = Scheduler::new(30, Instant::now());
+    assert_eq!(cycles(&mut scheduler, 4), [0, 1, 0, 1]);
+
+    // Changing the speed starts the spreading again
+    let mut scheduler = Scheduler::new(100, Instant::now());
+    scheduler.cycles_for_next_tick();
+    scheduler.set_instructions_per_second(90);
+    assert_eq!(scheduler.instructions_per_second(), 90);
+    assert_eq!(cycles(&mut scheduler, 4), [1, 2, 1, 2]);
+}
+
+#[test]
+fn reset_forgets_pending_time() {
+    let start = Instant::now();
+    let mut scheduler = Scheduler::new(840, start);
+    assert_eq!(scheduler.update(start + TICK / 2), catchup(0, 0));
+    assert_eq!(scheduler.next_tick_at(), start + TICK);
+
+    // Paused for a minute
+    let resumed = start + Duration::from_secs(60);
+    scheduler.reset(resumed);
+    assert_eq!(scheduler.next_tick_at(), resumed + TICK);
+    // Half a tick after resuming is only half a tick, not a whole one with the time from before
+    assert_eq!(scheduler.update(resumed + TICK / 2), catchup(0, 0));
+    assert_eq!(scheduler.update(resumed + TICK), catchup(1, 0));
+
+    // A clock that goes backwards doesn't add time
+    assert_eq!(scheduler.update(start), catchup(0, 0));
+}
@@ -0,0 +1,131 @@
+// Trace formats: the text and export lines, and the binary format read back by chip8-tracediff.
+
+use std::io::ErrorKind;
+
+use chip8::trace::{
+    self, TraceFormat, TraceRecord, TraceWriter, Tracer, MAGIC, RECORD_SIZE, VERSION,
+};
+use chip8::{Platform, Quirks, CPU};
+
+fn record() -> TraceRecord {
+    TraceRecord {
+        pc: 0x0204,
+        opcode: 0x6A12,
+        registers: [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0x12, 0xBB, 0xCC, 0xDD,
+            0xEE, 0x01,
+        ],
+        index: 0x0ABC,
+        sp: 3,
+        delay_timer: 0x3C,
+        sound_timer: 0x05,
+    }
+}
+
+// Everything `TraceWriter` wrote for `records` in `format`.
+fn write(format: TraceFormat, records: &[TraceRecord]) -> Vec<u8> {
+    let cpu = CPU::new(Quirks::default());
+    let mut bytes = Vec::new();
+    let mut writer = TraceWriter::new(&mut bytes, format, Platform::CosmacVip);
+    for record in records {
+        writer.record(record, &cpu);
+    }
+    writer.finish().unwrap();
+    drop(writer);
+    bytes
+}
+
+#[test]
+fn text_lines() {
+    assert_eq!(
+        record().text(Platform::CosmacVip),
+        "0204 6A12 LD VA, 0x12       00 11 22 33 44 55 66 77 88 99 12 BB CC DD EE 01  0ABC 3  3C 05"
+    );
+    // Words that don't decode on the platform
+    let data = TraceRecord {
+        opcode: 0xF000,
+        ..record()
+    };
+    assert!(data
+        .text(Platform::CosmacVip)
+        .starts_with("0204 F000 DW 0xF000         00 11"));
+    assert_eq!(
+        String::from_utf8(write(TraceFormat::Text, &[record()])).unwrap(),
+        format!("{}\n", record().text(Platform::CosmacVip))
+    );
+}
+
+#[test]
+fn export_lines() {
+    assert_eq!(
+        record().export(&[0x80, 0x00, 0xFF]),
+        "0204 6A12 00 11 22 33 44 55 66 77 88 99 12 BB CC DD EE 01 0ABC 03 3C 05 8000FF"
+    );
+
+    // The framebuffer is one bit per pixel, leftmost pixel in the high bit
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.set_display_pixel(0, 0, 1);
+    cpu.set_display_pixel(9, 31, 1);
+    let framebuffer = trace::pack_framebuffer(&cpu);
+    assert_eq!(framebuffer.len(), 64 * 32 / 8);
+    assert_eq!(framebuffer[0], 0x80);
+    assert_eq!(framebuffer[31 * 8 + 1], 0x40);
+    assert_eq!(
+        framebuffer
+            .iter()
+            .map(|byte| byte.count_ones())
+            .sum::<u32>(),
+        2
+    );
+
+    let export = String::from_utf8(write(TraceFormat::Export, &[record()])).unwrap();
+    assert!(export.ends_with(&format!(" 05 {}\n", "00".repeat(256))));
+}
+
+#[test]
+fn binary_traces_round_trip() {
+    let bytes = record().to_bytes();
+    assert_eq!(bytes.len(), RECORD_SIZE);
+    assert_eq!(&bytes[..4], [0x04, 0x02, 0x12, 0x6A]);
+    assert_eq!(TraceRecord::from_bytes(&bytes), record());
+
+    let second = TraceRecord {
+        pc: 0x0206,
+        sp: 0,
+        ..record()
+    };
+    let trace = write(TraceFormat::Binary, &[record(), second]);
+    assert_eq!(&trace[..4], MAGIC);
+    assert_eq!(trace[4..6], VERSION.to_le_bytes());
+    assert_eq!(trace.len(), 6 + 2 * RECORD_SIZE);
+    assert_eq!(trace::read_binary(&trace[..]).unwrap(), [record(), second]);
+
+    // A trace with no records
+    assert_eq!(
+        trace::read_binary(&write(TraceFormat::Binary, &[])[..]).unwrap(),
+        []
+    );
+}
+
+#[test]
+fn bad_binary_traces() {
+    let trace = write(TraceFormat::Binary, &[record()]);
+    let error = |bytes: &[u8]| trace::read_binary(bytes).unwrap_err();
+
+    let mut bad_magic = trace.clone();
+    bad_magic[0] = b'X';
+    assert_eq!(error(&bad_magic).to_string(), "not a binary trace");
+
+    let mut bad_version = trace.clone();
+    bad_version[4..6].copy_from_slice(&2u16.to_le_bytes());
+    assert_eq!(
+        error(&bad_version).to_string(),
+        "unsupported trace version 2"
+    );
+
+    assert_eq!(
+        error(&trace[..trace.len() - 1]).to_string(),
+        "trace ends part way through a record"
+    );
+    assert_eq!(error(&trace[..3]).kind(), ErrorKind::UnexpectedEof);
+}
@@ -0,0 +1,253 @@
+// Comparing traces: column specs, reading reference traces, lining them up with ours and finding
+// the first instruction where they disagree.
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use chip8::assembler;
+use chip8::trace::{self, TraceRecord, Tracer};
+use chip8::tracediff::{self, ColumnFormat, Difference, Field, Step};
+use chip8::{Quirks, CPU};
+
+const PROGRAM: &str = "
+        LD V0, 5
+        LD F, V0
+        LD V1, 1
+        DRW V1, V1, 5
+        ADD V0, 1
+        LD I, 0x300
+spin:   JP spin
+";
+const STEPS: usize = 8;
+
+type Records = Rc<RefCell<Vec<(TraceRecord, Vec<u8>)>>>;
+
+struct Collect(Records);
+
+impl Tracer for Collect {
+    fn record(&mut self, record: &TraceRecord, cpu: &CPU) {
+        self.0
+            .borrow_mut()
+            .push((*record, trace::pack_framebuffer(cpu)));
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Each instruction PROGRAM runs, with the framebuffer after it.
+fn records() -> Vec<(TraceRecord, Vec<u8>)> {
+    // Without the display wait, so DRW doesn't wait for a vblank
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_rom(assembler::assemble(PROGRAM).unwrap()).unwrap();
+    let records = Records::default();
+    cpu.set_tracer(Box::new(Collect(records.clone())));
+    for _ in 0..STEPS {
+        cpu.execute_instruction().unwrap();
+    }
+    records.take()
+}
+
+// The export format, which is also what other emulators are compared in.
+fn export(records: &[(TraceRecord, Vec<u8>)]) -> Vec<String> {
+    records
+        .iter()
+        .map(|(record, framebuffer)| record.export(framebuffer))
+        .collect()
+}
+
+fn parse_export(lines: &[String]) -> Vec<Step> {
+    tracediff::parse_text(&lines.join("\n"), &ColumnFormat::export()).unwrap()
+}
+
+// Replaces the whitespace separated column `column` of `line`.
+fn replace_column(line: &str, column: usize, value: &str) -> String {
+    let mut columns: Vec<&str> = line.split_whitespace().collect();
+    columns[column] = value;
+    columns.join(" ")
+}
+
+#[test]
+fn matching_traces_have_no_divergence() {
+    let records = records();
+    let ours = parse_export(&export(&records));
+    assert_eq!(ours.len(), STEPS);
+    assert_eq!(tracediff::align(&ours, &ours), Some(0));
+    assert_eq!(tracediff::first_divergence(&ours, &ours), None);
+
+    // A binary trace of the same run. It has no framebuffer, so that isn't compared.
+    let mut binary = trace::MAGIC.to_vec();
+    binary.extend(trace::VERSION.to_le_bytes());
+    for (record, _) in &records {
+        binary.extend(record.to_bytes());
+    }
+    let binary = tracediff::parse(&binary, &ColumnFormat::export()).unwrap();
+    assert_eq!(binary[0].line, 1);
+    assert_eq!(binary[0].framebuffer, None);
+    assert_eq!(tracediff::first_divergence(&binary, &ours), None);
+
+    // A reference that started earlier, or logged fewer columns
+    let mut reference = vec!["0000 0000 -".to_string(), "0100 1200 -".to_string()];
+    reference.extend(
+        records.iter().map(|(record, _)| {
+            format!("PC:{:04X} op=0x{:04X} # comment", record.pc, record.opcode)
+        }),
+    );
+    let reference =
+        tracediff::parse_text(&reference.join("\n"), &"pc,op,skip".parse().unwrap()).unwrap();
+    let start = tracediff::align(&ours, &reference).unwrap();
+    assert_eq!(start, 2);
+    assert_eq!(
+        tracediff::first_divergence(&ours, &reference[start..]),
+        None
+    );
+}
+
+#[test]
+fn divergence_on_a_register() {
+    let mut lines = export(&records());
+    // V1 (column 3) after LD V1, 1
+    lines[2] = replace_column(&lines[2], 3, "02");
+    let divergence =
+        tracediff::first_divergence(&parse_export(&export(&records())), &parse_export(&lines))
+            .unwrap();
+    assert_eq!(divergence.position, 2);
+    assert_eq!(
+        divergence.differences,
+        [Difference {
+            field: "V1".to_string(),
+            ours: "01".to_string(),
+            reference: "02".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn divergence_on_the_index_register() {
+    let ours = parse_export(&export(&records()));
+    let mut lines = export(&records());
+    // I (column 18) after LD F, V0, and every line after it
+    for line in &mut lines[1..] {
+        *line = replace_column(line, 18, "0000");
+    }
+    let divergence = tracediff::first_divergence(&ours, &parse_export(&lines)).unwrap();
+    assert_eq!(divergence.position, 1);
+    assert_eq!(divergence.differences.len(), 1);
+    assert_eq!(divergence.differences[0].field, "I");
+    assert_eq!(divergence.differences[0].reference, "0000");
+}
+
+#[test]
+fn divergence_on_the_framebuffer() {
+    let records = records();
+    let ours = parse_export(&export(&records));
+    let mut lines = export(&records);
+    // An extra pixel in the top left corner from DRW on
+    let mut framebuffer = records[3].1.clone();
+    framebuffer[0] |= 0x80;
+    let packed: String = framebuffer
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    for line in &mut lines[3..] {
+        *line = replace_column(line, 22, &packed);
+    }
+
+    let divergence = tracediff::first_divergence(&ours, &parse_export(&lines)).unwrap();
+    assert_eq!(divergence.position, 3);
+    assert_eq!(
+        divergence.differences,
+        [Difference {
+            field: "framebuffer".to_string(),
+            ours: "256 bytes".to_string(),
+            reference: "256 bytes, 1 pixels differ".to_string(),
+        }]
+    );
+
+    let picture = tracediff::framebuffers_side_by_side(&records[3].1, &framebuffer);
+    let rows: Vec<&str> = picture.lines().collect();
+    assert_eq!(rows.len(), 32);
+    assert!(rows[0].starts_with(&format!("{}  X", ".".repeat(64))));
+    // The 5 drawn at (1, 1) is the same on both sides
+    assert_eq!(&rows[1][..6], ".####.");
+    assert_eq!(&rows[1][66..72], ".####.");
+}
+
+#[test]
+fn state_before_traces_are_converted_to_state_after() {
+    let records = records();
+    let ours = parse_export(&export(&records));
+
+    // Each line has the instruction with the state before it ran: the previous line's state
+    let mut before = Vec::new();
+    let mut previous = TraceRecord {
+        pc: 0,
+        opcode: 0,
+        registers: [0; 16],
+        index: 0,
+        sp: 0,
+        delay_timer: 0,
+        sound_timer: 0,
+    };
+    for (record, _) in &records {
+        before.push(TraceRecord {
+            pc: record.pc,
+            opcode: record.opcode,
+            ..previous
+        });
+        previous = *record;
+    }
+    let before: Vec<String> = before.iter().map(|record| record.export(&[])).collect();
+    let format: ColumnFormat = "pc,op,v0-vf,i,sp,dt,st".parse().unwrap();
+    let before = tracediff::parse_text(&before.join("\n"), &format).unwrap();
+
+    // Read as is, it's a step behind
+    let divergence = tracediff::first_divergence(&ours, &before).unwrap();
+    assert_eq!(divergence.position, 0);
+    assert_eq!(divergence.differences[0].field, "V0");
+
+    let after = tracediff::state_before_to_after(&before);
+    assert_eq!(after.len(), STEPS - 1);
+    assert_eq!(after[0].pc, 0x200);
+    assert_eq!(after[0].line, 1);
+    assert_eq!(after[0].registers[0], Some(5));
+    assert_eq!(tracediff::first_divergence(&ours, &after), None);
+}
+
+#[test]
+fn column_specs() {
+    let format: ColumnFormat = "PC, op, v0-vf, i, _, dt".parse().unwrap();
+    assert_eq!(format.fields().len(), 21);
+    assert_eq!(format.fields()[2], Field::Register(0));
+    assert_eq!(format.fields()[17], Field::Register(0xF));
+    assert_eq!(format.fields()[19], Field::Skip);
+    assert_eq!(
+        "pc,vA,skip".parse::<ColumnFormat>().unwrap().fields(),
+        [Field::Pc, Field::Register(0xA), Field::Skip]
+    );
+    assert_eq!(ColumnFormat::default(), ColumnFormat::export());
+
+    let error = |spec: &str| spec.parse::<ColumnFormat>().unwrap_err();
+    assert_eq!(error("op,v0"), "the columns must include pc");
+    assert_eq!(error("pc,v10"), "unknown column 'v10'");
+    assert_eq!(error("pc,flags"), "unknown column 'flags'");
+    assert_eq!(error("pc,,op"), "unknown column ''");
+}
+
+#[test]
+fn bad_reference_lines() {
+    let format: ColumnFormat = "pc,op,v0".parse().unwrap();
+    let error = |text: &str| tracediff::parse_text(text, &format).unwrap_err();
+    assert_eq!(
+        error("0200 6005 05\n0202 F029"),
+        "line 2: expected 3 columns, found 2"
+    );
+    assert_eq!(error("0200 6005 XY"), "line 1: 'XY' is not a hex number");
+    assert_eq!(error("0200 6005 100"), "line 1: '100' is not a hex number");
+    assert_eq!(
+        tracediff::parse(&[0xFF, 0xFE], &format).unwrap_err(),
+        "not a binary or text trace"
+    );
+}